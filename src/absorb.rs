@@ -0,0 +1,443 @@
+//! `stackbuddy absorb` moves staged changes into the branches of the stack they belong to, like
+//! git-absorb does for the commits of a single branch. Every staged hunk is blamed, and when the
+//! lines it touches were all last changed by one commit of the stack, the hunk becomes part of a
+//! `fixup!` commit for it, on top of the branch that commit belongs to. The branches above are then
+//! restacked.
+
+use crate::{
+    checkout, current_branch, git, git_dir, main_branch,
+    operation::{Operation, Then},
+    rebase::stack_steps,
+    rev_parse, whole_stack,
+};
+use eyre::{eyre, Context, Error};
+use std::{collections::HashMap, io::Write, path::Path, process::Stdio};
+
+/// A staged change to a file
+#[derive(Debug, Clone)]
+struct Hunk {
+    /// The `diff --git` header of the file, up to the first hunk
+    header: String,
+    /// The `@@` line and the lines after it
+    body: String,
+    /// The lines of the file in HEAD the hunk depends on, counted from 1: the ones it removes, or
+    /// the ones around the place it inserts lines into
+    lines: Vec<usize>,
+    path: String,
+}
+
+/// A fixup commit to create: the hunks that go into the commit `target` of `branch`
+#[derive(Debug, Clone)]
+pub struct Fixup {
+    pub branch: String,
+    pub target: String,
+    /// The subject of `target`
+    pub subject: String,
+    /// How many hunks go into the fixup, and which files they touch
+    pub hunks: usize,
+    pub files: Vec<String>,
+    patch: String,
+}
+
+/// What `absorb` did, or would do with `dry_run`
+#[derive(Debug, Clone)]
+pub struct AbsorbReport {
+    pub fixups: Vec<Fixup>,
+    /// Hunks that stayed staged because no single commit of the stack touched their lines
+    pub left: usize,
+}
+
+/// Absorbs the staged changes into the branches of the stack that contains the current branch, and
+/// restacks the branches above them. Changes that can't be absorbed stay staged.
+pub fn absorb(dry_run: bool) -> Result<AbsorbReport, Error> {
+    let branch = current_branch()?;
+    let main = main_branch()?;
+    let stack = whole_stack(&branch)?;
+
+    let hunks = staged_hunks()?;
+    if hunks.is_empty() {
+        return Err(eyre!("there are no staged changes to absorb"));
+    }
+    let owners = commit_owners(&stack, &main)?;
+
+    let mut groups: Vec<(String, Vec<Hunk>)> = Vec::new();
+    let mut left = Vec::new();
+    for hunk in hunks {
+        match blame_target(&hunk, &owners)? {
+            Some(target) => match groups.iter_mut().find(|(t, _)| t == &target) {
+                Some((_, group)) => group.push(hunk),
+                None => groups.push((target, vec![hunk])),
+            },
+            None => left.push(hunk),
+        }
+    }
+
+    let mut fixups = Vec::new();
+    for (target, hunks) in groups {
+        let mut files: Vec<String> = hunks.iter().map(|hunk| hunk.path.clone()).collect();
+        files.dedup();
+        fixups.push(Fixup {
+            branch: owners[&target].clone(),
+            subject: subject(&target)?,
+            hunks: hunks.len(),
+            files,
+            patch: patch(&hunks),
+            target,
+        });
+    }
+    // Lower branches first, so the restack starts from the bottom-most one that changed
+    fixups.sort_by_key(|fixup| std::cmp::Reverse(stack.iter().position(|b| b == &fixup.branch)));
+    let report = AbsorbReport {
+        fixups,
+        left: left.len(),
+    };
+    if dry_run || report.fixups.is_empty() {
+        return Ok(report);
+    }
+
+    // Everything is committed and restacked from a clean working tree, so changes that aren't
+    // staged would be lost
+    if !git::status(&["diff", "--quiet"]).context("git diff failed")? {
+        return Err(eyre!(
+            "there are unstaged changes, stage or stash them before absorbing"
+        ));
+    }
+
+    // The steps are computed before the branches move, since they need their old tips
+    let lowest = report
+        .fixups
+        .iter()
+        .filter_map(|fixup| stack.iter().position(|b| b == &fixup.branch))
+        .max()
+        .unwrap_or(0);
+    let mut steps = stack_steps(&stack[..=lowest], &main, &main)?;
+    // The lowest branch only gets a new commit on top, so it doesn't need to be rebased
+    steps.remove(0);
+
+    // Every commit is created before any branch moves, so a hunk that doesn't apply changes nothing
+    let mut tips: HashMap<String, (String, String)> = HashMap::new();
+    for fixup in &report.fixups {
+        let (old, parent) = match tips.get(&fixup.branch) {
+            Some((old, new)) => (old.clone(), new.clone()),
+            None => {
+                let tip = rev_parse(&fixup.branch)?;
+                (tip.clone(), tip)
+            }
+        };
+        let commit = fixup_commit(fixup, &parent)?;
+        tips.insert(fixup.branch.clone(), (old, commit));
+    }
+
+    let leftover = patch(&left);
+    let dir = git_dir()?.join("stackbuddy");
+    let leftover_path = dir.join("absorb.patch");
+    if !left.is_empty() {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        std::fs::write(&leftover_path, &leftover)
+            .with_context(|| format!("failed to write {}", leftover_path.display()))?;
+    }
+
+    for (branch, (old, new)) in &tips {
+        let success = git::status(&[
+            "update-ref",
+            "-m",
+            "stackbuddy: absorb",
+            &format!("refs/heads/{branch}"),
+            new,
+            old,
+        ])
+        .context("git update-ref failed")?;
+        if !success {
+            return Err(eyre!("failed to add the fixup commit to '{branch}'"));
+        }
+    }
+    // The current branch may have moved, and the absorbed changes are in the commits now
+    let success = git::status(&["reset", "--quiet", "--hard"]).context("git reset failed")?;
+    if !success {
+        return Err(eyre!("failed to reset the working tree"));
+    }
+
+    if !steps.is_empty() {
+        let mut op = Operation::start(branch.clone(), steps, Then::Restack)?;
+        op.run().map_err(|e| {
+            if left.is_empty() {
+                e
+            } else {
+                eyre!(
+                    "{e}. The changes that weren't absorbed are in {}",
+                    leftover_path.display()
+                )
+            }
+        })?;
+        checkout(&op.original)?;
+        op.clear()?;
+    }
+
+    if !left.is_empty() {
+        restore(&leftover, &leftover_path)?;
+    }
+    Ok(report)
+}
+
+/// Stages the changes that weren't absorbed again, and forgets the copy saved in `path`
+fn restore(patch: &str, path: &Path) -> Result<(), Error> {
+    run_git(&["apply", "--index", "--3way", "-"], None, Some(patch)).map_err(|e| {
+        eyre!(
+            "{e}. The changes that weren't absorbed are in {}",
+            path.display()
+        )
+    })?;
+    std::fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))
+}
+
+/// Returns the staged hunks. Files that are new, deleted or binary become a single hunk with no
+/// lines to blame.
+fn staged_hunks() -> Result<Vec<Hunk>, Error> {
+    let output = git::output(&[
+        "diff",
+        "--cached",
+        "--no-color",
+        "--no-ext-diff",
+        "--no-renames",
+        "--binary",
+        "--unified=1",
+        "--src-prefix=a/",
+        "--dst-prefix=b/",
+    ])
+    .context("git diff failed")?;
+    if !output.success {
+        return Err(eyre!("git diff failed: {}", output.stderr));
+    }
+
+    let mut hunks = Vec::new();
+    if output.stdout.trim().is_empty() {
+        return Ok(hunks);
+    }
+    for file in output.stdout.split("\ndiff --git ") {
+        let file = file.strip_prefix("diff --git ").unwrap_or(file);
+        let file = format!("diff --git {}\n", file.trim_end_matches('\n'));
+        let path = file
+            .lines()
+            .find_map(|line| line.strip_prefix("diff --git a/"))
+            .and_then(|paths| paths.split(" b/").next())
+            .unwrap_or_default()
+            .to_string();
+
+        // New, deleted and binary files are kept whole, with no lines to blame, so they're left
+        // staged
+        let start = file.find("\n@@").map(|start| start + 1);
+        let blameable = !file.contains("\nnew file mode")
+            && !file.contains("\ndeleted file mode")
+            && !file.contains("\nGIT binary patch");
+        let (Some(start), true) = (start, blameable) else {
+            hunks.push(Hunk {
+                header: file,
+                body: String::new(),
+                lines: Vec::new(),
+                path,
+            });
+            continue;
+        };
+
+        let header = file[..start].to_string();
+        for body in file[start..]
+            .split("\n@@")
+            .map(|body| body.trim_end_matches('\n'))
+        {
+            // Splitting took the @@ off of every hunk but the first
+            let body = if body.starts_with("@@") {
+                format!("{body}\n")
+            } else {
+                format!("@@{body}\n")
+            };
+            hunks.push(Hunk {
+                header: header.clone(),
+                lines: touched_lines(&body).unwrap_or_default(),
+                body,
+                path: path.clone(),
+            });
+        }
+    }
+    Ok(hunks)
+}
+
+/// Returns the lines of the old file `hunk` depends on, or None if its `@@` line can't be parsed
+fn touched_lines(hunk: &str) -> Option<Vec<usize>> {
+    // @@ -12,3 +12,4 @@
+    let range = hunk.strip_prefix("@@ -")?.split(' ').next()?;
+    let mut old = range.split(',').next()?.parse::<usize>().ok()?;
+
+    let mut removed = Vec::new();
+    let mut around = Vec::new();
+    let mut previous = None;
+    for line in hunk.lines().skip(1) {
+        match line.chars().next() {
+            Some('-') => {
+                removed.push(old);
+                old += 1;
+            }
+            Some('+') => around.extend(previous.into_iter().chain([old])),
+            Some('\\') => {}
+            _ => {
+                previous = Some(old);
+                old += 1;
+            }
+        }
+    }
+    let mut lines = if removed.is_empty() { around } else { removed };
+    lines.sort_unstable();
+    lines.dedup();
+    // The line after an insertion at the end of the file doesn't exist
+    lines.retain(|&line| line >= 1 && line < old);
+    Some(lines)
+}
+
+/// Maps every commit of the branches of `stack` (ordered from top to bottom) that isn't in its
+/// parent to that branch
+pub(crate) fn commit_owners(
+    stack: &[String],
+    main: &str,
+) -> Result<HashMap<String, String>, Error> {
+    let mut owners = HashMap::new();
+    for (i, branch) in stack.iter().enumerate() {
+        let parent = stack.get(i + 1).map_or(main, String::as_str);
+        let output = git::output(&["rev-list", &format!("{parent}..{branch}")])
+            .context("git rev-list failed")?;
+        if !output.success {
+            return Err(eyre!("failed to list the commits of '{branch}'"));
+        }
+        for commit in output.stdout.lines() {
+            owners.insert(commit.to_string(), branch.clone());
+        }
+    }
+    Ok(owners)
+}
+
+/// Returns the commit of the stack that last changed every line `hunk` depends on, or None if
+/// they come from several commits, or from outside the stack
+fn blame_target(hunk: &Hunk, owners: &HashMap<String, String>) -> Result<Option<String>, Error> {
+    if hunk.lines.is_empty() {
+        return Ok(None);
+    }
+    let ranges: Vec<String> = hunk
+        .lines
+        .iter()
+        .map(|line| format!("-L{line},{line}"))
+        .collect();
+    let mut args = vec!["blame", "--porcelain"];
+    args.extend(ranges.iter().map(String::as_str));
+    args.extend(["HEAD", "--", &hunk.path]);
+    let output = git::output(&args).context("git blame failed")?;
+    if !output.success {
+        return Err(eyre!("failed to blame {}: {}", hunk.path, output.stderr));
+    }
+
+    // Every blamed line starts with a header like `<commit> <old line> <new line> [<count>]`
+    let mut commits: Vec<&str> = output
+        .stdout
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split(' ');
+            let commit = words.next()?;
+            let is_header = commit.len() >= 40
+                && commit.chars().all(|c| c.is_ascii_hexdigit())
+                && words.count() >= 2;
+            is_header.then_some(commit)
+        })
+        .collect();
+    commits.sort_unstable();
+    commits.dedup();
+    match commits[..] {
+        [commit] if owners.contains_key(commit) => Ok(Some(commit.to_string())),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the patch with every hunk in `hunks`, grouped by file
+fn patch(hunks: &[Hunk]) -> String {
+    let mut patch = String::new();
+    let mut header = None;
+    for hunk in hunks {
+        if header != Some(&hunk.header) {
+            patch.push_str(&hunk.header);
+            header = Some(&hunk.header);
+        }
+        patch.push_str(&hunk.body);
+    }
+    patch
+}
+
+fn subject(commit: &str) -> Result<String, Error> {
+    let output = git::output(&["log", "-1", "--format=%s", commit]).context("git log failed")?;
+    if !output.success {
+        return Err(eyre!("failed to read the message of {commit}"));
+    }
+    Ok(output.stdout.trim().to_string())
+}
+
+/// Creates the commit of `fixup` on top of `parent`, without touching the index or any branch.
+/// Returns the new commit.
+fn fixup_commit(fixup: &Fixup, parent: &str) -> Result<String, Error> {
+    let index = git_dir()?.join("stackbuddy-absorb-index");
+    let tree = fixup_tree(fixup, parent, &index);
+    // The index may not exist if read-tree failed
+    let _ = std::fs::remove_file(&index);
+
+    let message = format!("fixup! {}", fixup.subject);
+    let commit = run_git(
+        &["commit-tree", tree?.trim(), "-p", parent, "-m", &message],
+        None,
+        None,
+    )?;
+    Ok(commit.trim().to_string())
+}
+
+/// Applies the patch of `fixup` to the tree of `parent` in the temporary `index`, and returns the
+/// resulting tree
+fn fixup_tree(fixup: &Fixup, parent: &str, index: &Path) -> Result<String, Error> {
+    run_git(&["read-tree", parent], Some(index), None)?;
+    run_git(&["apply", "--cached", "-"], Some(index), Some(&fixup.patch)).map_err(|e| {
+        eyre!(
+            "the changes to {} don't apply to '{}': {e}",
+            fixup.files.join(", "),
+            fixup.branch
+        )
+    })?;
+    run_git(&["write-tree"], Some(index), None)
+}
+
+/// Runs git with `args`, using `index` instead of the repository's index and writing `stdin` to
+/// its standard input if given. Returns what it printed to stdout.
+fn run_git(args: &[&str], index: Option<&Path>, stdin: Option<&str>) -> Result<String, Error> {
+    let mut command = crate::repo::current().command("git");
+    if let Some(index) = index {
+        command.env("GIT_INDEX_FILE", index);
+    }
+    let mut child = command
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run git")?;
+    if let Some(input) = stdin {
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("git stdin was not captured"))?
+            .write_all(input.as_bytes())
+            .context("failed to write to git stdin")?;
+    }
+    let output = child.wait_with_output().context("failed to run git")?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if !output.status.success() {
+        return Err(eyre!("git {} failed: {}", args[0], stderr.trim()));
+    }
+    Ok(stdout)
+}
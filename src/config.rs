@@ -0,0 +1,331 @@
+use crate::{
+    forge::{Backend, ForgeKind, PrLookup},
+    git,
+    note::{NoteDialect, NoteMode, NotePlacement, NoteReferences},
+    Discovery, NoteFormat, ParentStrategy,
+};
+use clap::ValueEnum;
+use eyre::{eyre, Context, Error};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+pub const DEFAULT_NOTE_OPEN: &str = "<!-- stackbuddy note -->";
+pub const DEFAULT_NOTE_CLOSE: &str = "<!-- /stackbuddy note -->";
+pub const DEFAULT_MAX_STACK_DEPTH: usize = 100;
+pub const DEFAULT_CACHE_TTL: u64 = 60;
+
+/// Per-repository settings, read from `.stackbuddy.toml` at the root of the repository and from
+/// the `stackbuddy.*` keys of the git config. Values in the git config take precedence.
+///
+/// ```toml
+/// note-format = "list"
+/// main-branch = "develop"
+/// remote = "upstream"
+/// exclude = ["wip/*", "backup/*"]
+/// ```
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// The note format used when none is given in the command line
+    pub note_format: Option<NoteFormat>,
+
+    /// The branch stacks are based on. If not set, the default branch of the remote is used. Can
+    /// also be written as `base`, e.g. for stacks on a release branch
+    #[serde(alias = "base")]
+    pub main_branch: Option<String>,
+
+    /// The remote that branches are pushed to and PRs are opened against. Defaults to `origin`
+    pub remote: Option<String>,
+
+    /// The remote that branches are pushed to, when it's a fork of `remote`. PRs are still opened
+    /// against `remote`. Defaults to `remote`
+    pub push_remote: Option<String>,
+
+    /// Glob patterns of branches that are never considered part of a stack
+    pub exclude: Vec<String>,
+
+    /// The template used by the custom note format. Relative paths are relative to the root of
+    /// the repository
+    pub note_template: Option<PathBuf>,
+
+    /// Whether notes show the titles of the PRs next to their numbers
+    pub note_titles: bool,
+
+    /// Whether notes say where the PR is in the stack, like "PR 2 of 5"
+    pub note_position: bool,
+
+    /// Whether notes link each PR to the diff between its branch and its parent, which is right
+    /// even while the base of the PR isn't
+    pub note_compare: bool,
+
+    /// Whether notes show the status of the CI checks and the review decision of each open PR
+    pub note_badges: bool,
+
+    /// How notes refer to PRs: by number, by number prefixed with the repository, or by URL
+    pub note_references: Option<NoteReferences>,
+
+    /// The flavor of markdown notes are written in. Defaults to the one of the forge
+    pub note_dialect: Option<NoteDialect>,
+
+    /// The `owner/repo` name that references are prefixed with. Defaults to the one in the URL of
+    /// the remote
+    pub note_repo: Option<String>,
+
+    /// Whether the bottom PR of the stack gets an overview of every PR in the stack, with their
+    /// titles and statuses, instead of the usual note
+    pub note_overview: bool,
+
+    /// Whether notes go in the PR body or in a comment. Defaults to the body
+    pub note_mode: Option<NoteMode>,
+
+    /// The HTML comment that marks the start of the note in a PR body
+    pub note_open: Option<String>,
+
+    /// The HTML comment that marks the end of the note in a PR body
+    pub note_close: Option<String>,
+
+    /// Where notes are inserted in PR bodies that don't have one yet. Defaults to the top
+    pub note_placement: Option<NotePlacement>,
+
+    /// The heading that notes go after, with the `after-heading` placement, e.g. `## Stack`
+    pub note_heading: Option<String>,
+
+    pub forge: Option<ForgeKind>,
+
+    pub backend: Option<Backend>,
+
+    /// The host of GitHub Enterprise Server, e.g. `github.example.com`. Defaults to the `GH_HOST`
+    /// environment variable, or github.com
+    pub host: Option<String>,
+
+    /// How the branches of a stack are found. Defaults to following the git log
+    pub stack_discovery: Option<Discovery>,
+
+    /// How the parent of a branch is guessed when none was recorded. Defaults to the git log, then
+    /// merge-bases
+    pub parent_strategy: Option<ParentStrategy>,
+
+    /// Whether PRs are kept as drafts until their parent is merged, after submit, update-notes,
+    /// land and sync. See `stackbuddy guard`
+    pub auto_draft: bool,
+
+    /// Whether a detached HEAD stands for the closest branch that contains it, instead of being an
+    /// error
+    pub guess_branch: bool,
+
+    /// Whether the pre-push hook installed by `stackbuddy hooks install --update-notes` edits the
+    /// PRs whose notes are stale. Otherwise it only lists them
+    pub push_notes: bool,
+
+    /// Which PR of a branch is used when it has several. Defaults to preferring the open one
+    pub pr_lookup: Option<PrLookup>,
+
+    /// How many branches a stack can have before it's considered a mistake, like a wrong parent.
+    /// Defaults to [`DEFAULT_MAX_STACK_DEPTH`]
+    pub max_stack_depth: Option<usize>,
+
+    /// How many seconds the PRs fetched from the forge are remembered between invocations.
+    /// Defaults to [`DEFAULT_CACHE_TTL`], and 0 turns the cache off
+    pub cache_ttl: Option<u64>,
+
+    /// Shell commands run before and after some operations, keyed by when they run, like
+    /// `pre-land` or `post-update-notes`. See [`crate::hooks::around`]
+    pub hooks: HashMap<String, String>,
+}
+
+impl Config {
+    /// The note format used when none is given in the command line: the configured one, or the
+    /// custom format if there's a template, or the default one
+    pub fn note_format(&self) -> NoteFormat {
+        self.note_format
+            .or(self.note_template.as_ref().map(|_| NoteFormat::Custom))
+            .unwrap_or_default()
+    }
+
+    pub fn remote(&self) -> &str {
+        self.remote.as_deref().unwrap_or("origin")
+    }
+
+    pub fn push_remote(&self) -> &str {
+        self.push_remote.as_deref().unwrap_or(self.remote())
+    }
+
+    pub fn max_stack_depth(&self) -> usize {
+        self.max_stack_depth.unwrap_or(DEFAULT_MAX_STACK_DEPTH)
+    }
+
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl.unwrap_or(DEFAULT_CACHE_TTL))
+    }
+
+    pub fn note_open(&self) -> &str {
+        self.note_open.as_deref().unwrap_or(DEFAULT_NOTE_OPEN)
+    }
+
+    pub fn note_close(&self) -> &str {
+        self.note_close.as_deref().unwrap_or(DEFAULT_NOTE_CLOSE)
+    }
+
+    /// Whether `branch` matches one of the `exclude` patterns
+    pub fn is_excluded(&self, branch: &str) -> bool {
+        self.exclude
+            .iter()
+            .any(|pattern| glob_match(pattern, branch))
+    }
+
+    /// Reads the configuration of the current repository. Most of the time [`load`] should be
+    /// used instead.
+    pub fn read() -> Result<Self, Error> {
+        let root = repo_root();
+        let mut config = match &root {
+            Some(root) => Self::from_file(&Path::new(root).join(".stackbuddy.toml"))?,
+            None => Config::default(),
+        };
+        config.merge_git_config()?;
+
+        if let (Some(root), Some(template)) = (root, &mut config.note_template) {
+            *template = Path::new(&root).join(&*template);
+        }
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, Error> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("invalid config in {}", path.display()))
+    }
+
+    fn merge_git_config(&mut self) -> Result<(), Error> {
+        let output = git::output(&["config", "--get-regexp", r"^stackbuddy\."])
+            .context("git config failed")?;
+
+        // git config exits with 1 when no key matches
+        for entry in output.stdout.lines() {
+            // git prints the keys in lowercase
+            let (key, value) = entry.split_once(' ').unwrap_or((entry, ""));
+            let value = value.to_string();
+            match key.trim_start_matches("stackbuddy.") {
+                "noteformat" => self.note_format = Some(parse_value(key, &value)?),
+                "mainbranch" | "base" => self.main_branch = Some(value),
+                "remote" => self.remote = Some(value),
+                "pushremote" => self.push_remote = Some(value),
+                "exclude" => self.exclude.push(value),
+                "notetemplate" => self.note_template = Some(PathBuf::from(value)),
+                "notetitles" => self.note_titles = parse_bool(key, &value)?,
+                "noteposition" => self.note_position = parse_bool(key, &value)?,
+                "notecompare" => self.note_compare = parse_bool(key, &value)?,
+                "notebadges" => self.note_badges = parse_bool(key, &value)?,
+                "notereferences" => self.note_references = Some(parse_value(key, &value)?),
+                "noterepo" => self.note_repo = Some(value),
+                "notedialect" => self.note_dialect = Some(parse_value(key, &value)?),
+                "noteoverview" => self.note_overview = parse_bool(key, &value)?,
+                "notemode" => self.note_mode = Some(parse_value(key, &value)?),
+                "noteopen" => self.note_open = Some(value),
+                "noteclose" => self.note_close = Some(value),
+                "noteplacement" => self.note_placement = Some(parse_value(key, &value)?),
+                "noteheading" => self.note_heading = Some(value),
+                "forge" => self.forge = Some(parse_value(key, &value)?),
+                "backend" => self.backend = Some(parse_value(key, &value)?),
+                "host" => self.host = Some(value),
+                "stackdiscovery" => self.stack_discovery = Some(parse_value(key, &value)?),
+                "parentstrategy" => self.parent_strategy = Some(parse_value(key, &value)?),
+                "autodraft" => self.auto_draft = parse_bool(key, &value)?,
+                "guessbranch" => self.guess_branch = parse_bool(key, &value)?,
+                "pushnotes" => self.push_notes = parse_bool(key, &value)?,
+                "prlookup" => self.pr_lookup = Some(parse_value(key, &value)?),
+                "maxstackdepth" => self.max_stack_depth = Some(parse_number(key, &value)?),
+                "cachettl" => self.cache_ttl = Some(parse_number(key, &value)? as u64),
+                // e.g. stackbuddy.hook.pre-land
+                name if name.starts_with("hook.") => {
+                    self.hooks.insert(name["hook.".len()..].to_string(), value);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_value<T: ValueEnum>(key: &str, value: &str) -> Result<T, Error> {
+    T::from_str(value, true).map_err(|e| eyre!("invalid value for {key}: {e}"))
+}
+
+fn parse_number(key: &str, value: &str) -> Result<usize, Error> {
+    value
+        .parse()
+        .map_err(|_| eyre!("invalid value for {key}: expected a number, got '{value}'"))
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, Error> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" | "" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(eyre!(
+            "invalid value for {key}: expected a boolean, got '{value}'"
+        )),
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Sets the configuration returned by [`load`], e.g. to apply overrides from the command line.
+/// Does nothing if the configuration was already loaded.
+pub fn init(config: Config) -> &'static Config {
+    CONFIG.get_or_init(|| config)
+}
+
+/// Returns the configuration of the current repository. It's only read the first time this is
+/// called.
+pub fn load() -> Result<&'static Config, Error> {
+    if let Some(config) = CONFIG.get() {
+        return Ok(config);
+    }
+    let config = Config::read()?;
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+fn repo_root() -> Option<String> {
+    let output = git::output(&["rev-parse", "--show-toplevel"]).ok()?;
+    let root = output.stdout.trim().to_string();
+    Some(root).filter(|root| output.success && !root.is_empty())
+}
+
+/// Matches `text` against a glob pattern, where `*` matches any sequence of characters and `?`
+/// matches a single one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last star match one more character
+                Some((star, star_t)) => {
+                    backtrack = Some((star, star_t + 1));
+                    p = star + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,113 @@
+//! Line-based unified diffs, used to preview what `update-notes --dry-run` would change.
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Returns a unified diff from `old` to `new`, with `context` unchanged lines around every change
+/// and ANSI colors if `color` is set. The diff is empty if the texts have the same lines.
+pub fn unified(old: &str, new: &str, context: usize, color: bool) -> String {
+    let ops = diff_lines(old, new);
+
+    // Positions of the changes, grouped into hunks that are close enough to share context
+    let changes: Vec<usize> = (0..ops.len())
+        .filter(|&i| !matches!(ops[i], Op::Equal(_)))
+        .collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let paint = |code: &str, line: String| {
+        if color {
+            format!("{code}{line}{RESET}")
+        } else {
+            line
+        }
+    };
+
+    let mut out = Vec::new();
+    for (start, end) in hunks {
+        // Line numbers where the hunk starts, counted from 1
+        let old_start = 1 + ops[..start]
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert(_)))
+            .count();
+        let new_start = 1 + ops[..start]
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete(_)))
+            .count();
+        let old_len = ops[start..end]
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert(_)))
+            .count();
+        let new_len = ops[start..end]
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete(_)))
+            .count();
+
+        out.push(paint(
+            CYAN,
+            format!("@@ -{old_start},{old_len} +{new_start},{new_len} @@"),
+        ));
+        for op in &ops[start..end] {
+            out.push(match op {
+                Op::Equal(line) => format!(" {line}"),
+                Op::Delete(line) => paint(RED, format!("-{line}")),
+                Op::Insert(line) => paint(GREEN, format!("+{line}")),
+            });
+        }
+    }
+    out.join("\n")
+}
+
+/// Finds the shortest list of line deletions and insertions that turns `old` into `new`, through
+/// their longest common subsequence
+fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<Op<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            ops.push(Op::Equal(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(Op::Delete(old[i]));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|line| Op::Delete(line)));
+    ops.extend(new[j..].iter().map(|line| Op::Insert(line)));
+    ops
+}
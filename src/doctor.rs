@@ -0,0 +1,285 @@
+//! `stackbuddy doctor`: checks that the environment has everything stackbuddy needs, and says how
+//! to fix what's missing.
+
+use crate::{
+    config,
+    forge::{run_command, Backend, ForgeKind},
+    git, local_branches, main_branch, tracked_parent,
+};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// The outcome of a single check
+#[derive(Serialize, Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// What was found, or what went wrong
+    pub detail: String,
+    /// How to fix the problem, if the check failed
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            ok: true,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn failed(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            ok: false,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Runs every check. `kind` and `backend` are the ones given in the command line, if any.
+///
+/// Checks that depend on a failed one are skipped, since they would only fail for the same reason.
+pub fn diagnose(kind: Option<ForgeKind>, backend: Option<Backend>) -> Vec<Check> {
+    let mut checks = vec![check_git()];
+    if !checks[0].ok {
+        return checks;
+    }
+
+    let work_tree = check_work_tree();
+    let in_work_tree = work_tree.ok;
+    checks.push(work_tree);
+    if !in_work_tree {
+        return checks;
+    }
+
+    let remote = check_remote();
+    let has_remote = remote.ok;
+    checks.push(remote);
+    if has_remote || kind.is_some() {
+        checks.extend(check_forge(kind, backend));
+    }
+    checks.push(check_main_branch());
+    checks.push(check_parents());
+    checks
+}
+
+fn check_git() -> Check {
+    match git::output(&["--version"]) {
+        Ok(output) if output.success => Check::ok("git", output.stdout.trim()),
+        Ok(output) => Check::failed(
+            "git",
+            output.stderr.trim(),
+            "check that git works by running `git --version`",
+        ),
+        Err(e) => Check::failed(
+            "git",
+            format!("{e:#}"),
+            "install git from https://git-scm.com and make sure it's in your PATH",
+        ),
+    }
+}
+
+fn check_work_tree() -> Check {
+    match git::output(&["rev-parse", "--is-inside-work-tree"]) {
+        Ok(output) if output.stdout.trim() == "true" => {
+            Check::ok("repository", "inside a git work tree")
+        }
+        _ => Check::failed(
+            "repository",
+            "not inside a git work tree",
+            "run stackbuddy from inside the repository of your stack",
+        ),
+    }
+}
+
+fn check_remote() -> Check {
+    let remote = match config::load() {
+        Ok(config) => config.remote().to_string(),
+        Err(e) => {
+            return Check::failed(
+                "remote",
+                format!("{e:#}"),
+                "fix .stackbuddy.toml or the stackbuddy section of the git config",
+            )
+        }
+    };
+    match git::output(&["remote", "get-url", &remote]) {
+        Ok(output) if output.success => {
+            Check::ok("remote", format!("{remote} is {}", output.stdout.trim()))
+        }
+        _ => Check::failed(
+            "remote",
+            format!("there is no remote named '{remote}'"),
+            format!(
+                "add it with `git remote add {remote} <url>`, or set `remote` in the config to \
+                 the one PRs are opened against"
+            ),
+        ),
+    }
+}
+
+/// Checks that the forge CLI is installed and logged in, or that the API has a token
+fn check_forge(kind: Option<ForgeKind>, backend: Option<Backend>) -> Vec<Check> {
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(_) => return Vec::new(),
+    };
+    let kind = match kind.or(config.forge) {
+        Some(kind) => kind,
+        None => match ForgeKind::detect() {
+            Ok(kind) => kind,
+            Err(e) => {
+                return vec![Check::failed(
+                    "forge",
+                    format!("{e:#}"),
+                    "pass --forge, or set `forge` in the config",
+                )]
+            }
+        },
+    };
+    let backend = backend.or(config.backend).unwrap_or_default();
+
+    if kind == ForgeKind::Gitea {
+        return vec![match std::env::var("GITEA_TOKEN") {
+            Ok(token) if !token.is_empty() => {
+                Check::ok("forge", "Gitea API, with a token in GITEA_TOKEN")
+            }
+            _ => Check::failed(
+                "forge",
+                "GITEA_TOKEN is not set",
+                "set GITEA_TOKEN to a token that can read and edit pull requests",
+            ),
+        }];
+    }
+    if backend == Backend::Api {
+        return vec![match (kind, std::env::var("GITHUB_TOKEN")) {
+            (ForgeKind::Gitlab, _) => Check::failed(
+                "forge",
+                "the api backend is only available for GitHub",
+                "use the cli backend with GitLab",
+            ),
+            (ForgeKind::Github, Ok(token)) if !token.is_empty() => {
+                Check::ok("forge", "GitHub API, with a token in GITHUB_TOKEN")
+            }
+            (ForgeKind::Github, _) => Check::failed(
+                "forge",
+                "GITHUB_TOKEN is not set",
+                "set GITHUB_TOKEN to a token that can read and edit pull requests",
+            ),
+            (ForgeKind::Gitea, _) => unreachable!("Gitea is checked above"),
+        }];
+    }
+
+    let (program, url) = match kind {
+        ForgeKind::Github => ("gh", "https://cli.github.com"),
+        ForgeKind::Gitlab => ("glab", "https://gitlab.com/gitlab-org/cli"),
+        ForgeKind::Gitea => unreachable!("Gitea is checked above"),
+    };
+    let installed = match run_command(program, &["--version"], None) {
+        Ok(version) => {
+            let version = version.lines().next().unwrap_or_default().to_string();
+            Check::ok("forge cli", version)
+        }
+        Err(_) => {
+            return vec![Check::failed(
+                "forge cli",
+                format!("{program} is not installed"),
+                format!("install {program} from {url}, or use --backend api"),
+            )]
+        }
+    };
+    let auth = match run_command(program, &["auth", "status"], None) {
+        Ok(_) => Check::ok("forge auth", format!("{program} is logged in")),
+        Err(e) => Check::failed(
+            "forge auth",
+            format!("{e:#}"),
+            format!("log in with `{program} auth login`"),
+        ),
+    };
+    vec![installed, auth]
+}
+
+fn check_main_branch() -> Check {
+    let main = match main_branch() {
+        Ok(main) => main,
+        Err(e) => {
+            return Check::failed(
+                "main branch",
+                format!("{e:#}"),
+                "set `main-branch` in the config, or pass --base",
+            )
+        }
+    };
+    // A clone may only have the remote-tracking branch, e.g. in CI
+    let remote = config::load().map_or("origin", |config| config.remote());
+    let exists = [main.clone(), format!("{remote}/{main}")]
+        .iter()
+        .any(|rev| {
+            git::output(&["rev-parse", "--verify", "--quiet", rev])
+                .is_ok_and(|output| output.success)
+        });
+    if exists {
+        Check::ok("main branch", main)
+    } else {
+        Check::failed(
+            "main branch",
+            format!("the main branch '{main}' doesn't exist"),
+            "fetch it with `git fetch`, or fix `main-branch` in the config",
+        )
+    }
+}
+
+/// Checks that every parent recorded by `stackbuddy track` still exists, and that following them
+/// never goes around in circles
+fn check_parents() -> Check {
+    let branches = match local_branches() {
+        Ok(branches) => branches,
+        Err(e) => {
+            return Check::failed(
+                "stack metadata",
+                format!("{e:#}"),
+                "check that `git for-each-ref` works",
+            )
+        }
+    };
+    let existing: HashSet<&str> = branches.iter().map(String::as_str).collect();
+
+    let mut problems = Vec::new();
+    let mut fixes = Vec::new();
+    for branch in &branches {
+        let Ok(Some(parent)) = tracked_parent(branch) else {
+            continue;
+        };
+        if !existing.contains(parent.as_str()) {
+            problems.push(format!(
+                "the parent of '{branch}', '{parent}', doesn't exist"
+            ));
+            fixes.push(format!("stackbuddy track {branch} --parent <parent>"));
+            continue;
+        }
+
+        let mut seen = HashSet::from([branch.clone()]);
+        let mut current = parent;
+        while let Ok(Some(next)) = tracked_parent(&current) {
+            if !seen.insert(current.clone()) {
+                problems.push(format!("the parents of '{branch}' go around in a cycle"));
+                fixes.push(format!("stackbuddy track {branch} --parent <parent>"));
+                break;
+            }
+            current = next;
+        }
+    }
+
+    if problems.is_empty() {
+        return Check::ok("stack metadata", "every tracked parent exists");
+    }
+    Check::failed(
+        "stack metadata",
+        problems.join("; "),
+        format!("record the right parents with {}", fixes.join(", ")),
+    )
+}
@@ -0,0 +1,706 @@
+use crate::{config, git};
+use clap::ValueEnum;
+use eyre::{eyre, Context, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+mod cached;
+mod gitea;
+mod github;
+mod github_api;
+mod gitlab;
+mod graphql;
+mod journaled;
+mod offline;
+mod retry;
+
+pub use cached::{CacheMode, Cached};
+pub use gitea::Gitea;
+pub use github::GitHub;
+pub use github_api::GitHubApi;
+pub use gitlab::GitLab;
+pub use journaled::Journaled;
+pub use offline::Offline;
+
+/// A Forge is the service that hosts the repository and its pull requests (or merge requests, as
+/// GitLab calls them)
+pub trait Forge: Send + Sync {
+    /// Returns every PR whose head is `branch`, in any state, from the most recent to the oldest
+    fn prs_for_branch(&self, branch: &str) -> Result<Vec<PrRef>, Error>;
+
+    /// Returns the PR of `branch`, if there is one. When there are several, the one picked
+    /// depends on `pr-lookup` in the config: by default, the open one if there is one, or else the
+    /// most recent.
+    fn pr_for_branch(&self, branch: &str) -> Result<Option<PrRef>, Error> {
+        let lookup = config::load()?.pr_lookup.unwrap_or_default();
+        Ok(lookup.pick(self.prs_for_branch(branch)?))
+    }
+
+    /// Returns the URL of the PR of `branch`, if there is one
+    fn pr_url(&self, branch: &str) -> Result<Option<String>, Error> {
+        Ok(self.pr_for_branch(branch)?.map(|pr| pr.url))
+    }
+
+    /// Returns whether the PR of `branch` is open, merged or closed, if there is one
+    fn pr_state(&self, branch: &str) -> Result<Option<PrState>, Error> {
+        Ok(self.pr_for_branch(branch)?.map(|pr| pr.state))
+    }
+
+    /// Returns the head branch of the PR with the given number
+    fn pr_head(&self, number: &str) -> Result<String, Error>;
+
+    fn pr_body(&self, branch: &str) -> Result<String, Error>;
+
+    fn set_pr_body(&self, branch: &str, body: &str) -> Result<(), Error>;
+
+    /// Returns the bodies of the open PRs of `branches` at once, keyed by branch, or None if the
+    /// forge can only read them one by one with [`Forge::pr_body`]. Branches without an open PR
+    /// may be left out.
+    fn pr_bodies(&self, _branches: &[String]) -> Result<Option<HashMap<String, String>>, Error> {
+        Ok(None)
+    }
+
+    /// Sets the bodies of the PRs of several branches, given as (branch, body) pairs, in as few
+    /// requests as the forge allows. Returns the result of each, in the same order.
+    fn set_pr_bodies(&self, bodies: &[(String, String)]) -> Vec<Result<(), Error>> {
+        bodies
+            .iter()
+            .map(|(branch, body)| self.set_pr_body(branch, body))
+            .collect()
+    }
+
+    /// Opens a PR for `branch` targeting `base`, using the commit messages to fill in the title
+    /// and body. Returns the number of the new PR.
+    fn create_pr(&self, branch: &str, base: &str, draft: bool) -> Result<String, Error>;
+
+    /// Changes the branch the PR of `branch` will be merged into
+    fn set_pr_base(&self, branch: &str, base: &str) -> Result<(), Error>;
+
+    /// Merges the PR of `branch` into its base. The branch itself is not deleted.
+    fn merge_pr(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error>;
+
+    /// Makes the forge merge the PR of `branch` into its base on its own, as soon as its checks
+    /// pass and it's approved
+    fn enable_auto_merge(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error>;
+
+    /// Adds the PR of `branch` to the merge queue of its base branch, which merges it once the
+    /// checks pass on top of the PRs ahead of it
+    fn enqueue_pr(&self, _branch: &str) -> Result<(), Error> {
+        Err(eyre!("merge queues are only supported on GitHub"))
+    }
+
+    /// Closes the PR of `branch` without merging it
+    fn close_pr(&self, branch: &str) -> Result<(), Error>;
+
+    /// Marks the PR of `branch` as a draft, or as ready for review
+    fn set_pr_draft(&self, branch: &str, draft: bool) -> Result<(), Error>;
+
+    /// Adds `label` to the PR of `branch`, creating the label if the repository doesn't have it
+    fn add_pr_label(&self, branch: &str, label: &str) -> Result<(), Error>;
+
+    /// Removes `label` from the PR of `branch`
+    fn remove_pr_label(&self, branch: &str, label: &str) -> Result<(), Error>;
+
+    /// Returns who was asked to review the PR of `branch`, or already did, and who it's assigned to
+    fn pr_participants(&self, branch: &str) -> Result<Participants, Error>;
+
+    /// Requests reviews from `users` on the PR of `branch`, on top of the ones already requested
+    fn add_pr_reviewers(&self, branch: &str, users: &[String]) -> Result<(), Error>;
+
+    /// Assigns the PR of `branch` to `users`, on top of the ones already assigned
+    fn add_pr_assignees(&self, branch: &str, users: &[String]) -> Result<(), Error>;
+
+    /// Renames the branch `old` on the remote to `new`, which must already be the name of the local
+    /// branch. Forges that can rename branches keep the PR of the branch, and retarget the PRs
+    /// based on it. Elsewhere, see [`recreate_branch`].
+    fn rename_branch(&self, old: &str, new: &str) -> Result<(), Error> {
+        recreate_branch(self, old, new)
+    }
+
+    /// Opens the PR of `branch` in the browser
+    fn open_pr(&self, branch: &str) -> Result<(), Error>;
+
+    /// Returns the comments on the PR of `branch`, oldest first
+    fn pr_comments(&self, branch: &str) -> Result<Vec<Comment>, Error>;
+
+    /// Posts a new comment on the PR of `branch`
+    fn create_pr_comment(&self, branch: &str, body: &str) -> Result<(), Error>;
+
+    /// Replaces the body of the comment `id` on the PR of `branch`
+    fn edit_pr_comment(&self, branch: &str, id: &str, body: &str) -> Result<(), Error>;
+
+    /// Returns the status of every open PR, keyed by the name of its head branch. Implementations
+    /// fetch everything in a single request.
+    fn open_prs(&self) -> Result<HashMap<String, PrStatus>, Error>;
+
+    /// Forgets anything the forge remembers about PRs, so the next queries see changes made
+    /// elsewhere. See [`Cached`].
+    fn refresh(&self) {}
+
+    /// How old the answers are when they may be out of date, because they come from the cache
+    /// with `--offline`
+    fn staleness(&self) -> Option<Duration> {
+        None
+    }
+
+    /// How a PR is referenced in markdown, e.g. `#12` on GitHub or `!12` on GitLab
+    fn pr_reference(&self, number: &str) -> String {
+        format!("#{number}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrState {
+    Open,
+    Merged,
+    /// Closed without being merged
+    Closed,
+}
+
+impl PrState {
+    /// Parses the state names used by GitHub (`OPEN`, `MERGED`, `CLOSED`) and GitLab (`opened`,
+    /// `merged`, `closed`)
+    pub fn parse(state: &str) -> Option<Self> {
+        match state.to_lowercase().as_str() {
+            "open" | "opened" => Some(PrState::Open),
+            "merged" => Some(PrState::Merged),
+            "closed" => Some(PrState::Closed),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PrState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            PrState::Open => "open",
+            PrState::Merged => "merged",
+            PrState::Closed => "closed",
+        };
+        write!(f, "{state}")
+    }
+}
+
+/// A PR of a branch, as returned by [`Forge::pr_for_branch`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PrRef {
+    pub number: String,
+    pub state: PrState,
+    pub url: String,
+}
+
+/// The people involved in a PR, by username
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Participants {
+    /// Who was asked for a review, or already reviewed it
+    pub reviewers: Vec<String>,
+    pub assignees: Vec<String>,
+}
+
+/// A comment on a PR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub id: String,
+    pub body: String,
+}
+
+/// Which PR of a branch is used when it has more than one, e.g. a closed one and an open one
+#[derive(ValueEnum, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrLookup {
+    /// The open PR, or the most recent one if none is open
+    #[default]
+    PreferOpen,
+
+    /// Only the open PR. Branches whose PRs were all merged or closed have no PR
+    Open,
+
+    /// The most recent PR, whatever its state
+    Latest,
+}
+
+impl PrLookup {
+    /// Picks a PR out of `prs`, which go from the most recent to the oldest
+    pub fn pick(self, prs: Vec<PrRef>) -> Option<PrRef> {
+        let open = prs.iter().position(|pr| pr.state == PrState::Open);
+        match (self, open) {
+            (PrLookup::PreferOpen | PrLookup::Open, Some(i)) => prs.into_iter().nth(i),
+            (PrLookup::Open, None) => None,
+            (PrLookup::PreferOpen | PrLookup::Latest, _) => prs.into_iter().next(),
+        }
+    }
+}
+
+/// The state of a PR, as shown by `stackbuddy status`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PrStatus {
+    pub number: String,
+    pub url: String,
+    pub title: String,
+    /// The branch the PR will be merged into
+    pub base: String,
+    pub draft: bool,
+    /// Whether the PR can be merged, e.g. `MERGEABLE`, `CONFLICTING` or `UNKNOWN`
+    pub mergeable: String,
+    pub checks: Checks,
+    /// The review decision, e.g. `APPROVED`, `CHANGES_REQUESTED` or `REVIEW_REQUIRED`
+    pub review: String,
+    pub labels: Vec<String>,
+}
+
+/// How many CI checks of a PR passed, failed or are still running
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct Checks {
+    pub passed: usize,
+    pub failed: usize,
+    pub pending: usize,
+}
+
+impl Checks {
+    /// Counts a check with the given conclusion or state, using GitHub's names for them
+    pub fn add(&mut self, state: &str) {
+        match state {
+            "SUCCESS" | "NEUTRAL" | "SKIPPED" => self.passed += 1,
+            "FAILURE" | "ERROR" | "CANCELLED" | "TIMED_OUT" | "ACTION_REQUIRED"
+            | "STARTUP_FAILURE" => self.failed += 1,
+            _ => self.pending += 1,
+        }
+    }
+
+    /// Counts the checks in a GitHub `statusCheckRollup`, which mixes check runs (that have a
+    /// `conclusion` once completed) and commit statuses (that have a `state`)
+    fn from_rollup(rollup: &[Value]) -> Self {
+        let mut checks = Checks::default();
+        for check in rollup {
+            let state = check["conclusion"]
+                .as_str()
+                .filter(|c| !c.is_empty())
+                .or_else(|| check["state"].as_str())
+                .unwrap_or("PENDING");
+            checks.add(state);
+        }
+        checks
+    }
+
+    /// ❌ if any check failed, 🟡 if any is still running and ✅ if they all passed, or None if the
+    /// PR has no checks
+    pub fn icon(&self) -> Option<&'static str> {
+        if self.failed > 0 {
+            Some("❌")
+        } else if self.pending > 0 {
+            Some("🟡")
+        } else if self.passed > 0 {
+            Some("✅")
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Checks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.passed + self.failed + self.pending;
+        if total == 0 {
+            return write!(f, "-");
+        }
+        write!(f, "{}/{total} passed", self.passed)?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        if self.pending > 0 {
+            write!(f, ", {} pending", self.pending)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForgeKind {
+    /// GitHub, through the gh CLI
+    Github,
+
+    /// GitLab, through the glab CLI
+    Gitlab,
+
+    /// Gitea or Forgejo, through their API, with a token in the GITEA_TOKEN environment variable
+    Gitea,
+}
+
+impl ForgeKind {
+    /// Guesses the forge from the URL of the remote, defaulting to GitHub
+    pub fn detect() -> Result<Self, Error> {
+        let url = remote_url()?;
+        if url.contains("gitlab") {
+            Ok(ForgeKind::Gitlab)
+        } else if ["gitea", "forgejo", "codeberg.org"]
+            .iter()
+            .any(|name| url.contains(name))
+        {
+            Ok(ForgeKind::Gitea)
+        } else {
+            Ok(ForgeKind::Github)
+        }
+    }
+
+    pub fn forge(self, backend: Backend) -> Result<Box<dyn Forge>, Error> {
+        match (self, backend) {
+            (ForgeKind::Github, Backend::Cli) => Ok(Box::new(GitHub)),
+            (ForgeKind::Github, Backend::Api) => Ok(Box::new(GitHubApi::from_env()?)),
+            (ForgeKind::Gitlab, Backend::Cli) => Ok(Box::new(GitLab)),
+            (ForgeKind::Gitlab, Backend::Api) => {
+                Err(eyre!("the api backend is only available for GitHub"))
+            }
+            // There's no CLI to shell out to, so the API is always used
+            (ForgeKind::Gitea, _) => Ok(Box::new(Gitea::from_env()?)),
+        }
+    }
+}
+
+/// How the commits of a PR are brought into its base branch when merging
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Squashes all commits of the PR into a single one
+    #[default]
+    Squash,
+
+    /// Rebases the commits of the PR onto the base branch
+    Rebase,
+
+    /// Creates a merge commit
+    Merge,
+}
+
+impl MergeStrategy {
+    /// The name GitHub and the forge CLIs use for the strategy
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MergeStrategy::Squash => "squash",
+            MergeStrategy::Rebase => "rebase",
+            MergeStrategy::Merge => "merge",
+        }
+    }
+}
+
+/// How stackbuddy talks to the forge
+#[derive(ValueEnum, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// Shells out to the forge's CLI (gh or glab)
+    #[default]
+    Cli,
+
+    /// Calls the forge's REST API directly, authenticating with the GITHUB_TOKEN environment
+    /// variable. Useful in CI and containers without the CLI installed
+    Api,
+}
+
+/// Returns the forge of the given kind, or the one from the config or detected from the remote if
+/// `kind` is None. Likewise, the backend falls back to the one in the config. The answers of the
+/// forge are cached, see [`Cached`], and kept between invocations depending on `cache`.
+pub fn forge(
+    kind: Option<ForgeKind>,
+    backend: Option<Backend>,
+    cache: CacheMode,
+) -> Result<Box<dyn Forge>, Error> {
+    let config = config::load()?;
+    let kind = match kind.or(config.forge) {
+        Some(kind) => kind,
+        None => ForgeKind::detect()?,
+    };
+    let forge = kind.forge(backend.or(config.backend).unwrap_or_default())?;
+    Ok(Box::new(Cached::persistent(forge, cache)?))
+}
+
+/// Runs `program` with `args`, writing `stdin` to its standard input if given, and waits for it
+/// to exit. Returns what it printed to stdout, or an error with what it printed to stderr if it
+/// failed. Errors are named after the program and its first two arguments, e.g. `gh pr view`.
+/// Failures caused by the network or the rate limit are retried, see [`retry::command`], but
+/// commands that create something are only retried when they surely weren't applied.
+pub(crate) fn run_command(
+    program: &str,
+    args: &[&str],
+    stdin: Option<&str>,
+) -> Result<String, Error> {
+    let name = [program]
+        .into_iter()
+        .chain(args.iter().copied().take(2))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    retry::command(&name, repeatable(args, stdin), || {
+        run_once(&name, program, args, stdin)
+    })
+}
+
+/// Whether the CLI command with `args` can run twice without doing anything twice. Creating PRs,
+/// comments and merges can't, and neither can POSTs to the API and GraphQL mutations
+fn repeatable(args: &[&str], stdin: Option<&str>) -> bool {
+    match args {
+        [_, "create" | "comment" | "note" | "merge", ..] => false,
+        ["api", "graphql", ..] => !stdin.is_some_and(|input| input.contains("\"mutation")),
+        ["api", rest @ ..] => !rest
+            .windows(2)
+            .any(|pair| matches!(pair, ["--method" | "-X", "POST"])),
+        _ => true,
+    }
+}
+
+/// Runs the command of [`run_command`] once
+fn run_once(
+    name: &str,
+    program: &str,
+    args: &[&str],
+    stdin: Option<&str>,
+) -> Result<String, Error> {
+    let start = Instant::now();
+    let mut command = crate::repo::current().command(program);
+    // gh guesses the repository from the remotes, which may not be the configured one
+    let config = config::load()?;
+    if program == "gh" && config.host.is_some() {
+        command.env("GH_HOST", github_host()?);
+    }
+    if program == "gh" && config.remote.is_some() {
+        command.env("GH_REPO", format!("{}/{}", github_host()?, repo_name()?));
+    }
+    let mut child = command
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("{name} failed"))?;
+
+    if let Some(input) = stdin {
+        // Dropping the handle closes stdin, so the child knows the input is over
+        let mut handle = child
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("{name} stdin was not captured"))?;
+        handle
+            .write_all(input.as_bytes())
+            .with_context(|| format!("failed to write to {name} stdin"))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("{name} failed"))?;
+    let stdout = String::from_utf8(output.stdout)
+        .with_context(|| format!("{name} stdout was not valid utf-8"))?;
+    let stderr = String::from_utf8(output.stderr)
+        .with_context(|| format!("{name} stderr was not valid utf-8"))?;
+    git::log_command(
+        program,
+        args,
+        output.status.success(),
+        start.elapsed(),
+        &stdout,
+        &stderr,
+    );
+
+    if !output.status.success() {
+        return Err(eyre!("{name} failed: {}", stderr));
+    }
+    Ok(stdout)
+}
+
+/// Returns the names of the labels in `labels`, which is a list of either names or objects with a
+/// `name`, depending on the forge
+fn label_names(labels: &Value) -> Vec<String> {
+    labels
+        .as_array()
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .filter_map(|label| label.as_str().or_else(|| label["name"].as_str()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the `login` (or `username`, on GitLab) of every user in `users`, a list of objects like
+/// `{ "login": "octocat" }`
+fn logins(users: &Value) -> Vec<String> {
+    users
+        .as_array()
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .filter_map(|user| user["login"].as_str().or_else(|| user["username"].as_str()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Escapes `segment` to be used as a single segment of the path of a URL, like a label name
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Opens `url` with the default browser of the system
+pub fn open_url(url: &str) -> Result<(), Error> {
+    if cfg!(target_os = "macos") {
+        run_command("open", &[url], None)?;
+    } else if cfg!(windows) {
+        run_command("cmd", &["/C", "start", "", url], None)?;
+    } else {
+        run_command("xdg-open", &[url], None)?;
+    }
+    Ok(())
+}
+
+fn remote_url() -> Result<String, Error> {
+    url_of(config::load()?.remote())
+}
+
+fn url_of(remote: &str) -> Result<String, Error> {
+    let output =
+        git::output(&["remote", "get-url", remote]).context("git remote get-url failed")?;
+    Ok(output.stdout.trim().to_string())
+}
+
+/// Returns the host of GitHub: `host` in the config, or the `GH_HOST` environment variable that gh
+/// also reads, or github.com
+pub fn github_host() -> Result<String, Error> {
+    if let Some(host) = &config::load()?.host {
+        return Ok(host.clone());
+    }
+    Ok(std::env::var("GH_HOST")
+        .ok()
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "github.com".to_string()))
+}
+
+/// Renames the branch `old` on the remote to `new` by pushing `new` and deleting `old`, for forges
+/// that can't rename branches. A PR's head can't change, so an open PR of `old` is replaced by a
+/// new one for `new` with the same body, and the PRs based on `old` are retargeted to `new` before
+/// it's deleted, so they aren't closed with it.
+pub fn recreate_branch<F: Forge + ?Sized>(forge: &F, old: &str, new: &str) -> Result<(), Error> {
+    crate::push::push_branch(new)?;
+
+    let prs = forge.open_prs()?;
+    if let Some(pr) = prs.get(old) {
+        let body = forge.pr_body(old)?;
+        let number = forge.create_pr(new, &pr.base, pr.draft)?;
+        forge.set_pr_body(new, &body)?;
+        forge.close_pr(old)?;
+        println!(
+            "Replaced {} with {}",
+            forge.pr_reference(&pr.number),
+            forge.pr_reference(&number)
+        );
+    }
+    for (branch, pr) in &prs {
+        if pr.base == old {
+            forge.set_pr_base(branch, new)?;
+        }
+    }
+    crate::push::delete_remote_branch(old)
+}
+
+/// Returns the owner of the fork that branches are pushed to, if `push-remote` is a different
+/// remote than the one PRs are opened against
+pub fn fork_owner() -> Result<Option<String>, Error> {
+    let config = config::load()?;
+    if config.push_remote() == config.remote() {
+        return Ok(None);
+    }
+    let url = url_of(config.push_remote())?;
+    let (owner, _) =
+        repo_slug(&url).ok_or_else(|| eyre!("could not find owner/repo in '{url}'"))?;
+    Ok(Some(owner))
+}
+
+/// How PRs refer to `branch`: by its name, or as `owner:branch` when it's on a fork
+pub fn head_ref(branch: &str) -> Result<String, Error> {
+    Ok(match fork_owner()? {
+        Some(owner) => format!("{owner}:{branch}"),
+        None => branch.to_string(),
+    })
+}
+
+/// Returns the web page that shows the diff between the branches `base` and `head`, like
+/// `https://github.com/owner/repo/compare/base...head`, from the URL of the remote
+pub fn compare_url(base: &str, head: &str) -> Result<String, Error> {
+    let url = remote_url()?;
+    let host = repo_host(&url).ok_or_else(|| eyre!("could not find the host in '{url}'"))?;
+    let head = head_ref(head)?;
+    Ok(match ForgeKind::detect()? {
+        ForgeKind::Gitlab => format!("https://{host}/{}/-/compare/{base}...{head}", repo_name()?),
+        ForgeKind::Github | ForgeKind::Gitea => {
+            format!("https://{host}/{}/compare/{base}...{head}", repo_name()?)
+        }
+    })
+}
+
+/// Extracts the host from a remote URL, like `github.com` from `git@github.com:owner/repo.git`,
+/// `ssh://git@github.com:22/owner/repo` or `https://github.com/owner/repo`
+fn repo_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split_once('@').map_or(rest, |(_, rest)| rest);
+    let host = rest.split([':', '/']).next()?;
+    Some(host.to_string()).filter(|host| !host.is_empty())
+}
+
+/// Returns the `owner/repo` name of the repository, from the URL of the remote
+pub fn repo_name() -> Result<String, Error> {
+    let url = remote_url()?;
+    let (owner, repo) =
+        repo_slug(&url).ok_or_else(|| eyre!("could not find owner/repo in '{url}'"))?;
+    Ok(format!("{owner}/{repo}"))
+}
+
+/// Extracts the owner and repository name from a remote URL, like
+/// `git@github.com:owner/repo.git` or `https://github.com/owner/repo`
+fn repo_slug(url: &str) -> Option<(String, String)> {
+    let path = url.trim_end_matches('/').trim_end_matches(".git");
+    let mut parts = path.rsplit(['/', ':']);
+    let repo = parts.next().filter(|repo| !repo.is_empty())?;
+    let owner = parts.next().filter(|owner| !owner.is_empty())?;
+    Some((owner.to_string(), repo.to_string()))
+}
+
+/// Returns the number of the PR that `arg` refers to, if it's a reference like `#12` or `!12`, or
+/// the URL of a PR or merge request
+pub fn parse_pr_arg(arg: &str) -> Option<String> {
+    let is_number = |n: &&str| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit());
+    if let Some(number) = arg.strip_prefix('#').or(arg.strip_prefix('!')) {
+        return Some(number).filter(is_number).map(str::to_string);
+    }
+    if !arg.starts_with("https://") && !arg.starts_with("http://") {
+        return None;
+    }
+    // e.g. https://github.com/owner/repo/pull/12/files or .../-/merge_requests/12
+    let segments: Vec<&str> = arg.split(['/', '?', '#']).collect();
+    segments
+        .windows(2)
+        .find(|pair| matches!(pair[0], "pull" | "pulls" | "merge_requests"))
+        .map(|pair| pair[1])
+        .filter(is_number)
+        .map(str::to_string)
+}
+
+/// Extracts the PR number from the URL printed by `gh pr create` and `glab mr create`
+fn number_from_url(url: &str) -> Option<String> {
+    url.trim()
+        .rsplit('/')
+        .next()
+        .filter(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+        .map(str::to_string)
+}
@@ -0,0 +1,319 @@
+use super::{
+    Comment, Forge, MergeStrategy, Offline, Participants, PrLookup, PrRef, PrState, PrStatus,
+};
+use crate::{config, git_dir};
+use eyre::Error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Wraps a forge, remembering the answers to its queries for the rest of the invocation.
+///
+/// Rendering the notes of a stack asks for the PR of every branch once per note, so without a
+/// cache a stack of n branches costs n² requests. Here, PRs are looked up in the list of open
+/// PRs, which is fetched once, and only branches without an open PR are asked about one by one.
+/// Anything that changes a PR clears the cache.
+///
+/// The cache can also be kept in `.git/stackbuddy/cache.json`, so that commands run one after the
+/// other don't fetch the same PRs again, for as long as the `cache-ttl` config says.
+pub struct Cached {
+    inner: Box<dyn Forge>,
+    cache: Mutex<Cache>,
+    /// Where the cache is saved, if it's kept between invocations
+    path: Option<PathBuf>,
+    /// Whether the answers come from a cache that can't be refreshed, see [`CacheMode::Offline`]
+    offline: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Cache {
+    /// When the oldest answer in the cache was fetched, in seconds since the epoch
+    fetched_at: u64,
+    open_prs: Option<HashMap<String, PrStatus>>,
+    prs: HashMap<String, Vec<PrRef>>,
+    /// PR bodies fetched at once by [`Forge::pr_bodies`], which aren't saved since they're only
+    /// read once per command, right before being edited
+    #[serde(skip)]
+    bodies: HashMap<String, String>,
+}
+
+/// How the answers of the forge are kept between invocations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Uses the saved answers that aren't older than the `cache-ttl` config, and saves new ones
+    Use,
+    /// Fetches everything again, and saves the new answers
+    Refresh,
+    /// Only remembers answers until the command exits
+    Off,
+    /// Only uses the saved answers, however old, and never reaches the forge
+    Offline,
+}
+
+impl Cached {
+    pub fn new(inner: Box<dyn Forge>) -> Self {
+        Self {
+            inner,
+            cache: Mutex::default(),
+            path: None,
+            offline: false,
+        }
+    }
+
+    /// Like [`Cached::new`], but the cache is kept in `.git/stackbuddy/cache.json` as `mode` says
+    pub fn persistent(inner: Box<dyn Forge>, mode: CacheMode) -> Result<Self, Error> {
+        let path = git_dir()?.join("stackbuddy").join("cache.json");
+        if mode == CacheMode::Offline {
+            // Nothing is saved, since nothing new is fetched
+            return Ok(Self {
+                inner: Box::new(Offline::new(inner)),
+                cache: Mutex::new(Cache::load(&path, Duration::MAX)),
+                path: None,
+                offline: true,
+            });
+        }
+        let ttl = config::load()?.cache_ttl();
+        if mode == CacheMode::Off || ttl.is_zero() {
+            return Ok(Self::new(inner));
+        }
+        let cache = match mode {
+            CacheMode::Use => Cache::load(&path, ttl),
+            _ => Cache::default(),
+        };
+        Ok(Self {
+            inner,
+            cache: Mutex::new(cache),
+            path: Some(path),
+            offline: false,
+        })
+    }
+
+    /// Saves the cache, if it's kept between invocations. The cache is only an optimization, so
+    /// failing to save it isn't an error
+    fn save(&self, cache: &mut Cache) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if cache.fetched_at == 0 {
+            cache.fetched_at = now().as_secs();
+        }
+        let saved = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(path, serde_json::to_string(cache).unwrap_or_default()));
+        if let Err(e) = saved {
+            tracing::debug!("failed to save {}: {e}", path.display());
+        }
+    }
+
+    /// Returns the open PR of `branch`, if there is one
+    fn open_pr_ref(&self, branch: &str) -> Result<Option<PrRef>, Error> {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.open_prs.is_none() {
+            cache.open_prs = Some(self.inner.open_prs()?);
+            self.save(&mut cache);
+        }
+        let prs = cache.open_prs.as_ref().unwrap();
+        Ok(prs.get(branch).map(|pr| PrRef {
+            number: pr.number.clone(),
+            state: PrState::Open,
+            url: pr.url.clone(),
+        }))
+    }
+}
+
+impl Forge for Cached {
+    fn prs_for_branch(&self, branch: &str) -> Result<Vec<PrRef>, Error> {
+        if let Some(prs) = self.cache.lock().unwrap().prs.get(branch) {
+            return Ok(prs.clone());
+        }
+        let prs = self.inner.prs_for_branch(branch)?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.prs.insert(branch.to_string(), prs.clone());
+        self.save(&mut cache);
+        Ok(prs)
+    }
+
+    fn pr_for_branch(&self, branch: &str) -> Result<Option<PrRef>, Error> {
+        // An open PR wins unless the most recent one is asked for, and most are in the list
+        let lookup = config::load()?.pr_lookup.unwrap_or_default();
+        if lookup != PrLookup::Latest {
+            if let Some(pr) = self.open_pr_ref(branch)? {
+                return Ok(Some(pr));
+            }
+        }
+        Ok(lookup.pick(self.prs_for_branch(branch)?))
+    }
+
+    fn pr_head(&self, number: &str) -> Result<String, Error> {
+        self.inner.pr_head(number)
+    }
+
+    fn pr_body(&self, branch: &str) -> Result<String, Error> {
+        if let Some(body) = self.cache.lock().unwrap().bodies.get(branch) {
+            return Ok(body.clone());
+        }
+        self.inner.pr_body(branch)
+    }
+
+    fn set_pr_body(&self, branch: &str, body: &str) -> Result<(), Error> {
+        self.inner.set_pr_body(branch, body)?;
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(cached) = cache.bodies.get_mut(branch) {
+            *cached = body.to_string();
+        }
+        Ok(())
+    }
+
+    fn pr_bodies(&self, branches: &[String]) -> Result<Option<HashMap<String, String>>, Error> {
+        let bodies = self.inner.pr_bodies(branches)?;
+        if let Some(bodies) = &bodies {
+            let mut cache = self.cache.lock().unwrap();
+            cache.bodies.extend(bodies.clone());
+        }
+        Ok(bodies)
+    }
+
+    fn set_pr_bodies(&self, bodies: &[(String, String)]) -> Vec<Result<(), Error>> {
+        let results = self.inner.set_pr_bodies(bodies);
+        let mut cache = self.cache.lock().unwrap();
+        for ((branch, body), result) in bodies.iter().zip(&results) {
+            if let (Some(cached), Ok(())) = (cache.bodies.get_mut(branch), result) {
+                *cached = body.clone();
+            }
+        }
+        results
+    }
+
+    fn create_pr(&self, branch: &str, base: &str, draft: bool) -> Result<String, Error> {
+        self.refresh();
+        self.inner.create_pr(branch, base, draft)
+    }
+
+    fn set_pr_base(&self, branch: &str, base: &str) -> Result<(), Error> {
+        self.refresh();
+        self.inner.set_pr_base(branch, base)
+    }
+
+    fn merge_pr(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        self.refresh();
+        self.inner.merge_pr(branch, strategy)
+    }
+
+    fn enable_auto_merge(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        self.refresh();
+        self.inner.enable_auto_merge(branch, strategy)
+    }
+
+    fn enqueue_pr(&self, branch: &str) -> Result<(), Error> {
+        self.refresh();
+        self.inner.enqueue_pr(branch)
+    }
+
+    fn close_pr(&self, branch: &str) -> Result<(), Error> {
+        self.refresh();
+        self.inner.close_pr(branch)
+    }
+
+    fn set_pr_draft(&self, branch: &str, draft: bool) -> Result<(), Error> {
+        self.refresh();
+        self.inner.set_pr_draft(branch, draft)
+    }
+
+    fn add_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        self.refresh();
+        self.inner.add_pr_label(branch, label)
+    }
+
+    fn remove_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        self.refresh();
+        self.inner.remove_pr_label(branch, label)
+    }
+
+    fn pr_participants(&self, branch: &str) -> Result<Participants, Error> {
+        self.inner.pr_participants(branch)
+    }
+
+    fn add_pr_reviewers(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        self.refresh();
+        self.inner.add_pr_reviewers(branch, users)
+    }
+
+    fn add_pr_assignees(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        self.refresh();
+        self.inner.add_pr_assignees(branch, users)
+    }
+
+    fn rename_branch(&self, old: &str, new: &str) -> Result<(), Error> {
+        self.refresh();
+        self.inner.rename_branch(old, new)
+    }
+
+    fn open_pr(&self, branch: &str) -> Result<(), Error> {
+        self.inner.open_pr(branch)
+    }
+
+    fn pr_comments(&self, branch: &str) -> Result<Vec<Comment>, Error> {
+        self.inner.pr_comments(branch)
+    }
+
+    fn create_pr_comment(&self, branch: &str, body: &str) -> Result<(), Error> {
+        self.inner.create_pr_comment(branch, body)
+    }
+
+    fn edit_pr_comment(&self, branch: &str, id: &str, body: &str) -> Result<(), Error> {
+        self.inner.edit_pr_comment(branch, id, body)
+    }
+
+    fn open_prs(&self) -> Result<HashMap<String, PrStatus>, Error> {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.open_prs.is_none() {
+            cache.open_prs = Some(self.inner.open_prs()?);
+            self.save(&mut cache);
+        }
+        Ok(cache.open_prs.clone().unwrap())
+    }
+
+    fn refresh(&self) {
+        *self.cache.lock().unwrap() = Cache::default();
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn staleness(&self) -> Option<Duration> {
+        let fetched_at = self.cache.lock().unwrap().fetched_at;
+        // With nothing cached, there are no answers to be out of date
+        if !self.offline || fetched_at == 0 {
+            return None;
+        }
+        Some(now().saturating_sub(Duration::from_secs(fetched_at)))
+    }
+
+    fn pr_reference(&self, number: &str) -> String {
+        self.inner.pr_reference(number)
+    }
+}
+
+impl Cache {
+    /// Reads the cache saved in `path`, or returns an empty one if there's none, it can't be read,
+    /// or it's older than `ttl`
+    fn load(path: &Path, ttl: Duration) -> Self {
+        let cache: Option<Cache> = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok());
+        cache
+            .filter(|cache| now().saturating_sub(Duration::from_secs(cache.fetched_at)) < ttl)
+            .unwrap_or_default()
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
@@ -0,0 +1,419 @@
+use super::{
+    github_api::fill,
+    label_names, logins, open_url, remote_url, repo_slug,
+    retry::{status_error, Request},
+    Checks, Comment, Forge, MergeStrategy, Participants, PrRef, PrState, PrStatus,
+};
+use eyre::{eyre, Context, Error};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// How many PRs are asked for per page, which is the most Gitea allows by default
+const PAGE_SIZE: usize = 50;
+
+/// How many pages of PRs are looked through before giving up on finding the one of a branch
+const MAX_PAGES: usize = 20;
+
+/// Gitea or Forgejo, through their REST API. It authenticates with a token in the `GITEA_TOKEN`
+/// environment variable, and the instance is the one in the URL of the remote unless `GITEA_URL`
+/// says otherwise.
+pub struct Gitea {
+    token: String,
+    /// e.g. `https://codeberg.org`
+    url: String,
+    owner: String,
+    repo: String,
+}
+
+impl Gitea {
+    pub fn from_env() -> Result<Self, Error> {
+        let token = std::env::var("GITEA_TOKEN")
+            .context("Gitea needs a token in the GITEA_TOKEN environment variable")?;
+        let remote = remote_url()?;
+        let (owner, repo) =
+            repo_slug(&remote).ok_or_else(|| eyre!("could not find owner/repo in '{remote}'"))?;
+        let url = match std::env::var("GITEA_URL") {
+            Ok(url) => url.trim_end_matches('/').to_string(),
+            Err(_) => {
+                let host = repo_host(&remote)
+                    .ok_or_else(|| eyre!("could not find the host in '{remote}'"))?;
+                format!("https://{host}")
+            }
+        };
+        Ok(Self {
+            token,
+            url,
+            owner,
+            repo,
+        })
+    }
+
+    fn request(&self, method: &str, path: &str) -> Request {
+        Request::new(ureq::request(
+            method,
+            &format!(
+                "{}/api/v1/repos/{}/{}{path}",
+                self.url, self.owner, self.repo
+            ),
+        ))
+        .set("Authorization", &format!("token {}", self.token))
+        .set("Accept", "application/json")
+    }
+
+    /// Returns the PRs in `state` (`open`, `closed` or `all`), most recent first, as returned by
+    /// the API. Gitea can't filter them by head branch, so `stop` is asked after every page
+    /// whether the ones so far are enough.
+    fn pulls(&self, state: &str, stop: impl Fn(&[Value]) -> bool) -> Result<Vec<Value>, Error> {
+        let mut pulls = Vec::new();
+        for page in 1..=MAX_PAGES {
+            let batch: Value = self
+                .request("GET", "/pulls")
+                .query("state", state)
+                .query("sort", "newest")
+                .query("limit", &PAGE_SIZE.to_string())
+                .query("page", &page.to_string())
+                .call()
+                .map_err(api_error)
+                .context("failed to list pull requests")?
+                .into_json()
+                .context("failed to parse pull requests")?;
+            let batch = batch.as_array().cloned().unwrap_or_default();
+            let last = batch.len() < PAGE_SIZE;
+            pulls.extend(batch);
+            if last || stop(&pulls) {
+                break;
+            }
+        }
+        Ok(pulls)
+    }
+
+    fn pull(&self, number: &str) -> Result<Value, Error> {
+        self.request("GET", &format!("/pulls/{number}"))
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to get pull request #{number}"))?
+            .into_json()
+            .context("failed to parse pull request")
+    }
+
+    /// Returns the id of the label named `name`, which is how the API refers to labels
+    fn label_id(&self, name: &str) -> Result<Option<u64>, Error> {
+        for page in 1..=MAX_PAGES {
+            let labels: Value = self
+                .request("GET", "/labels")
+                .query("limit", &PAGE_SIZE.to_string())
+                .query("page", &page.to_string())
+                .call()
+                .map_err(api_error)
+                .context("failed to list labels")?
+                .into_json()
+                .context("failed to parse labels")?;
+            let labels = labels.as_array().cloned().unwrap_or_default();
+            if let Some(label) = labels
+                .iter()
+                .find(|label| label["name"].as_str() == Some(name))
+            {
+                return Ok(label["id"].as_u64());
+            }
+            if labels.len() < PAGE_SIZE {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Creates a label named `name`, returning its id
+    fn create_label(&self, name: &str) -> Result<u64, Error> {
+        let label: Value = self
+            .request("POST", "/labels")
+            .send_json(json!({ "name": name, "color": "#ededed" }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to create label '{name}'"))?
+            .into_json()
+            .context("failed to parse the created label")?;
+        label["id"]
+            .as_u64()
+            .ok_or_else(|| eyre!("the created label has no id"))
+    }
+
+    /// Returns the number of the PR of `branch`, even if it was merged or closed
+    fn number(&self, branch: &str) -> Result<String, Error> {
+        let pr = self
+            .pr_for_branch(branch)?
+            .ok_or_else(|| eyre!("no pull requests found for branch '{branch}'"))?;
+        Ok(pr.number)
+    }
+}
+
+impl Forge for Gitea {
+    fn prs_for_branch(&self, branch: &str) -> Result<Vec<PrRef>, Error> {
+        let is_head = |pr: &Value| pr["head"]["ref"].as_str() == Some(branch);
+        let prs = self
+            .pulls("all", |prs| prs.iter().any(is_head))?
+            .iter()
+            .filter(|pr| is_head(pr))
+            .map(|pr| {
+                // Like GitHub's, merged PRs are reported as closed
+                let state = match pr["state"].as_str() {
+                    Some("open") => PrState::Open,
+                    _ if pr["merged"].as_bool() == Some(true) => PrState::Merged,
+                    _ => PrState::Closed,
+                };
+                PrRef {
+                    number: pr["number"].to_string(),
+                    state,
+                    url: pr["html_url"].as_str().unwrap_or_default().to_string(),
+                }
+            })
+            .collect();
+        Ok(prs)
+    }
+
+    fn pr_head(&self, number: &str) -> Result<String, Error> {
+        self.pull(number)?["head"]["ref"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| eyre!("pull request #{number} has no head branch"))
+    }
+
+    fn pr_body(&self, branch: &str) -> Result<String, Error> {
+        let pr = self.pull(&self.number(branch)?)?;
+        Ok(pr["body"].as_str().unwrap_or_default().to_string())
+    }
+
+    fn set_pr_body(&self, branch: &str, body: &str) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        self.request("PATCH", &format!("/pulls/{number}"))
+            .send_json(json!({ "body": body }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to edit pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn create_pr(&self, branch: &str, base: &str, draft: bool) -> Result<String, Error> {
+        let (mut title, body) = fill(branch, base)?;
+        // Gitea marks drafts with a prefix in the title
+        if draft {
+            title = format!("WIP: {title}");
+        }
+        let pr: Value = self
+            .request("POST", "/pulls")
+            .send_json(json!({
+                "head": branch,
+                "base": base,
+                "title": title,
+                "body": body,
+            }))
+            .map_err(api_error)
+            .context("failed to create pull request")?
+            .into_json()
+            .context("failed to parse the created pull request")?;
+        pr["number"]
+            .as_u64()
+            .map(|n| n.to_string())
+            .ok_or_else(|| eyre!("the created pull request has no number"))
+    }
+
+    fn set_pr_base(&self, branch: &str, base: &str) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        self.request("PATCH", &format!("/pulls/{number}"))
+            .send_json(json!({ "base": base }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to change the base of pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn merge_pr(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        self.request("POST", &format!("/pulls/{number}/merge"))
+            .send_json(json!({ "Do": strategy.as_str() }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to merge pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn enable_auto_merge(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        self.request("POST", &format!("/pulls/{number}/merge"))
+            .send_json(json!({ "Do": strategy.as_str(), "merge_when_checks_succeed": true }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to enable auto-merge on pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn close_pr(&self, branch: &str) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        self.request("PATCH", &format!("/pulls/{number}"))
+            .send_json(json!({ "state": "closed" }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to close pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn set_pr_draft(&self, branch: &str, draft: bool) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        let pr = self.pull(&number)?;
+        let title = pr["title"].as_str().unwrap_or_default();
+        let bare = title.strip_prefix("WIP: ").unwrap_or(title);
+        let title = if draft {
+            format!("WIP: {bare}")
+        } else {
+            bare.to_string()
+        };
+        self.request("PATCH", &format!("/pulls/{number}"))
+            .send_json(json!({ "title": title }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to edit pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn add_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        let id = match self.label_id(label)? {
+            Some(id) => id,
+            None => self.create_label(label)?,
+        };
+        self.request("POST", &format!("/issues/{number}/labels"))
+            .send_json(json!({ "labels": [id] }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to label pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn remove_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        let Some(id) = self.label_id(label)? else {
+            return Ok(());
+        };
+        self.request("DELETE", &format!("/issues/{number}/labels/{id}"))
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to remove a label from pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn pr_participants(&self, branch: &str) -> Result<Participants, Error> {
+        let pr = self.pull(&self.number(branch)?)?;
+        Ok(Participants {
+            reviewers: logins(&pr["requested_reviewers"]),
+            assignees: logins(&pr["assignees"]),
+        })
+    }
+
+    fn add_pr_reviewers(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        self.request("POST", &format!("/pulls/{number}/requested_reviewers"))
+            .send_json(json!({ "reviewers": users }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to request reviews on pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn add_pr_assignees(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        // The assignees are replaced, so the current ones are sent too
+        let mut assignees = logins(&self.pull(&number)?["assignees"]);
+        for user in users {
+            if !assignees.contains(user) {
+                assignees.push(user.clone());
+            }
+        }
+        self.request("PATCH", &format!("/issues/{number}"))
+            .send_json(json!({ "assignees": assignees }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to assign pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn open_pr(&self, branch: &str) -> Result<(), Error> {
+        let pr = self
+            .pr_for_branch(branch)?
+            .ok_or_else(|| eyre!("no pull requests found for branch '{branch}'"))?;
+        open_url(&pr.url)
+    }
+
+    fn pr_comments(&self, branch: &str) -> Result<Vec<Comment>, Error> {
+        let number = self.number(branch)?;
+        let comments: Value = self
+            .request("GET", &format!("/issues/{number}/comments"))
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to list the comments of pull request #{number}"))?
+            .into_json()
+            .context("failed to parse comments")?;
+        let comments = comments
+            .as_array()
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(|comment| Comment {
+                id: comment["id"].to_string(),
+                body: comment["body"].as_str().unwrap_or_default().to_string(),
+            })
+            .collect();
+        Ok(comments)
+    }
+
+    fn create_pr_comment(&self, branch: &str, body: &str) -> Result<(), Error> {
+        let number = self.number(branch)?;
+        self.request("POST", &format!("/issues/{number}/comments"))
+            .send_json(json!({ "body": body }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to comment on pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn edit_pr_comment(&self, _branch: &str, id: &str, body: &str) -> Result<(), Error> {
+        self.request("PATCH", &format!("/issues/comments/{id}"))
+            .send_json(json!({ "body": body }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to edit comment {id}"))?;
+        Ok(())
+    }
+
+    fn open_prs(&self) -> Result<HashMap<String, PrStatus>, Error> {
+        let prs = self
+            .pulls("open", |_| false)?
+            .iter()
+            .map(|pr| {
+                let title = pr["title"].as_str().unwrap_or_default().to_string();
+                let mergeable = match pr["mergeable"].as_bool() {
+                    Some(true) => "MERGEABLE",
+                    Some(false) => "CONFLICTING",
+                    None => "UNKNOWN",
+                };
+                let status = PrStatus {
+                    number: pr["number"].to_string(),
+                    url: pr["html_url"].as_str().unwrap_or_default().to_string(),
+                    base: pr["base"]["ref"].as_str().unwrap_or_default().to_string(),
+                    draft: pr["draft"].as_bool().unwrap_or(title.starts_with("WIP:")),
+                    title,
+                    mergeable: mergeable.to_string(),
+                    // Commit statuses need a request per PR, so they're left out
+                    checks: Checks::default(),
+                    review: String::new(),
+                    labels: label_names(&pr["labels"]),
+                };
+                let head = pr["head"]["ref"].as_str().unwrap_or_default().to_string();
+                (head, status)
+            })
+            .collect();
+        Ok(prs)
+    }
+}
+
+/// Extracts the host from a remote URL, like `git@codeberg.org:owner/repo.git` or
+/// `https://codeberg.org/owner/repo`
+fn repo_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split_once('@').map_or(rest, |(_, rest)| rest);
+    let host = rest.split(['/', ':']).next()?;
+    Some(host.to_string()).filter(|host| !host.is_empty())
+}
+
+/// Includes the response body in the error, which is where Gitea explains what went wrong
+fn api_error(err: Box<ureq::Error>) -> Error {
+    match *err {
+        ureq::Error::Status(code, response) => status_error("Gitea API", code, response),
+        ureq::Error::Transport(transport) => eyre!("request to the Gitea API failed: {transport}"),
+    }
+}
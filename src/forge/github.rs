@@ -0,0 +1,419 @@
+use super::{
+    encode_path_segment, fork_owner, graphql, head_ref, label_names, logins, number_from_url,
+    recreate_branch, repo_name, run_command, Checks, Comment, Forge, MergeStrategy, Participants,
+    PrRef, PrState, PrStatus,
+};
+use eyre::{eyre, Context, Error};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// GitHub, through the gh CLI
+pub struct GitHub;
+
+impl GitHub {
+    /// Returns the number of the PR of `branch`
+    fn number(&self, branch: &str) -> Result<String, Error> {
+        let pr = self
+            .pr_for_branch(branch)?
+            .ok_or_else(|| eyre!("no pull requests found for branch '{branch}'"))?;
+        Ok(pr.number)
+    }
+
+    /// Sends a GraphQL query with its variables through `gh api graphql`, returning its `data`
+    fn graphql(&self, query: &str, variables: Value) -> Result<Value, Error> {
+        let request = json!({ "query": query, "variables": variables });
+        let output = run_command(
+            "gh",
+            &["api", "graphql", "--input", "-"],
+            Some(&request.to_string()),
+        )?;
+        let response: Value =
+            serde_json::from_str(&output).context("gh api graphql output was not json")?;
+        if let Some(errors) = response["errors"].as_array() {
+            return Err(eyre!(
+                "GraphQL request failed: {}",
+                Value::Array(errors.clone())
+            ));
+        }
+        Ok(response["data"].clone())
+    }
+
+    /// The owner and name of the repository, for GraphQL queries
+    fn repo(&self) -> Result<(String, String), Error> {
+        let name = repo_name()?;
+        let (owner, repo) = name
+            .split_once('/')
+            .ok_or_else(|| eyre!("'{name}' is not an owner/repo name"))?;
+        Ok((owner.to_string(), repo.to_string()))
+    }
+}
+
+impl Forge for GitHub {
+    fn prs_for_branch(&self, branch: &str) -> Result<Vec<PrRef>, Error> {
+        let output = run_command(
+            "gh",
+            &[
+                "pr",
+                "list",
+                "--head",
+                branch,
+                "--state",
+                "all",
+                "--json",
+                "number,state,url,headRepositoryOwner",
+            ],
+            None,
+        )?;
+        let fork = fork_owner()?;
+
+        let prs: Value = serde_json::from_str(&output).context("gh pr list output was not json")?;
+        let prs = prs
+            .as_array()
+            .ok_or_else(|| eyre!("gh pr list output was not a list"))?
+            .iter()
+            .filter(|pr| from_fork(pr, &fork))
+            .filter_map(|pr| {
+                Some(PrRef {
+                    number: pr["number"].to_string(),
+                    state: PrState::parse(pr["state"].as_str()?)?,
+                    url: pr["url"].as_str().unwrap_or_default().to_string(),
+                })
+            })
+            .collect();
+        Ok(prs)
+    }
+
+    fn pr_head(&self, number: &str) -> Result<String, Error> {
+        let head = run_command(
+            "gh",
+            &[
+                "pr",
+                "view",
+                number,
+                "--json",
+                "headRefName",
+                "--jq",
+                ".headRefName",
+            ],
+            None,
+        )?;
+        Ok(head.trim().to_string())
+    }
+
+    fn pr_body(&self, branch: &str) -> Result<String, Error> {
+        run_command(
+            "gh",
+            &[
+                "pr",
+                "view",
+                &head_ref(branch)?,
+                "--json",
+                "body",
+                "--jq",
+                ".body",
+            ],
+            None,
+        )
+    }
+
+    fn set_pr_body(&self, branch: &str, body: &str) -> Result<(), Error> {
+        run_command(
+            "gh",
+            &["pr", "edit", &head_ref(branch)?, "--body-file", "-"],
+            Some(body),
+        )?;
+        Ok(())
+    }
+
+    fn pr_bodies(&self, branches: &[String]) -> Result<Option<HashMap<String, String>>, Error> {
+        let (owner, repo) = self.repo()?;
+        let run = |query: &str, variables| self.graphql(query, variables);
+        graphql::pr_bodies(&run, &owner, &repo, branches).map(Some)
+    }
+
+    fn set_pr_bodies(&self, bodies: &[(String, String)]) -> Vec<Result<(), Error>> {
+        let (owner, repo) = match self.repo() {
+            Ok(repo) => repo,
+            Err(e) => {
+                tracing::debug!("failed to edit the PRs at once: {e}");
+                return bodies
+                    .iter()
+                    .map(|(branch, body)| self.set_pr_body(branch, body))
+                    .collect();
+            }
+        };
+        let run = |query: &str, variables| self.graphql(query, variables);
+        let set_pr_body = |branch: &str, body: &str| self.set_pr_body(branch, body);
+        graphql::set_pr_bodies(&run, &owner, &repo, bodies, &set_pr_body)
+    }
+
+    fn create_pr(&self, branch: &str, base: &str, draft: bool) -> Result<String, Error> {
+        let head = head_ref(branch)?;
+        let mut args = vec!["pr", "create", "--head", &head, "--base", base, "--fill"];
+        if draft {
+            args.push("--draft");
+        }
+        let url = run_command("gh", &args, None)?;
+        number_from_url(&url).ok_or_else(|| eyre!("unexpected gh pr create output: {url}"))
+    }
+
+    fn set_pr_base(&self, branch: &str, base: &str) -> Result<(), Error> {
+        run_command(
+            "gh",
+            &["pr", "edit", &head_ref(branch)?, "--base", base],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn merge_pr(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        let strategy = format!("--{}", strategy.as_str());
+        run_command("gh", &["pr", "merge", &head_ref(branch)?, &strategy], None)?;
+        Ok(())
+    }
+
+    fn enable_auto_merge(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        let strategy = format!("--{}", strategy.as_str());
+        run_command(
+            "gh",
+            &["pr", "merge", &head_ref(branch)?, "--auto", &strategy],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn enqueue_pr(&self, branch: &str) -> Result<(), Error> {
+        // When the base requires a merge queue, gh adds the PR to it instead of merging, and the
+        // queue decides how it's merged
+        run_command("gh", &["pr", "merge", &head_ref(branch)?, "--auto"], None)?;
+        Ok(())
+    }
+
+    fn close_pr(&self, branch: &str) -> Result<(), Error> {
+        run_command("gh", &["pr", "close", &head_ref(branch)?], None)?;
+        Ok(())
+    }
+
+    fn set_pr_draft(&self, branch: &str, draft: bool) -> Result<(), Error> {
+        let head = head_ref(branch)?;
+        let mut args = vec!["pr", "ready", &head];
+        if draft {
+            args.push("--undo");
+        }
+        run_command("gh", &args, None)?;
+        Ok(())
+    }
+
+    fn add_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        // Unlike `gh pr edit --add-label`, the REST API creates labels that don't exist yet
+        let path = format!(
+            "repos/{{owner}}/{{repo}}/issues/{}/labels",
+            self.number(branch)?
+        );
+        let field = format!("labels[]={label}");
+        run_command(
+            "gh",
+            &["api", "--method", "POST", &path, "--raw-field", &field],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn remove_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        let path = format!(
+            "repos/{{owner}}/{{repo}}/issues/{}/labels/{}",
+            self.number(branch)?,
+            encode_path_segment(label)
+        );
+        run_command("gh", &["api", "--method", "DELETE", &path], None)?;
+        Ok(())
+    }
+
+    fn pr_participants(&self, branch: &str) -> Result<Participants, Error> {
+        let output = run_command(
+            "gh",
+            &[
+                "pr",
+                "view",
+                &head_ref(branch)?,
+                "--json",
+                "reviewRequests,latestReviews,assignees",
+            ],
+            None,
+        )?;
+        let pr: Value = serde_json::from_str(&output).context("gh pr view output was not json")?;
+
+        // Teams are requested by their slug, and users by their login
+        let mut reviewers: Vec<String> = pr["reviewRequests"]
+            .as_array()
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .filter_map(|request| request["login"].as_str().or(request["slug"].as_str()))
+            .map(str::to_string)
+            .collect();
+        for review in pr["latestReviews"]
+            .as_array()
+            .map_or(&[][..], Vec::as_slice)
+        {
+            if let Some(login) = review["author"]["login"].as_str() {
+                if !reviewers.iter().any(|r| r == login) {
+                    reviewers.push(login.to_string());
+                }
+            }
+        }
+        Ok(Participants {
+            reviewers,
+            assignees: logins(&pr["assignees"]),
+        })
+    }
+
+    fn add_pr_reviewers(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        run_command(
+            "gh",
+            &[
+                "pr",
+                "edit",
+                &head_ref(branch)?,
+                "--add-reviewer",
+                &users.join(","),
+            ],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn add_pr_assignees(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        run_command(
+            "gh",
+            &[
+                "pr",
+                "edit",
+                &head_ref(branch)?,
+                "--add-assignee",
+                &users.join(","),
+            ],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn rename_branch(&self, old: &str, new: &str) -> Result<(), Error> {
+        // Branches on forks are renamed in the fork, which the placeholders don't point to
+        if fork_owner()?.is_some() {
+            return recreate_branch(self, old, new);
+        }
+        let path = format!(
+            "repos/{{owner}}/{{repo}}/branches/{}/rename",
+            encode_path_segment(old)
+        );
+        let field = format!("new_name={new}");
+        run_command(
+            "gh",
+            &["api", "--method", "POST", &path, "--raw-field", &field],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn open_pr(&self, branch: &str) -> Result<(), Error> {
+        run_command("gh", &["pr", "view", &head_ref(branch)?, "--web"], None)?;
+        Ok(())
+    }
+
+    fn pr_comments(&self, branch: &str) -> Result<Vec<Comment>, Error> {
+        // `gh pr view --json comments` only has the GraphQL ids, which the REST API can't edit
+        let path = format!(
+            "repos/{{owner}}/{{repo}}/issues/{}/comments",
+            self.number(branch)?
+        );
+        let output = run_command("gh", &["api", "--paginate", &path], None)?;
+
+        let comments: Value =
+            serde_json::from_str(&output).context("gh api output was not json")?;
+        let comments = comments
+            .as_array()
+            .ok_or_else(|| eyre!("gh api output was not a list"))?
+            .iter()
+            .map(|comment| Comment {
+                id: comment["id"].to_string(),
+                body: comment["body"].as_str().unwrap_or_default().to_string(),
+            })
+            .collect();
+        Ok(comments)
+    }
+
+    fn create_pr_comment(&self, branch: &str, body: &str) -> Result<(), Error> {
+        run_command(
+            "gh",
+            &["pr", "comment", &head_ref(branch)?, "--body-file", "-"],
+            Some(body),
+        )?;
+        Ok(())
+    }
+
+    fn edit_pr_comment(&self, _branch: &str, id: &str, body: &str) -> Result<(), Error> {
+        let path = format!("repos/{{owner}}/{{repo}}/issues/comments/{id}");
+        run_command(
+            "gh",
+            &["api", "--method", "PATCH", &path, "-F", "body=@-"],
+            Some(body),
+        )?;
+        Ok(())
+    }
+
+    fn open_prs(&self) -> Result<HashMap<String, PrStatus>, Error> {
+        let output = run_command(
+            "gh",
+            &[
+                "pr",
+                "list",
+                "--state",
+                "open",
+                "--limit",
+                "200",
+                "--json",
+                "number,url,title,baseRefName,isDraft,mergeable,statusCheckRollup,reviewDecision,headRefName,headRepositoryOwner,labels",
+            ],
+            None,
+        )?;
+        let fork = fork_owner()?;
+
+        let prs: Value = serde_json::from_str(&output).context("gh pr list output was not json")?;
+        let prs = prs
+            .as_array()
+            .ok_or_else(|| eyre!("gh pr list output was not a list"))?
+            .iter()
+            .filter(|pr| from_fork(pr, &fork))
+            .map(|pr| {
+                let status = PrStatus {
+                    number: pr["number"].to_string(),
+                    url: pr["url"].as_str().unwrap_or_default().to_string(),
+                    title: pr["title"].as_str().unwrap_or_default().to_string(),
+                    base: pr["baseRefName"].as_str().unwrap_or_default().to_string(),
+                    draft: pr["isDraft"].as_bool().unwrap_or_default(),
+                    mergeable: pr["mergeable"].as_str().unwrap_or("UNKNOWN").to_string(),
+                    checks: Checks::from_rollup(
+                        pr["statusCheckRollup"]
+                            .as_array()
+                            .map_or(&[], Vec::as_slice),
+                    ),
+                    review: pr["reviewDecision"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    labels: label_names(&pr["labels"]),
+                };
+                let head = pr["headRefName"].as_str().unwrap_or_default().to_string();
+                (head, status)
+            })
+            .collect();
+        Ok(prs)
+    }
+}
+
+/// Whether `pr` comes from the fork branches are pushed to, if there's one. Otherwise, PRs of
+/// other forks with a branch of the same name would be mistaken for ours.
+fn from_fork(pr: &Value, fork: &Option<String>) -> bool {
+    fork.as_ref()
+        .is_none_or(|owner| pr["headRepositoryOwner"]["login"].as_str() == Some(owner))
+}
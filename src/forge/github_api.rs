@@ -0,0 +1,519 @@
+use super::{
+    encode_path_segment, fork_owner, github_host, graphql, head_ref, label_names, logins, open_url,
+    recreate_branch, remote_url, repo_slug,
+    retry::{status_error, Request},
+    Checks, Comment, Forge, MergeStrategy, Participants, PrRef, PrState, PrStatus,
+};
+use crate::git;
+use eyre::{eyre, Context, Error};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// GitHub, through its REST API. Unlike [`super::GitHub`], this doesn't need the gh CLI to be
+/// installed, only a token in the `GITHUB_TOKEN` environment variable.
+pub struct GitHubApi {
+    token: String,
+    /// e.g. `https://api.github.com`, or `https://<host>/api/v3` on GitHub Enterprise Server
+    api_url: String,
+    graphql_url: String,
+    owner: String,
+    repo: String,
+}
+
+impl GitHubApi {
+    pub fn from_env() -> Result<Self, Error> {
+        let token = std::env::var("GITHUB_TOKEN")
+            .context("the api backend needs a token in the GITHUB_TOKEN environment variable")?;
+        // GitHub Actions tells which repository it's running for
+        let slug = match std::env::var("GITHUB_REPOSITORY") {
+            Ok(slug) => slug
+                .split_once('/')
+                .map(|(owner, repo)| (owner.to_string(), repo.to_string())),
+            Err(_) => None,
+        };
+        let (owner, repo) = match slug {
+            Some(slug) => slug,
+            None => {
+                let url = remote_url()?;
+                repo_slug(&url).ok_or_else(|| eyre!("could not find owner/repo in '{url}'"))?
+            }
+        };
+        // Actions also tells where the API is, which matters on GitHub Enterprise Server
+        let (api_url, graphql_url) = match github_host()?.as_str() {
+            "github.com" => (
+                "https://api.github.com".to_string(),
+                "https://api.github.com/graphql".to_string(),
+            ),
+            host => (
+                format!("https://{host}/api/v3"),
+                format!("https://{host}/api/graphql"),
+            ),
+        };
+        Ok(Self {
+            token,
+            api_url: std::env::var("GITHUB_API_URL").unwrap_or(api_url),
+            graphql_url: std::env::var("GITHUB_GRAPHQL_URL").unwrap_or(graphql_url),
+            owner,
+            repo,
+        })
+    }
+
+    fn request(&self, method: &str, path: &str) -> Request {
+        Request::new(ureq::request(
+            method,
+            &format!("{}/repos/{}/{}{path}", self.api_url, self.owner, self.repo),
+        ))
+        .set("Authorization", &format!("Bearer {}", self.token))
+        .set("Accept", "application/vnd.github+json")
+        .set("X-GitHub-Api-Version", "2022-11-28")
+    }
+
+    fn graphql(&self, query: &str) -> Result<Value, Error> {
+        self.graphql_with(query, json!({}))
+    }
+
+    /// Like [`GitHubApi::graphql`], with values for the `$variables` of the query
+    fn graphql_with(&self, query: &str, variables: Value) -> Result<Value, Error> {
+        // Queries only read, but mutations may have been applied before a failure
+        let query_only = !query.trim_start().starts_with("mutation");
+        let response: Value = Request::new(ureq::post(&self.graphql_url))
+            .repeatable(query_only)
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .send_json(json!({ "query": query, "variables": variables }))
+            .map_err(api_error)
+            .context("GraphQL request failed")?
+            .into_json()
+            .context("failed to parse GraphQL response")?;
+
+        if let Some(errors) = response["errors"].as_array() {
+            return Err(eyre!(
+                "GraphQL request failed: {}",
+                Value::Array(errors.clone())
+            ));
+        }
+        Ok(response["data"].clone())
+    }
+
+    /// Returns the PRs whose head is `branch`, most recent first, as returned by the API. `state`
+    /// is either `open`, `closed` or `all`.
+    fn pull_requests(&self, branch: &str, state: &str) -> Result<Vec<Value>, Error> {
+        let pulls: Value = self
+            .request("GET", "/pulls")
+            .query("head", &format!("{}:{branch}", self.head_owner()?))
+            .query("state", state)
+            .call()
+            .map_err(api_error)
+            .context("failed to list pull requests")?
+            .into_json()
+            .context("failed to parse pull requests")?;
+        Ok(pulls.as_array().cloned().unwrap_or_default())
+    }
+
+    /// The owner of the repository branches are pushed to, which is a fork with `push-remote`
+    fn head_owner(&self) -> Result<String, Error> {
+        Ok(fork_owner()?.unwrap_or_else(|| self.owner.clone()))
+    }
+
+    /// Returns the number of the open PR of `branch`
+    fn pr_number(&self, branch: &str) -> Result<String, Error> {
+        let pr = self
+            .pull_requests(branch, "open")?
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("no open pull requests found for branch '{branch}'"))?;
+        Ok(pr["number"].to_string())
+    }
+
+    /// Returns the number of the PR of `branch`, even if it was merged or closed
+    fn any_pr_number(&self, branch: &str) -> Result<String, Error> {
+        let pr = self
+            .pr_for_branch(branch)?
+            .ok_or_else(|| eyre!("no pull requests found for branch '{branch}'"))?;
+        Ok(pr.number)
+    }
+}
+
+impl Forge for GitHubApi {
+    fn prs_for_branch(&self, branch: &str) -> Result<Vec<PrRef>, Error> {
+        let prs = self
+            .pull_requests(branch, "all")?
+            .iter()
+            .map(|pr| {
+                // The REST API reports merged PRs as closed, with a merge date
+                let state = match pr["state"].as_str() {
+                    Some("open") => PrState::Open,
+                    _ if !pr["merged_at"].is_null() => PrState::Merged,
+                    _ => PrState::Closed,
+                };
+                PrRef {
+                    number: pr["number"].to_string(),
+                    state,
+                    url: pr["html_url"].as_str().unwrap_or_default().to_string(),
+                }
+            })
+            .collect();
+        Ok(prs)
+    }
+
+    fn pr_head(&self, number: &str) -> Result<String, Error> {
+        let pr: Value = self
+            .request("GET", &format!("/pulls/{number}"))
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to get pull request #{number}"))?
+            .into_json()
+            .context("failed to parse pull request")?;
+        pr["head"]["ref"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| eyre!("pull request #{number} has no head branch"))
+    }
+
+    fn pr_body(&self, branch: &str) -> Result<String, Error> {
+        // Merged and closed PRs have bodies too, e.g. to clean their notes
+        let number = self.any_pr_number(branch)?;
+        let pr: Value = self
+            .request("GET", &format!("/pulls/{number}"))
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to get pull request #{number}"))?
+            .into_json()
+            .context("failed to parse pull request")?;
+        Ok(pr["body"].as_str().unwrap_or_default().to_string())
+    }
+
+    fn set_pr_body(&self, branch: &str, body: &str) -> Result<(), Error> {
+        let number = self.any_pr_number(branch)?;
+        self.request("PATCH", &format!("/pulls/{number}"))
+            .send_json(json!({ "body": body }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to edit pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn pr_bodies(&self, branches: &[String]) -> Result<Option<HashMap<String, String>>, Error> {
+        let run = |query: &str, variables| self.graphql_with(query, variables);
+        graphql::pr_bodies(&run, &self.owner, &self.repo, branches).map(Some)
+    }
+
+    fn set_pr_bodies(&self, bodies: &[(String, String)]) -> Vec<Result<(), Error>> {
+        let run = |query: &str, variables| self.graphql_with(query, variables);
+        let set_pr_body = |branch: &str, body: &str| self.set_pr_body(branch, body);
+        graphql::set_pr_bodies(&run, &self.owner, &self.repo, bodies, &set_pr_body)
+    }
+
+    fn create_pr(&self, branch: &str, base: &str, draft: bool) -> Result<String, Error> {
+        let (title, body) = fill(branch, base)?;
+        let pr: Value = self
+            .request("POST", "/pulls")
+            .send_json(json!({
+                "head": head_ref(branch)?,
+                "base": base,
+                "title": title,
+                "body": body,
+                "draft": draft,
+            }))
+            .map_err(api_error)
+            .context("failed to create pull request")?
+            .into_json()
+            .context("failed to parse the created pull request")?;
+        pr["number"]
+            .as_u64()
+            .map(|n| n.to_string())
+            .ok_or_else(|| eyre!("the created pull request has no number"))
+    }
+
+    fn set_pr_base(&self, branch: &str, base: &str) -> Result<(), Error> {
+        let number = self.pr_number(branch)?;
+        self.request("PATCH", &format!("/pulls/{number}"))
+            .send_json(json!({ "base": base }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to change the base of pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn merge_pr(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        let number = self.pr_number(branch)?;
+        self.request("PUT", &format!("/pulls/{number}/merge"))
+            .send_json(json!({ "merge_method": strategy.as_str() }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to merge pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn enable_auto_merge(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        // Like drafts, auto-merge can only be enabled through the GraphQL API
+        let pr = self
+            .pull_requests(branch, "open")?
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("no open pull requests found for branch '{branch}'"))?;
+        let id = serde_json::to_string(&pr["node_id"])?;
+        let method = strategy.as_str().to_uppercase();
+        self.graphql(&format!(
+            "mutation {{ enablePullRequestAutoMerge(input: {{ pullRequestId: {id}, mergeMethod: {method} }}) {{ clientMutationId }} }}"
+        ))
+        .with_context(|| format!("failed to enable auto-merge on the PR of '{branch}'"))?;
+        Ok(())
+    }
+
+    fn enqueue_pr(&self, branch: &str) -> Result<(), Error> {
+        let pr = self
+            .pull_requests(branch, "open")?
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("no open pull requests found for branch '{branch}'"))?;
+        let id = serde_json::to_string(&pr["node_id"])?;
+        self.graphql(&format!(
+            "mutation {{ enqueuePullRequest(input: {{ pullRequestId: {id} }}) {{ clientMutationId }} }}"
+        ))
+        .with_context(|| format!("failed to add the PR of '{branch}' to the merge queue"))?;
+        Ok(())
+    }
+
+    fn close_pr(&self, branch: &str) -> Result<(), Error> {
+        let number = self.pr_number(branch)?;
+        self.request("PATCH", &format!("/pulls/{number}"))
+            .send_json(json!({ "state": "closed" }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to close pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn set_pr_draft(&self, branch: &str, draft: bool) -> Result<(), Error> {
+        // Only the GraphQL API can change whether a PR is a draft, by its node id
+        let pr = self
+            .pull_requests(branch, "open")?
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("no open pull requests found for branch '{branch}'"))?;
+        let id = serde_json::to_string(&pr["node_id"])?;
+        let mutation = if draft {
+            "convertPullRequestToDraft"
+        } else {
+            "markPullRequestReadyForReview"
+        };
+        self.graphql(&format!(
+            "mutation {{ {mutation}(input: {{ pullRequestId: {id} }}) {{ clientMutationId }} }}"
+        ))
+        .with_context(|| format!("failed to change whether the PR of '{branch}' is a draft"))?;
+        Ok(())
+    }
+
+    fn add_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        let number = self.any_pr_number(branch)?;
+        self.request("POST", &format!("/issues/{number}/labels"))
+            .send_json(json!({ "labels": [label] }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to label pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn remove_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        let number = self.any_pr_number(branch)?;
+        let label = encode_path_segment(label);
+        self.request("DELETE", &format!("/issues/{number}/labels/{label}"))
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to remove a label from pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn pr_participants(&self, branch: &str) -> Result<Participants, Error> {
+        let number = self.any_pr_number(branch)?;
+        let pr: Value = self
+            .request("GET", &format!("/pulls/{number}"))
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to get pull request #{number}"))?
+            .into_json()
+            .context("failed to parse pull request")?;
+        let reviews: Value = self
+            .request("GET", &format!("/pulls/{number}/reviews"))
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to list the reviews of pull request #{number}"))?
+            .into_json()
+            .context("failed to parse reviews")?;
+
+        let mut reviewers = logins(&pr["requested_reviewers"]);
+        for review in reviews.as_array().map_or(&[][..], Vec::as_slice) {
+            if let Some(login) = review["user"]["login"].as_str() {
+                if !reviewers.iter().any(|r| r == login) {
+                    reviewers.push(login.to_string());
+                }
+            }
+        }
+        Ok(Participants {
+            reviewers,
+            assignees: logins(&pr["assignees"]),
+        })
+    }
+
+    fn add_pr_reviewers(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        let number = self.pr_number(branch)?;
+        self.request("POST", &format!("/pulls/{number}/requested_reviewers"))
+            .send_json(json!({ "reviewers": users }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to request reviews on pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn add_pr_assignees(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        let number = self.any_pr_number(branch)?;
+        self.request("POST", &format!("/issues/{number}/assignees"))
+            .send_json(json!({ "assignees": users }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to assign pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn rename_branch(&self, old: &str, new: &str) -> Result<(), Error> {
+        if fork_owner()?.is_some() {
+            return recreate_branch(self, old, new);
+        }
+        self.request(
+            "POST",
+            &format!("/branches/{}/rename", encode_path_segment(old)),
+        )
+        .send_json(json!({ "new_name": new }))
+        .map_err(api_error)
+        .with_context(|| format!("failed to rename '{old}' to '{new}'"))?;
+        Ok(())
+    }
+
+    fn open_pr(&self, branch: &str) -> Result<(), Error> {
+        let pr = self
+            .pr_for_branch(branch)?
+            .ok_or_else(|| eyre!("no pull requests found for branch '{branch}'"))?;
+        open_url(&pr.url)
+    }
+
+    fn pr_comments(&self, branch: &str) -> Result<Vec<Comment>, Error> {
+        let number = self.any_pr_number(branch)?;
+        let comments: Value = self
+            .request("GET", &format!("/issues/{number}/comments"))
+            .query("per_page", "100")
+            .call()
+            .map_err(api_error)
+            .with_context(|| format!("failed to list the comments of pull request #{number}"))?
+            .into_json()
+            .context("failed to parse comments")?;
+        let comments = comments
+            .as_array()
+            .ok_or_else(|| eyre!("unexpected comments response: {comments}"))?
+            .iter()
+            .map(|comment| Comment {
+                id: comment["id"].to_string(),
+                body: comment["body"].as_str().unwrap_or_default().to_string(),
+            })
+            .collect();
+        Ok(comments)
+    }
+
+    fn create_pr_comment(&self, branch: &str, body: &str) -> Result<(), Error> {
+        let number = self.any_pr_number(branch)?;
+        self.request("POST", &format!("/issues/{number}/comments"))
+            .send_json(json!({ "body": body }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to comment on pull request #{number}"))?;
+        Ok(())
+    }
+
+    fn edit_pr_comment(&self, _branch: &str, id: &str, body: &str) -> Result<(), Error> {
+        self.request("PATCH", &format!("/issues/comments/{id}"))
+            .send_json(json!({ "body": body }))
+            .map_err(api_error)
+            .with_context(|| format!("failed to edit comment {id}"))?;
+        Ok(())
+    }
+
+    fn open_prs(&self) -> Result<HashMap<String, PrStatus>, Error> {
+        let query = format!(
+            r#"{{
+                repository(owner: "{}", name: "{}") {{
+                    pullRequests(states: OPEN, first: 100) {{
+                        nodes {{
+                            number url title baseRefName isDraft mergeable reviewDecision headRefName
+                            headRepositoryOwner {{ login }}
+                            labels(first: 100) {{ nodes {{ name }} }}
+                            commits(last: 1) {{ nodes {{ commit {{ statusCheckRollup {{
+                                contexts(first: 100) {{ nodes {{
+                                    ... on CheckRun {{ conclusion }}
+                                    ... on StatusContext {{ state }}
+                                }} }}
+                            }} }} }} }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            self.owner, self.repo
+        );
+        let data = self.graphql(&query)?;
+        let owner = self.head_owner()?;
+
+        let prs = data["repository"]["pullRequests"]["nodes"]
+            .as_array()
+            .ok_or_else(|| eyre!("unexpected GraphQL response: {data}"))?
+            .iter()
+            // Other forks can have branches with the same names
+            .filter(|pr| pr["headRepositoryOwner"]["login"].as_str() == Some(&owner))
+            .map(|pr| {
+                let rollup = &pr["commits"]["nodes"][0]["commit"]["statusCheckRollup"];
+                let status = PrStatus {
+                    number: pr["number"].to_string(),
+                    url: pr["url"].as_str().unwrap_or_default().to_string(),
+                    title: pr["title"].as_str().unwrap_or_default().to_string(),
+                    base: pr["baseRefName"].as_str().unwrap_or_default().to_string(),
+                    draft: pr["isDraft"].as_bool().unwrap_or_default(),
+                    mergeable: pr["mergeable"].as_str().unwrap_or("UNKNOWN").to_string(),
+                    checks: Checks::from_rollup(
+                        rollup["contexts"]["nodes"]
+                            .as_array()
+                            .map_or(&[], Vec::as_slice),
+                    ),
+                    review: pr["reviewDecision"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    labels: label_names(&pr["labels"]["nodes"]),
+                };
+                let head = pr["headRefName"].as_str().unwrap_or_default().to_string();
+                (head, status)
+            })
+            .collect();
+        Ok(prs)
+    }
+}
+
+/// Includes the response body in the error, which is where GitHub explains what went wrong
+fn api_error(err: Box<ureq::Error>) -> Error {
+    match *err {
+        ureq::Error::Status(code, response) => status_error("GitHub API", code, response),
+        ureq::Error::Transport(transport) => eyre!("request to the GitHub API failed: {transport}"),
+    }
+}
+
+/// Comes up with a title and body for a new PR from its commits, like `gh pr create --fill`: a
+/// single commit is used as is, while several commits are listed under the branch name
+pub(super) fn fill(branch: &str, base: &str) -> Result<(String, String), Error> {
+    let range = format!("{base}..{branch}");
+    let subjects = git::output(&["log", "--reverse", "--format=%s", &range])
+        .context("git log failed")?
+        .stdout;
+    let subjects: Vec<&str> = subjects.lines().collect();
+
+    if let [subject] = subjects[..] {
+        let body = git::output(&["log", "-1", "--format=%b", branch])
+            .context("git log failed")?
+            .stdout;
+        return Ok((subject.to_string(), body.trim().to_string()));
+    }
+
+    let body = subjects
+        .iter()
+        .map(|subject| format!("- {subject}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok((branch.to_string(), body))
+}
@@ -0,0 +1,275 @@
+use super::{
+    label_names, logins, number_from_url, run_command, Checks, Comment, Forge, MergeStrategy,
+    Participants, PrRef, PrState, PrStatus,
+};
+use eyre::{eyre, Context, Error};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// GitLab, through the glab CLI
+pub struct GitLab;
+
+impl GitLab {
+    /// Returns the open merge requests whose source branch is `branch`, most recent first, as
+    /// returned by the GitLab API. With `all`, merged and closed merge requests are also included.
+    fn merge_requests(&self, branch: &str, all: bool) -> Result<Vec<Value>, Error> {
+        let mut args = vec!["mr", "list", "--source-branch", branch, "--output", "json"];
+        if all {
+            args.push("--all");
+        }
+        let output = run_command("glab", &args, None)?;
+
+        let mrs: Value =
+            serde_json::from_str(&output).context("glab mr list output was not json")?;
+        Ok(mrs.as_array().cloned().unwrap_or_default())
+    }
+
+    /// Returns the iid of the merge request of `branch`, even if it was merged or closed
+    fn iid(&self, branch: &str) -> Result<String, Error> {
+        let mr = self
+            .pr_for_branch(branch)?
+            .ok_or_else(|| eyre!("no merge request found for branch '{branch}'"))?;
+        Ok(mr.number)
+    }
+}
+
+impl Forge for GitLab {
+    fn prs_for_branch(&self, branch: &str) -> Result<Vec<PrRef>, Error> {
+        let mrs = self
+            .merge_requests(branch, true)?
+            .iter()
+            .filter_map(|mr| {
+                Some(PrRef {
+                    number: mr["iid"].to_string(),
+                    state: PrState::parse(mr["state"].as_str()?)?,
+                    url: mr["web_url"].as_str().unwrap_or_default().to_string(),
+                })
+            })
+            .collect();
+        Ok(mrs)
+    }
+
+    fn pr_head(&self, number: &str) -> Result<String, Error> {
+        let output = run_command("glab", &["mr", "view", number, "--output", "json"], None)?;
+        let mr: Value =
+            serde_json::from_str(&output).context("glab mr view output was not json")?;
+        mr["source_branch"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| eyre!("merge request !{number} has no source branch"))
+    }
+
+    fn pr_body(&self, branch: &str) -> Result<String, Error> {
+        // Merged and closed merge requests have descriptions too, e.g. to clean their notes
+        let iid = self.iid(branch)?;
+        let iid: u64 = iid
+            .parse()
+            .with_context(|| format!("'{iid}' is not a merge request number"))?;
+        let mr = self
+            .merge_requests(branch, true)?
+            .into_iter()
+            .find(|mr| mr["iid"].as_u64() == Some(iid))
+            .ok_or_else(|| eyre!("no merge request found for branch '{branch}'"))?;
+        Ok(mr["description"].as_str().unwrap_or_default().to_string())
+    }
+
+    fn set_pr_body(&self, branch: &str, body: &str) -> Result<(), Error> {
+        run_command(
+            "glab",
+            &["mr", "update", &self.iid(branch)?, "--description", body],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn create_pr(&self, branch: &str, base: &str, draft: bool) -> Result<String, Error> {
+        let mut args = vec![
+            "mr",
+            "create",
+            "--source-branch",
+            branch,
+            "--target-branch",
+            base,
+            "--fill",
+            "--yes",
+        ];
+        if draft {
+            args.push("--draft");
+        }
+        let stdout = run_command("glab", &args, None)?;
+
+        // glab prints some progress before the URL of the merge request
+        stdout
+            .lines()
+            .rev()
+            .find_map(number_from_url)
+            .ok_or_else(|| eyre!("unexpected glab mr create output: {stdout}"))
+    }
+
+    fn set_pr_base(&self, branch: &str, base: &str) -> Result<(), Error> {
+        run_command(
+            "glab",
+            &["mr", "update", branch, "--target-branch", base],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn merge_pr(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        let mut args = vec!["mr", "merge", branch, "--yes"];
+        match strategy {
+            MergeStrategy::Squash => args.push("--squash"),
+            MergeStrategy::Rebase => args.push("--rebase"),
+            MergeStrategy::Merge => {}
+        };
+        run_command("glab", &args, None)?;
+        Ok(())
+    }
+
+    fn enable_auto_merge(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        let mut args = vec!["mr", "merge", branch, "--yes", "--auto-merge"];
+        match strategy {
+            MergeStrategy::Squash => args.push("--squash"),
+            MergeStrategy::Rebase => args.push("--rebase"),
+            MergeStrategy::Merge => {}
+        };
+        run_command("glab", &args, None)?;
+        Ok(())
+    }
+
+    fn close_pr(&self, branch: &str) -> Result<(), Error> {
+        run_command("glab", &["mr", "close", branch], None)?;
+        Ok(())
+    }
+
+    fn set_pr_draft(&self, branch: &str, draft: bool) -> Result<(), Error> {
+        let flag = if draft { "--draft" } else { "--ready" };
+        run_command("glab", &["mr", "update", branch, flag], None)?;
+        Ok(())
+    }
+
+    fn add_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        run_command("glab", &["mr", "update", branch, "--label", label], None)?;
+        Ok(())
+    }
+
+    fn remove_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        run_command("glab", &["mr", "update", branch, "--unlabel", label], None)?;
+        Ok(())
+    }
+
+    fn pr_participants(&self, branch: &str) -> Result<Participants, Error> {
+        let iid = self.iid(branch)?;
+        let output = run_command("glab", &["mr", "view", &iid, "--output", "json"], None)?;
+        let mr: Value =
+            serde_json::from_str(&output).context("glab mr view output was not json")?;
+        Ok(Participants {
+            reviewers: logins(&mr["reviewers"]),
+            assignees: logins(&mr["assignees"]),
+        })
+    }
+
+    fn add_pr_reviewers(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        // Without the +, glab replaces the reviewers instead
+        let users: Vec<String> = users.iter().map(|user| format!("+{user}")).collect();
+        run_command(
+            "glab",
+            &["mr", "update", branch, "--reviewer", &users.join(",")],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn add_pr_assignees(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        let users: Vec<String> = users.iter().map(|user| format!("+{user}")).collect();
+        run_command(
+            "glab",
+            &["mr", "update", branch, "--assignee", &users.join(",")],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn open_pr(&self, branch: &str) -> Result<(), Error> {
+        run_command("glab", &["mr", "view", branch, "--web"], None)?;
+        Ok(())
+    }
+
+    fn pr_comments(&self, branch: &str) -> Result<Vec<Comment>, Error> {
+        let path = format!(
+            "projects/:id/merge_requests/{}/notes?sort=asc&per_page=100",
+            self.iid(branch)?
+        );
+        let output = run_command("glab", &["api", &path], None)?;
+
+        let notes: Value = serde_json::from_str(&output).context("glab api output was not json")?;
+        let notes = notes
+            .as_array()
+            .ok_or_else(|| eyre!("glab api output was not a list"))?
+            .iter()
+            // System notes are the events of the merge request, like pushes
+            .filter(|note| !note["system"].as_bool().unwrap_or_default())
+            .map(|note| Comment {
+                id: note["id"].to_string(),
+                body: note["body"].as_str().unwrap_or_default().to_string(),
+            })
+            .collect();
+        Ok(notes)
+    }
+
+    fn create_pr_comment(&self, branch: &str, body: &str) -> Result<(), Error> {
+        run_command("glab", &["mr", "note", branch, "--message", body], None)?;
+        Ok(())
+    }
+
+    fn edit_pr_comment(&self, branch: &str, id: &str, body: &str) -> Result<(), Error> {
+        let path = format!(
+            "projects/:id/merge_requests/{}/notes/{id}",
+            self.iid(branch)?
+        );
+        let body = format!("body={body}");
+        run_command(
+            "glab",
+            &["api", "--method", "PUT", &path, "--raw-field", &body],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn open_prs(&self) -> Result<HashMap<String, PrStatus>, Error> {
+        let output = run_command(
+            "glab",
+            &["mr", "list", "--per-page", "100", "--output", "json"],
+            None,
+        )?;
+
+        let mrs: Value =
+            serde_json::from_str(&output).context("glab mr list output was not json")?;
+        let mrs = mrs
+            .as_array()
+            .ok_or_else(|| eyre!("glab mr list output was not a list"))?
+            .iter()
+            .map(|mr| {
+                // The list endpoint doesn't include pipelines or approvals
+                let status = PrStatus {
+                    number: mr["iid"].to_string(),
+                    url: mr["web_url"].as_str().unwrap_or_default().to_string(),
+                    title: mr["title"].as_str().unwrap_or_default().to_string(),
+                    base: mr["target_branch"].as_str().unwrap_or_default().to_string(),
+                    draft: mr["draft"].as_bool().unwrap_or_default(),
+                    mergeable: mr["merge_status"].as_str().unwrap_or("unknown").to_string(),
+                    checks: Checks::default(),
+                    review: String::new(),
+                    labels: label_names(&mr["labels"]),
+                };
+                let head = mr["source_branch"].as_str().unwrap_or_default().to_string();
+                (head, status)
+            })
+            .collect();
+        Ok(mrs)
+    }
+
+    fn pr_reference(&self, number: &str) -> String {
+        format!("!{number}")
+    }
+}
@@ -0,0 +1,142 @@
+//! Reads and edits the PRs of a whole stack on GitHub with a few GraphQL requests, instead of a
+//! few per PR. Both the gh CLI and the API backends send the requests their own way, as a function
+//! that takes a query with its variables and returns the `data` of the response.
+
+use super::fork_owner;
+use eyre::Error;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// How many PRs are edited per mutation, which keeps requests well below GitHub's size limits
+const BATCH_SIZE: usize = 20;
+
+/// Sends a query with its variables, returning the `data` of the response
+pub(super) type Run<'a> = &'a dyn Fn(&str, Value) -> Result<Value, Error>;
+
+/// Returns the open PRs of `branches` in `owner/repo`, with the given `fields` of each, found with
+/// a single query. Branches without an open PR are left out.
+fn open_prs(
+    run: Run,
+    owner: &str,
+    repo: &str,
+    branches: &[String],
+    fields: &str,
+) -> Result<HashMap<String, Value>, Error> {
+    // Other forks can have PRs from branches with the same names
+    let head_owner = fork_owner()?.unwrap_or_else(|| owner.to_string());
+    let mut params = vec!["$owner: String!".to_string(), "$repo: String!".to_string()];
+    let mut selections = Vec::new();
+    let mut variables = Map::new();
+    variables.insert("owner".into(), json!(owner));
+    variables.insert("repo".into(), json!(repo));
+    for (i, branch) in branches.iter().enumerate() {
+        params.push(format!("$head{i}: String!"));
+        selections.push(format!(
+            "pr{i}: pullRequests(headRefName: $head{i}, states: OPEN, first: 10) {{ \
+             nodes {{ headRepositoryOwner {{ login }} {fields} }} }}"
+        ));
+        variables.insert(format!("head{i}"), json!(branch));
+    }
+    let query = format!(
+        "query({}) {{ repository(owner: $owner, name: $repo) {{ {} }} }}",
+        params.join(", "),
+        selections.join(" ")
+    );
+    let data = run(&query, Value::Object(variables))?;
+
+    let mut prs = HashMap::new();
+    for (i, branch) in branches.iter().enumerate() {
+        let nodes = data["repository"][format!("pr{i}")]["nodes"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let pr = nodes
+            .into_iter()
+            .find(|pr| pr["headRepositoryOwner"]["login"].as_str() == Some(&head_owner));
+        if let Some(pr) = pr {
+            prs.insert(branch.clone(), pr);
+        }
+    }
+    Ok(prs)
+}
+
+/// Returns the bodies of the open PRs of `branches`, keyed by branch
+pub(super) fn pr_bodies(
+    run: Run,
+    owner: &str,
+    repo: &str,
+    branches: &[String],
+) -> Result<HashMap<String, String>, Error> {
+    let prs = open_prs(run, owner, repo, branches, "body")?;
+    Ok(prs
+        .into_iter()
+        .map(|(branch, pr)| (branch, pr["body"].as_str().unwrap_or_default().to_string()))
+        .collect())
+}
+
+/// Sets the bodies of the open PRs of several branches, given as (branch, body) pairs, with one
+/// query for their ids and a mutation per [`BATCH_SIZE`] PRs. The results are in the same order
+/// as `bodies`. Branches without an open PR, like merged ones, are edited with `set_pr_body`, and
+/// so are the PRs of a mutation that failed, to find out which of them failed.
+pub(super) fn set_pr_bodies(
+    run: Run,
+    owner: &str,
+    repo: &str,
+    bodies: &[(String, String)],
+    set_pr_body: &dyn Fn(&str, &str) -> Result<(), Error>,
+) -> Vec<Result<(), Error>> {
+    let branches: Vec<String> = bodies.iter().map(|(branch, _)| branch.clone()).collect();
+    let ids = match open_prs(run, owner, repo, &branches, "id") {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::debug!("failed to get the ids of the PRs at once: {e}");
+            HashMap::new()
+        }
+    };
+    let id = |branch: &str| ids.get(branch).and_then(|pr| pr["id"].as_str());
+
+    let mut results: Vec<Option<Result<(), Error>>> = bodies.iter().map(|_| None).collect();
+    let found: Vec<usize> = (0..bodies.len())
+        .filter(|&i| id(&bodies[i].0).is_some())
+        .collect();
+    for batch in found.chunks(BATCH_SIZE) {
+        let edits: Vec<(&str, &str)> = batch
+            .iter()
+            .filter_map(|&i| Some((id(&bodies[i].0)?, bodies[i].1.as_str())))
+            .collect();
+        match update_bodies(run, &edits) {
+            Ok(()) => batch.iter().for_each(|&i| results[i] = Some(Ok(()))),
+            Err(e) => {
+                tracing::debug!("failed to edit the PRs at once, editing them one by one: {e}")
+            }
+        }
+    }
+    results
+        .into_iter()
+        .zip(bodies)
+        .map(|(result, (branch, body))| result.unwrap_or_else(|| set_pr_body(branch, body)))
+        .collect()
+}
+
+/// Sets the bodies of PRs, given as (node id, body) pairs, in a single mutation
+fn update_bodies(run: Run, edits: &[(&str, &str)]) -> Result<(), Error> {
+    let mut params = Vec::new();
+    let mut selections = Vec::new();
+    let mut variables = Map::new();
+    for (i, (id, body)) in edits.iter().enumerate() {
+        params.push(format!("$id{i}: ID!, $body{i}: String!"));
+        selections.push(format!(
+            "update{i}: updatePullRequest(input: {{ pullRequestId: $id{i}, body: $body{i} }}) \
+             {{ clientMutationId }}"
+        ));
+        variables.insert(format!("id{i}"), json!(id));
+        variables.insert(format!("body{i}"), json!(body));
+    }
+    let mutation = format!(
+        "mutation({}) {{ {} }}",
+        params.join(", "),
+        selections.join(" ")
+    );
+    run(&mutation, Value::Object(variables))?;
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use super::{Comment, Forge, MergeStrategy, Participants, PrRef, PrState, PrStatus};
+use crate::journal::Journal;
+use eyre::Error;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Wraps a forge, recording every PR description it edits in the journal, so `stackbuddy undo`
+/// can restore them. See [`Journal`].
+pub struct Journaled {
+    inner: Box<dyn Forge>,
+    journal: Arc<Journal>,
+}
+
+impl Journaled {
+    pub fn new(inner: Box<dyn Forge>, journal: Arc<Journal>) -> Self {
+        Self { inner, journal }
+    }
+}
+
+impl Forge for Journaled {
+    fn prs_for_branch(&self, branch: &str) -> Result<Vec<PrRef>, Error> {
+        self.inner.prs_for_branch(branch)
+    }
+
+    fn pr_for_branch(&self, branch: &str) -> Result<Option<PrRef>, Error> {
+        self.inner.pr_for_branch(branch)
+    }
+
+    fn pr_state(&self, branch: &str) -> Result<Option<PrState>, Error> {
+        self.inner.pr_state(branch)
+    }
+
+    fn pr_head(&self, number: &str) -> Result<String, Error> {
+        self.inner.pr_head(number)
+    }
+
+    fn pr_body(&self, branch: &str) -> Result<String, Error> {
+        self.inner.pr_body(branch)
+    }
+
+    fn set_pr_body(&self, branch: &str, body: &str) -> Result<(), Error> {
+        let before = self.inner.pr_body(branch)?;
+        self.inner.set_pr_body(branch, body)?;
+        self.journal.record_body(branch, before, body);
+        Ok(())
+    }
+
+    fn pr_bodies(&self, branches: &[String]) -> Result<Option<HashMap<String, String>>, Error> {
+        self.inner.pr_bodies(branches)
+    }
+
+    fn set_pr_bodies(&self, bodies: &[(String, String)]) -> Vec<Result<(), Error>> {
+        // Bodies that can't be read aren't edited, since they couldn't be restored
+        let mut results: Vec<Option<Result<(), Error>>> = Vec::new();
+        let mut edits = Vec::new();
+        let mut befores = Vec::new();
+        for (branch, body) in bodies {
+            match self.inner.pr_body(branch) {
+                Ok(before) => {
+                    results.push(None);
+                    edits.push((branch.clone(), body.clone()));
+                    befores.push(before);
+                }
+                Err(e) => results.push(Some(Err(e))),
+            }
+        }
+        let mut edited = self
+            .inner
+            .set_pr_bodies(&edits)
+            .into_iter()
+            .zip(edits.iter().zip(befores));
+        results
+            .into_iter()
+            .map(|result| {
+                result.unwrap_or_else(|| {
+                    let (result, ((branch, body), before)) = edited.next().unwrap();
+                    if result.is_ok() {
+                        self.journal.record_body(branch, before, body);
+                    }
+                    result
+                })
+            })
+            .collect()
+    }
+
+    fn create_pr(&self, branch: &str, base: &str, draft: bool) -> Result<String, Error> {
+        self.inner.create_pr(branch, base, draft)
+    }
+
+    fn set_pr_base(&self, branch: &str, base: &str) -> Result<(), Error> {
+        self.inner.set_pr_base(branch, base)
+    }
+
+    fn merge_pr(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        self.inner.merge_pr(branch, strategy)
+    }
+
+    fn enable_auto_merge(&self, branch: &str, strategy: MergeStrategy) -> Result<(), Error> {
+        self.inner.enable_auto_merge(branch, strategy)
+    }
+
+    fn enqueue_pr(&self, branch: &str) -> Result<(), Error> {
+        self.inner.enqueue_pr(branch)
+    }
+
+    fn close_pr(&self, branch: &str) -> Result<(), Error> {
+        self.inner.close_pr(branch)
+    }
+
+    fn set_pr_draft(&self, branch: &str, draft: bool) -> Result<(), Error> {
+        self.inner.set_pr_draft(branch, draft)
+    }
+
+    fn add_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        self.inner.add_pr_label(branch, label)
+    }
+
+    fn remove_pr_label(&self, branch: &str, label: &str) -> Result<(), Error> {
+        self.inner.remove_pr_label(branch, label)
+    }
+
+    fn pr_participants(&self, branch: &str) -> Result<Participants, Error> {
+        self.inner.pr_participants(branch)
+    }
+
+    fn add_pr_reviewers(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        self.inner.add_pr_reviewers(branch, users)
+    }
+
+    fn add_pr_assignees(&self, branch: &str, users: &[String]) -> Result<(), Error> {
+        self.inner.add_pr_assignees(branch, users)
+    }
+
+    fn rename_branch(&self, old: &str, new: &str) -> Result<(), Error> {
+        self.inner.rename_branch(old, new)
+    }
+
+    fn open_pr(&self, branch: &str) -> Result<(), Error> {
+        self.inner.open_pr(branch)
+    }
+
+    fn pr_comments(&self, branch: &str) -> Result<Vec<Comment>, Error> {
+        self.inner.pr_comments(branch)
+    }
+
+    fn create_pr_comment(&self, branch: &str, body: &str) -> Result<(), Error> {
+        self.inner.create_pr_comment(branch, body)
+    }
+
+    fn edit_pr_comment(&self, branch: &str, id: &str, body: &str) -> Result<(), Error> {
+        self.inner.edit_pr_comment(branch, id, body)
+    }
+
+    fn open_prs(&self) -> Result<HashMap<String, PrStatus>, Error> {
+        self.inner.open_prs()
+    }
+
+    fn refresh(&self) {
+        self.inner.refresh()
+    }
+
+    fn staleness(&self) -> Option<Duration> {
+        self.inner.staleness()
+    }
+
+    fn pr_reference(&self, number: &str) -> String {
+        self.inner.pr_reference(number)
+    }
+}
@@ -0,0 +1,120 @@
+use super::{Comment, Forge, MergeStrategy, Participants, PrRef, PrStatus};
+use eyre::{eyre, Error};
+use std::collections::HashMap;
+
+/// A forge that can't be reached, for `--offline`. Wrapped in a [`super::Cached`] with the PRs that
+/// were saved by earlier invocations, it answers what the cache doesn't know with an error, except
+/// for the PRs of single branches, which are assumed to not exist.
+pub struct Offline {
+    /// Only used for [`Forge::pr_reference`], which doesn't need the network
+    inner: Box<dyn Forge>,
+}
+
+impl Offline {
+    pub fn new(inner: Box<dyn Forge>) -> Self {
+        Self { inner }
+    }
+}
+
+fn offline<T>(what: &str) -> Result<T, Error> {
+    Err(eyre!(
+        "can't {what} with --offline, which doesn't reach the forge"
+    ))
+}
+
+impl Forge for Offline {
+    fn prs_for_branch(&self, _branch: &str) -> Result<Vec<PrRef>, Error> {
+        // Only the open PRs are sure to be cached, and most branches without one have no PR
+        Ok(Vec::new())
+    }
+
+    fn pr_head(&self, number: &str) -> Result<String, Error> {
+        offline(&format!("find the branch of {}", self.pr_reference(number)))
+    }
+
+    fn pr_body(&self, _branch: &str) -> Result<String, Error> {
+        offline("read PR descriptions")
+    }
+
+    fn set_pr_body(&self, _branch: &str, _body: &str) -> Result<(), Error> {
+        offline("edit PR descriptions")
+    }
+
+    fn create_pr(&self, _branch: &str, _base: &str, _draft: bool) -> Result<String, Error> {
+        offline("create PRs")
+    }
+
+    fn set_pr_base(&self, _branch: &str, _base: &str) -> Result<(), Error> {
+        offline("retarget PRs")
+    }
+
+    fn merge_pr(&self, _branch: &str, _strategy: MergeStrategy) -> Result<(), Error> {
+        offline("merge PRs")
+    }
+
+    fn enable_auto_merge(&self, _branch: &str, _strategy: MergeStrategy) -> Result<(), Error> {
+        offline("enable auto-merge")
+    }
+
+    fn enqueue_pr(&self, _branch: &str) -> Result<(), Error> {
+        offline("add PRs to the merge queue")
+    }
+
+    fn close_pr(&self, _branch: &str) -> Result<(), Error> {
+        offline("close PRs")
+    }
+
+    fn set_pr_draft(&self, _branch: &str, _draft: bool) -> Result<(), Error> {
+        offline("change whether PRs are drafts")
+    }
+
+    fn add_pr_label(&self, _branch: &str, _label: &str) -> Result<(), Error> {
+        offline("label PRs")
+    }
+
+    fn remove_pr_label(&self, _branch: &str, _label: &str) -> Result<(), Error> {
+        offline("label PRs")
+    }
+
+    fn pr_participants(&self, _branch: &str) -> Result<Participants, Error> {
+        offline("find the reviewers of PRs")
+    }
+
+    fn add_pr_reviewers(&self, _branch: &str, _users: &[String]) -> Result<(), Error> {
+        offline("request reviews")
+    }
+
+    fn add_pr_assignees(&self, _branch: &str, _users: &[String]) -> Result<(), Error> {
+        offline("assign PRs")
+    }
+
+    fn rename_branch(&self, _old: &str, _new: &str) -> Result<(), Error> {
+        offline("rename branches on the forge")
+    }
+
+    fn open_pr(&self, _branch: &str) -> Result<(), Error> {
+        offline("open PRs")
+    }
+
+    fn pr_comments(&self, _branch: &str) -> Result<Vec<Comment>, Error> {
+        offline("read PR comments")
+    }
+
+    fn create_pr_comment(&self, _branch: &str, _body: &str) -> Result<(), Error> {
+        offline("comment on PRs")
+    }
+
+    fn edit_pr_comment(&self, _branch: &str, _id: &str, _body: &str) -> Result<(), Error> {
+        offline("edit PR comments")
+    }
+
+    fn open_prs(&self) -> Result<HashMap<String, PrStatus>, Error> {
+        Err(eyre!(
+            "the open PRs aren't cached yet, run a command like `stackbuddy status` without --offline first"
+        ))
+    }
+
+    fn pr_reference(&self, number: &str) -> String {
+        self.inner.pr_reference(number)
+    }
+}
@@ -0,0 +1,245 @@
+//! Retries forge calls that failed because of a flaky network or a rate limit, waiting longer after
+//! each attempt. Big stacks make lots of calls in a row, and one of them failing shouldn't stop a
+//! command midway.
+//!
+//! A call that failed with a gateway error or a broken connection may have been applied anyway, so
+//! only the ones that can be sent twice without harm are retried then, like reads and edits.
+//! Creating a PR or a comment again would make a duplicate, so those are only retried when they
+//! surely weren't applied: when they hit the rate limit or never reached the forge.
+
+use eyre::{eyre, Error};
+use serde_json::Value;
+use std::{
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How many times a call is retried before giving up
+const RETRIES: u32 = 4;
+
+/// How long to wait before the first retry. The wait doubles after each one
+const FIRST_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest a rate limit is waited for. If it resets later than this, the call fails right away
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// Why a call is worth retrying
+enum Failure {
+    /// The network or the server had a hiccup, maybe after the call was applied
+    Transient,
+    /// The call couldn't reach the forge, so it wasn't applied
+    Unreached,
+    /// The rate limit was exceeded, and resets after the given time, if the forge said when
+    RateLimited(Option<Duration>),
+}
+
+/// Runs `call` until it succeeds, fails with an error that `classify` doesn't think is worth
+/// retrying, or runs out of retries. `name` tells which call is waiting
+fn retry<T, E>(
+    name: &str,
+    mut call: impl FnMut() -> Result<T, E>,
+    classify: impl Fn(&E) -> Option<Failure>,
+) -> Result<T, E> {
+    let mut delay = FIRST_DELAY;
+    let mut attempt = 0;
+    loop {
+        let result = call();
+        let Err(e) = &result else {
+            return result;
+        };
+        attempt += 1;
+        let Some(failure) = classify(e).filter(|_| attempt <= RETRIES) else {
+            return result;
+        };
+        let (wait, reason) = match failure {
+            Failure::RateLimited(Some(reset)) if reset > MAX_WAIT => return result,
+            Failure::RateLimited(Some(reset)) => (reset.max(delay), "hit the rate limit"),
+            // Rate limits without a reset time usually take a while
+            Failure::RateLimited(None) => (delay * 5, "hit the rate limit"),
+            Failure::Transient | Failure::Unreached => (delay, "failed"),
+        };
+        info!(
+            "{name} {reason}, retrying in {}s ({attempt}/{RETRIES})...",
+            wait.as_secs()
+        );
+        thread::sleep(wait);
+        delay *= 2;
+    }
+}
+
+/// Whether a call that failed with `failure` can be sent again. Calls that aren't `repeatable`
+/// may have been applied by the forge before a transient failure
+fn retriable(failure: Failure, repeatable: bool) -> Option<Failure> {
+    match failure {
+        Failure::Transient if !repeatable => None,
+        failure => Some(failure),
+    }
+}
+
+/// Runs `call`, a command of a forge's CLI named `name`, retrying it when what it printed says
+/// the network failed or the rate limit was exceeded. See [`retriable`] for `repeatable`
+pub(super) fn command(
+    name: &str,
+    repeatable: bool,
+    call: impl FnMut() -> Result<String, Error>,
+) -> Result<String, Error> {
+    let classify = |e: &Error| cli_failure(&e.to_string()).and_then(|f| retriable(f, repeatable));
+    retry(name, call, classify).map_err(|e| {
+        match cli_failure(&e.to_string()) {
+            Some(Failure::RateLimited(_)) => e.wrap_err(format!(
+                "{name} hit the rate limit, and it didn't reset after retrying {RETRIES} times. Try again in a few minutes"
+            )),
+            _ => e,
+        }
+    })
+}
+
+/// Tells what went wrong from the error messages of gh and glab
+fn cli_failure(message: &str) -> Option<Failure> {
+    let message = message.to_lowercase();
+    if message.contains("rate limit") {
+        return Some(Failure::RateLimited(None));
+    }
+    // gh and glab are written in Go, and print the errors of its HTTP client
+    let unreached = ["connection refused", "no such host"];
+    if unreached.iter().any(|pattern| message.contains(pattern)) {
+        return Some(Failure::Unreached);
+    }
+    let transient = [
+        "http 502",
+        "http 503",
+        "http 504",
+        "connection reset by peer",
+        "i/o timeout",
+        "tls handshake timeout",
+        "client.timeout exceeded",
+        "unexpected eof",
+    ];
+    transient
+        .iter()
+        .any(|pattern| message.contains(pattern))
+        .then_some(Failure::Transient)
+}
+
+/// A [`ureq::Request`] whose `call` and `send_json` are retried when they fail because of the
+/// network, a gateway or the rate limit. POSTs usually create something, so they're only retried
+/// when they surely weren't applied, unless they're marked [`Request::repeatable`]
+#[derive(Clone)]
+pub(super) struct Request {
+    request: ureq::Request,
+    repeatable: bool,
+}
+
+impl Request {
+    pub fn new(request: ureq::Request) -> Self {
+        let repeatable = request.method() != "POST";
+        Self {
+            request,
+            repeatable,
+        }
+    }
+
+    /// Marks a POST that only reads, like a GraphQL query, as safe to send twice
+    pub fn repeatable(self, repeatable: bool) -> Self {
+        Self { repeatable, ..self }
+    }
+
+    pub fn set(self, header: &str, value: &str) -> Self {
+        Self {
+            request: self.request.set(header, value),
+            ..self
+        }
+    }
+
+    pub fn query(self, param: &str, value: &str) -> Self {
+        Self {
+            request: self.request.query(param, value),
+            ..self
+        }
+    }
+
+    // ureq's errors are large, since they carry the whole response, so they're boxed
+    pub fn call(self) -> Result<ureq::Response, Box<ureq::Error>> {
+        retry(
+            &self.name(),
+            || self.request.clone().call().map_err(Box::new),
+            |e| self.failure(e),
+        )
+    }
+
+    pub fn send_json(self, data: Value) -> Result<ureq::Response, Box<ureq::Error>> {
+        retry(
+            &self.name(),
+            || {
+                self.request
+                    .clone()
+                    .send_json(data.clone())
+                    .map_err(Box::new)
+            },
+            |e| self.failure(e),
+        )
+    }
+
+    fn failure(&self, err: &ureq::Error) -> Option<Failure> {
+        http_failure(err).and_then(|failure| retriable(failure, self.repeatable))
+    }
+
+    /// e.g. `GET https://api.github.com/repos/owner/repo/pulls`, without the query
+    fn name(&self) -> String {
+        let url = self.request.url();
+        let url = url.split_once('?').map_or(url, |(url, _)| url);
+        format!("{} {url}", self.request.method())
+    }
+}
+
+fn http_failure(err: &ureq::Error) -> Option<Failure> {
+    match err {
+        ureq::Error::Status(code, response) => match rate_limit(*code, response) {
+            Some(reset) => Some(Failure::RateLimited(reset)),
+            None if matches!(code, 502..=504) => Some(Failure::Transient),
+            None => None,
+        },
+        ureq::Error::Transport(transport) => match transport.kind() {
+            ureq::ErrorKind::ConnectionFailed => Some(Failure::Unreached),
+            ureq::ErrorKind::Io => Some(Failure::Transient),
+            _ => None,
+        },
+    }
+}
+
+/// Returns Some if `response` says the rate limit was exceeded, with how long until it resets if
+/// it says so. GitHub answers 403 or 429 with either a `retry-after` header, or with
+/// `x-ratelimit-remaining: 0` and the time it resets in `x-ratelimit-reset`
+fn rate_limit(code: u16, response: &ureq::Response) -> Option<Option<Duration>> {
+    let retry_after = response
+        .header("retry-after")
+        .and_then(|seconds| seconds.parse().ok())
+        .map(Duration::from_secs);
+    let exhausted = response.header("x-ratelimit-remaining") == Some("0");
+    if !(code == 429 || (code == 403 && (retry_after.is_some() || exhausted))) {
+        return None;
+    }
+    let reset = response
+        .header("x-ratelimit-reset")
+        .and_then(|reset| reset.parse().ok())
+        .and_then(|reset| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+            Some(Duration::from_secs(reset).saturating_sub(now))
+        });
+    Some(retry_after.or(reset))
+}
+
+/// The error for a response of the API named `api`, e.g. `GitHub API`, with its body, which is
+/// where forges explain what went wrong
+pub(super) fn status_error(api: &str, code: u16, response: ureq::Response) -> Error {
+    let limit = rate_limit(code, &response);
+    let body = response.into_string().unwrap_or_default();
+    match limit {
+        Some(Some(reset)) => eyre!(
+            "the {api} rate limit was exceeded, try again in {} minutes: {body}",
+            reset.as_secs().div_ceil(60)
+        ),
+        Some(None) => eyre!("the {api} rate limit was exceeded, try again later: {body}"),
+        None => eyre!("{api} returned {code}: {body}"),
+    }
+}
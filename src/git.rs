@@ -0,0 +1,347 @@
+//! Every git command stackbuddy runs goes through a [`GitBackend`]. [`Subprocess`] runs the git
+//! executable, and with the `gix` feature, [`Gitoxide`] answers the most frequent queries, like the
+//! current branch and the tips of branches, in-process instead. Another backend can be installed
+//! with [`with_backend`], like [`Mock`] to exercise the library without a real repository.
+
+#[cfg(feature = "gix")]
+mod gitoxide;
+
+#[cfg(feature = "gix")]
+pub use gitoxide::Gitoxide;
+
+use eyre::{eyre, Context, Error};
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The result of running git with its output captured
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GitOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl GitOutput {
+    /// A successful run that printed `stdout`
+    pub fn ok(stdout: &str) -> Self {
+        Self {
+            success: true,
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        }
+    }
+
+    /// A failed run that printed `stderr`
+    pub fn failed(stderr: &str) -> Self {
+        Self {
+            success: false,
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        }
+    }
+}
+
+/// The size of a diff, like `git diff --shortstat` shows
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffStat {
+    pub files: usize,
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+pub trait GitBackend: Send + Sync {
+    /// Runs git with `args`, capturing its output
+    fn output(&self, args: &[&str]) -> Result<GitOutput, Error>;
+
+    /// Runs git with `args`, letting it print to the terminal. Returns whether it succeeded.
+    fn status(&self, args: &[&str]) -> Result<bool, Error>;
+
+    /// Returns the branch HEAD points to, or None if it's detached
+    fn head_branch(&self) -> Result<Option<String>, Error> {
+        let output = self
+            .output(&["rev-parse", "--abbrev-ref", "HEAD"])
+            .context("git rev-parse failed")?;
+        if !output.success {
+            return Err(eyre!(
+                "failed to get the current branch: {}",
+                output.stderr.trim()
+            ));
+        }
+        let head = output.stdout.trim();
+        Ok(Some(head.to_string()).filter(|head| head != "HEAD"))
+    }
+
+    /// Whether the repository is bare, so it has no working tree to check branches out in
+    fn is_bare(&self) -> Result<bool, Error> {
+        let output = self
+            .output(&["rev-parse", "--is-bare-repository"])
+            .context("git rev-parse failed")?;
+        Ok(output.success && output.stdout.trim() == "true")
+    }
+
+    /// Returns every local branch with the commit it points to, sorted by name
+    fn branch_tips(&self) -> Result<Vec<(String, String)>, Error> {
+        let output = self
+            .output(&[
+                "for-each-ref",
+                "--format=%(refname:short) %(objectname)",
+                "refs/heads",
+            ])
+            .context("git for-each-ref failed")?;
+        if !output.success {
+            return Err(eyre!("git for-each-ref failed: {}", output.stderr));
+        }
+        let tips = output
+            .stdout
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(branch, tip)| (branch.to_string(), tip.to_string()))
+            .collect();
+        Ok(tips)
+    }
+
+    /// Returns the short name of what the symbolic ref `name` points to, e.g. `origin/main` for
+    /// `refs/remotes/origin/HEAD`, or None if it doesn't exist or isn't symbolic
+    fn symbolic_ref(&self, name: &str) -> Result<Option<String>, Error> {
+        let output = self
+            .output(&["symbolic-ref", "--quiet", "--short", name])
+            .context("git symbolic-ref failed")?;
+        Ok(Some(output.stdout.trim().to_string()).filter(|_| output.success))
+    }
+
+    /// Returns how many files changed between `from` and `to`, and how many lines were added and
+    /// deleted
+    fn diff_stat(&self, from: &str, to: &str) -> Result<DiffStat, Error> {
+        let output = self
+            .output(&["diff", "--numstat", from, to])
+            .context("git diff failed")?;
+        if !output.success {
+            return Err(eyre!("failed to compare '{to}' to '{from}'"));
+        }
+        let mut stat = DiffStat::default();
+        for line in output.stdout.lines() {
+            let mut counts = line.split('\t');
+            // Binary files have `-` instead of line counts
+            let additions = counts.next().and_then(|n| n.parse::<usize>().ok());
+            let deletions = counts.next().and_then(|n| n.parse::<usize>().ok());
+            stat.files += 1;
+            stat.additions += additions.unwrap_or(0);
+            stat.deletions += deletions.unwrap_or(0);
+        }
+        Ok(stat)
+    }
+
+    /// Whether `ancestor` is reachable from `descendant`, which includes them being the same
+    /// commit. Revisions that don't exist are no one's ancestors.
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool, Error> {
+        let output = self
+            .output(&["merge-base", "--is-ancestor", ancestor, descendant])
+            .context("git merge-base failed")?;
+        Ok(output.success)
+    }
+}
+
+/// Runs the git executable
+pub struct Subprocess;
+
+impl GitBackend for Subprocess {
+    fn output(&self, args: &[&str]) -> Result<GitOutput, Error> {
+        let start = Instant::now();
+        let output = crate::repo::current()
+            .command("git")
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .context("failed to run git")?;
+        let output = GitOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8(output.stdout).context("git output was not valid utf-8")?,
+            stderr: String::from_utf8(output.stderr).context("git output was not valid utf-8")?,
+        };
+        log_command(
+            "git",
+            args,
+            output.success,
+            start.elapsed(),
+            &output.stdout,
+            &output.stderr,
+        );
+        Ok(output)
+    }
+
+    fn status(&self, args: &[&str]) -> Result<bool, Error> {
+        let start = Instant::now();
+        let status = crate::repo::current()
+            .command("git")
+            .args(args)
+            .status()
+            .context("failed to run git")?;
+        // The output went to the terminal, so there's none to log
+        log_command("git", args, status.success(), start.elapsed(), "", "");
+        Ok(status.success())
+    }
+}
+
+/// A backend that doesn't run anything. Every call is recorded and answered by a function of its
+/// arguments.
+pub struct Mock<F> {
+    respond: F,
+    calls: Mutex<Vec<Vec<String>>>,
+}
+
+impl<F: Fn(&[&str]) -> GitOutput + Send + Sync> Mock<F> {
+    pub fn new(respond: F) -> Self {
+        Self {
+            respond,
+            calls: Mutex::default(),
+        }
+    }
+
+    /// The arguments of every call so far, in order
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl<F: Fn(&[&str]) -> GitOutput + Send + Sync> GitBackend for Mock<F> {
+    fn output(&self, args: &[&str]) -> Result<GitOutput, Error> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(args.iter().map(|arg| arg.to_string()).collect());
+        Ok((self.respond)(args))
+    }
+
+    fn status(&self, args: &[&str]) -> Result<bool, Error> {
+        Ok(self.output(args)?.success)
+    }
+}
+
+thread_local! {
+    static BACKEND: RefCell<Option<Arc<dyn GitBackend>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with every git command of the current thread going through `backend`. Other threads,
+/// like the tests cargo runs in parallel, keep their own backend.
+pub fn with_backend<T>(backend: Arc<dyn GitBackend>, f: impl FnOnce() -> T) -> T {
+    /// Puts the previous backend back, even if `f` panics
+    struct Restore(Option<Arc<dyn GitBackend>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            BACKEND.with(|backend| *backend.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(BACKEND.with(|current| current.replace(Some(backend))));
+    f()
+}
+
+/// Returns the backend installed with [`with_backend`], or the default one: [`Gitoxide`] if the
+/// repository can be opened with it, or [`Subprocess`] otherwise
+pub fn backend() -> Arc<dyn GitBackend> {
+    BACKEND
+        .with(|backend| backend.borrow().clone())
+        .unwrap_or_else(default_backend)
+}
+
+#[cfg(feature = "gix")]
+fn default_backend() -> Arc<dyn GitBackend> {
+    static DEFAULT: std::sync::OnceLock<Arc<dyn GitBackend>> = std::sync::OnceLock::new();
+    DEFAULT
+        .get_or_init(|| match Gitoxide::open() {
+            Ok(gitoxide) => Arc::new(gitoxide),
+            Err(err) => {
+                tracing::debug!("falling back to the git executable: {err:#}");
+                Arc::new(Subprocess)
+            }
+        })
+        .clone()
+}
+
+#[cfg(not(feature = "gix"))]
+fn default_backend() -> Arc<dyn GitBackend> {
+    Arc::new(Subprocess)
+}
+
+/// Runs git with `args` through the current backend, capturing its output
+pub fn output(args: &[&str]) -> Result<GitOutput, Error> {
+    backend().output(args)
+}
+
+/// Runs git with `args` through the current backend, letting it print to the terminal
+pub fn status(args: &[&str]) -> Result<bool, Error> {
+    backend().status(args)
+}
+
+/// Logs a command that was run, with how long it took at the debug level and what it printed at
+/// the trace level, which `-v` and `-vv` show
+pub(crate) fn log_command(
+    program: &str,
+    args: &[&str],
+    success: bool,
+    elapsed: Duration,
+    stdout: &str,
+    stderr: &str,
+) {
+    let result = if success { "ok" } else { "failed" };
+    tracing::debug!("{program} {} ({result}, {elapsed:.2?})", args.join(" "));
+    for (name, output) in [("stdout", stdout), ("stderr", stderr)] {
+        if !output.trim().is_empty() {
+            tracing::trace!("{name}: {}", crate::truncate(output.trim_end(), 500));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_stat_sums_numstat_lines() {
+        let mock = Arc::new(Mock::new(|_: &[&str]| {
+            GitOutput::ok("3\t1\tsrc/lib.rs\n10\t0\tsrc/new.rs\n-\t-\tlogo.png\n")
+        }));
+        let stat = with_backend(mock.clone(), || backend().diff_stat("main", "feature")).unwrap();
+
+        assert_eq!(
+            stat,
+            DiffStat {
+                files: 3,
+                additions: 13,
+                deletions: 1,
+            }
+        );
+        assert_eq!(
+            mock.calls(),
+            vec![vec!["diff", "--numstat", "main", "feature"]]
+        );
+    }
+
+    #[test]
+    fn diff_stat_fails_when_git_fails() {
+        let mock = Arc::new(Mock::new(|_: &[&str]| {
+            GitOutput::failed("fatal: bad revision 'gone'")
+        }));
+        let stat = with_backend(mock, || backend().diff_stat("main", "gone"));
+        assert!(stat.is_err());
+    }
+
+    #[test]
+    fn backend_is_restored_after_with_backend() {
+        let outer = Arc::new(Mock::new(|_: &[&str]| GitOutput::ok("")));
+        let inner = Arc::new(Mock::new(|_: &[&str]| GitOutput::ok("")));
+        with_backend(outer.clone(), || {
+            with_backend(inner.clone(), || output(&["fetch"])).unwrap();
+            output(&["status"]).unwrap();
+        });
+
+        assert_eq!(inner.calls(), vec![vec!["fetch"]]);
+        assert_eq!(outer.calls(), vec![vec!["status"]]);
+    }
+}
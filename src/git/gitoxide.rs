@@ -0,0 +1,100 @@
+use super::{GitBackend, GitOutput, Subprocess};
+use eyre::{eyre, Context, Error};
+use gix::refs::TargetRef;
+use std::path::PathBuf;
+
+/// Answers queries about refs and ancestry by reading the repository with gitoxide, which is a
+/// lot faster than spawning git for each of them. Everything else goes to [`Subprocess`].
+pub struct Gitoxide {
+    repo: gix::ThreadSafeRepository,
+}
+
+impl Gitoxide {
+    /// Opens the repository stackbuddy runs on, which is the one `-C` points to, if given
+    pub fn open() -> Result<Self, Error> {
+        let dir = crate::repo::current()
+            .dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+        let repo = gix::discover(&dir)
+            .with_context(|| format!("failed to open the repository at {}", dir.display()))?;
+        Ok(Self {
+            repo: repo.into_sync(),
+        })
+    }
+}
+
+impl GitBackend for Gitoxide {
+    fn output(&self, args: &[&str]) -> Result<GitOutput, Error> {
+        Subprocess.output(args)
+    }
+
+    fn status(&self, args: &[&str]) -> Result<bool, Error> {
+        Subprocess.status(args)
+    }
+
+    fn head_branch(&self) -> Result<Option<String>, Error> {
+        let head = self
+            .repo
+            .to_thread_local()
+            .head_name()
+            .context("failed to get the current branch")?;
+        Ok(head.map(|name| name.shorten().to_string()))
+    }
+
+    fn is_bare(&self) -> Result<bool, Error> {
+        Ok(self.repo.to_thread_local().is_bare())
+    }
+
+    fn branch_tips(&self) -> Result<Vec<(String, String)>, Error> {
+        let repo = self.repo.to_thread_local();
+        let references = repo.references().context("failed to read the branches")?;
+        let mut tips = Vec::new();
+        for reference in references
+            .local_branches()
+            .context("failed to read the branches")?
+        {
+            let reference = reference.map_err(|err| eyre!("failed to read a branch: {err}"))?;
+            // Like for-each-ref, branches that point to other branches are skipped
+            let Some(tip) = reference
+                .target()
+                .try_id()
+                .map(|id| id.to_hex().to_string())
+            else {
+                continue;
+            };
+            tips.push((reference.name().shorten().to_string(), tip));
+        }
+        tips.sort();
+        Ok(tips)
+    }
+
+    fn symbolic_ref(&self, name: &str) -> Result<Option<String>, Error> {
+        let repo = self.repo.to_thread_local();
+        let reference = repo
+            .try_find_reference(name)
+            .with_context(|| format!("failed to read {name}"))?;
+        let target = reference.and_then(|reference| match reference.target() {
+            TargetRef::Symbolic(target) => Some(target.shorten().to_string()),
+            TargetRef::Object(_) => None,
+        });
+        Ok(target)
+    }
+
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool, Error> {
+        let repo = self.repo.to_thread_local();
+        let (Ok(ancestor), Ok(descendant)) = (
+            repo.rev_parse_single(ancestor),
+            repo.rev_parse_single(descendant),
+        ) else {
+            return Ok(false);
+        };
+        let (ancestor, descendant) = (ancestor.detach(), descendant.detach());
+        if ancestor == descendant {
+            return Ok(true);
+        }
+        // Unrelated histories have no merge base
+        let base = repo.merge_base(ancestor, descendant).ok();
+        Ok(base.is_some_and(|base| base.detach() == ancestor))
+    }
+}
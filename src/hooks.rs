@@ -0,0 +1,280 @@
+//! Hooks in both directions. `stackbuddy hooks install` writes git hooks that call back into
+//! `stackbuddy hook <name>`, which keeps the stack metadata up to date as branches are committed
+//! to, amended and pushed. And the `hooks` of the config are commands that stackbuddy runs before
+//! and after some of its operations, see [`around`].
+
+use crate::{
+    config, current_branch, descendants,
+    forge::Forge,
+    git, guess_parent, main_branch, merge_base,
+    note::{update_notes, update_some_notes, NoteAction, NoteOptions},
+    operation::Operation,
+    repo, rev_parse, set_parent, tracked_parent, whole_stack,
+};
+use eyre::{eyre, Context, Error};
+use std::{
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+/// Marks the hooks written by stackbuddy, so they're the only ones that are replaced or removed
+const MARKER: &str = "# Installed by stackbuddy";
+
+/// The hooks stackbuddy installs. The pre-push one is only about notes, so it's only installed when
+/// asked to
+fn hooks(update_notes: bool) -> Vec<&'static str> {
+    let mut hooks = vec!["post-commit", "post-rewrite"];
+    if update_notes {
+        hooks.push("pre-push");
+    }
+    hooks
+}
+
+/// Returns the directory git runs hooks from, which respects `core.hooksPath`
+fn hooks_dir() -> Result<PathBuf, Error> {
+    // Absolute, since the repository may not be in the current directory
+    let output = git::output(&["rev-parse", "--path-format=absolute", "--git-path", "hooks"])
+        .context("git rev-parse failed")?;
+    if !output.success {
+        return Err(eyre!(
+            "could not find the hooks directory: {}",
+            output.stderr
+        ));
+    }
+    Ok(PathBuf::from(output.stdout.trim()))
+}
+
+fn written_by_stackbuddy(path: &Path) -> bool {
+    std::fs::read_to_string(path).is_ok_and(|contents| contents.contains(MARKER))
+}
+
+/// Writes the post-commit and post-rewrite hooks, and with `update_notes` the pre-push hook.
+/// Hooks that weren't written by stackbuddy are left alone unless `force` is set. Failures in the
+/// hooks never stop git. Returns the paths of the hooks that were written.
+pub fn install(update_notes: bool, force: bool) -> Result<Vec<PathBuf>, Error> {
+    let dir = hooks_dir()?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let mut written = Vec::new();
+    for name in hooks(update_notes) {
+        let path = dir.join(name);
+        if path.exists() && !force && !written_by_stackbuddy(&path) {
+            return Err(eyre!(
+                "{} already exists, pass --force to replace it",
+                path.display()
+            ));
+        }
+        let script = format!(
+            "#!/bin/sh\n{MARKER}, remove with `stackbuddy hooks uninstall`\nstackbuddy hook {name} \"$@\" || true\n"
+        );
+        std::fs::write(&path, script)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        make_executable(&path)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("failed to make {} executable", path.display()))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Removes the hooks written by stackbuddy, and returns their paths
+pub fn uninstall() -> Result<Vec<PathBuf>, Error> {
+    let dir = hooks_dir()?;
+    let mut removed = Vec::new();
+    for name in hooks(true) {
+        let path = dir.join(name);
+        if written_by_stackbuddy(&path) {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Runs the hook `name`, called by the scripts [`install`] writes with the arguments git passed
+/// them
+pub fn run(forge: &dyn Forge, name: &str) -> Result<(), Error> {
+    // stackbuddy's own rebases and amends rewrite commits too, and take care of the stack
+    if Operation::in_progress()? {
+        return Ok(());
+    }
+    match name {
+        "post-commit" => post_commit(),
+        "post-rewrite" => post_rewrite(),
+        "pre-push" => pre_push(forge),
+        _ => Err(eyre!("unknown hook '{name}'")),
+    }
+}
+
+/// Records the parent of a branch that doesn't have one yet, the first time it's committed to
+fn post_commit() -> Result<(), Error> {
+    let branch = current_branch()?;
+    let config = config::load()?;
+    if branch == main_branch()? || config.is_excluded(&branch) || tracked_parent(&branch)?.is_some()
+    {
+        return Ok(());
+    }
+    let strategy = config.parent_strategy.unwrap_or_default();
+    if let Some(parent) = guess_parent(&branch, strategy)?.parent {
+        set_parent(&branch, &parent)?;
+        info!("stackbuddy: recorded {parent} as the parent of {branch}");
+    }
+    Ok(())
+}
+
+/// Warns when the branches above the current one are no longer on top of it, e.g. after
+/// `git commit --amend`
+fn post_rewrite() -> Result<(), Error> {
+    let branch = current_branch()?;
+    let tip = rev_parse(&branch)?;
+    let mut behind = Vec::new();
+    for descendant in descendants(&branch)? {
+        if merge_base(&descendant, &branch)? != tip {
+            behind.push(descendant);
+        }
+    }
+    if !behind.is_empty() {
+        let verb = if behind.len() == 1 { "is" } else { "are" };
+        eprintln!(
+            "stackbuddy: {} {verb} no longer on top of {branch}, run `stackbuddy restack` to move them",
+            behind.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Lists the PRs with stale notes in the stacks of the branches being pushed, which git writes to
+/// stdin as `<local ref> <local sha> <remote ref> <remote sha>` lines, and updates them when
+/// `push_notes` is set in the config
+fn pre_push(forge: &dyn Forge) -> Result<(), Error> {
+    let main = main_branch()?;
+    let mut stacks: Vec<Vec<String>> = Vec::new();
+    for line in std::io::stdin().lock().lines() {
+        let line = line.context("failed to read stdin")?;
+        let Some(branch) = line
+            .split_whitespace()
+            .next()
+            .and_then(|local| local.strip_prefix("refs/heads/"))
+        else {
+            continue;
+        };
+        if branch == main || stacks.iter().any(|stack| stack.iter().any(|b| b == branch)) {
+            continue;
+        }
+        let stack = whole_stack(branch)?;
+        // Branches outside of any stack have no notes
+        if stack.len() > 1 || tracked_parent(branch)?.is_some() {
+            stacks.push(stack);
+        }
+    }
+
+    // Like update-notes, PR bodies aren't edited behind the user's back: the plan is always
+    // printed, and the PRs are only edited when the config opts in
+    let config = config::load()?;
+    let options = NoteOptions::new(config.note_format())?;
+    for stack in stacks {
+        let plan = update_notes(forge, &stack, &options, true, 4, false);
+        let mut stale = Vec::new();
+        for (branch, result) in stack.iter().zip(plan) {
+            match result {
+                Ok(update) if update.action == NoteAction::Unchanged => {}
+                Ok(_) => stale.push(branch.clone()),
+                Err(e) => eprintln!("stackbuddy: error in branch {branch}: {e}"),
+            }
+        }
+        if stale.is_empty() {
+            continue;
+        }
+        if !config.push_notes {
+            info!(
+                "stackbuddy: the notes of {} are stale. Run `stackbuddy update-notes`, or `git \
+                 config stackbuddy.pushNotes true` to update them on push",
+                stale.join(", ")
+            );
+            continue;
+        }
+        info!("stackbuddy: updating the notes of {}...", stale.join(", "));
+        let results = update_some_notes(forge, &stack, &stale, &options, false, 4, false);
+        for (branch, result) in stale.iter().zip(results) {
+            if let Err(e) = result {
+                eprintln!("stackbuddy: error in branch {branch}: {e}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs `operation` on the stack that contains `branch` between the `pre-<name>` and
+/// `post-<name>` commands of the `hooks` in the config, if there are any. A pre hook that fails
+/// stops the operation, which is how hooks enforce policies. The commands run with `sh -c` in the
+/// repository, and are told about the operation in environment variables:
+///
+/// - `STACKBUDDY_HOOK`: the hook that runs, like `pre-land`
+/// - `STACKBUDDY_OPERATION`: `name`, like `land`
+/// - `STACKBUDDY_BRANCH`: `branch`
+/// - `STACKBUDDY_STACK`: the branches of the stack from the bottom up, separated by spaces
+/// - `STACKBUDDY_MAIN`: the main branch
+/// - `STACKBUDDY_RESULT`: in post hooks, `success` or `failure`
+///
+/// The post hook runs even if the operation fails, and the error of the operation wins over the
+/// one of the hook.
+pub fn around<T>(
+    name: &str,
+    branch: &str,
+    operation: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let hooks = &config::load()?.hooks;
+    let (pre, post) = (format!("pre-{name}"), format!("post-{name}"));
+    if !hooks.contains_key(&pre) && !hooks.contains_key(&post) {
+        return operation();
+    }
+
+    // Taken before, since the operation may delete branches, like land does
+    let mut stack = whole_stack(branch)?;
+    stack.reverse();
+    let mut env = vec![
+        ("STACKBUDDY_OPERATION", name.to_string()),
+        ("STACKBUDDY_BRANCH", branch.to_string()),
+        ("STACKBUDDY_STACK", stack.join(" ")),
+        ("STACKBUDDY_MAIN", main_branch()?),
+    ];
+    if let Some(command) = hooks.get(&pre) {
+        run_hook(&pre, command, &env)?;
+    }
+    let result = operation();
+    if let Some(command) = hooks.get(&post) {
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        env.push(("STACKBUDDY_RESULT", outcome.to_string()));
+        let hooked = run_hook(&post, command, &env);
+        if result.is_ok() {
+            hooked?;
+        }
+    }
+    result
+}
+
+fn run_hook(hook: &str, command: &str, env: &[(&str, String)]) -> Result<(), Error> {
+    tracing::debug!("running the {hook} hook: {command}");
+    let status = repo::current()
+        .command("sh")
+        .args(["-c", command])
+        .env("STACKBUDDY_HOOK", hook)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .status()
+        .with_context(|| format!("failed to run the {hook} hook"))?;
+    if !status.success() {
+        return Err(eyre!("the {hook} hook failed: {command}"));
+    }
+    Ok(())
+}
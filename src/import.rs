@@ -0,0 +1,140 @@
+//! Brings stacks tracked by other tools, or exported by `stackbuddy export`, into stackbuddy, by
+//! recording the parents they know of like `stackbuddy track` does.
+
+use crate::{
+    config, forge::Forge, git, local_branches, main_branch, rev_parse, set_parent, whole_stack,
+};
+use eyre::{eyre, Context, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+/// A stack as written by `stackbuddy export`, with enough to track it again in another clone
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StackFile {
+    /// The branch the stack is based on
+    pub base: String,
+    /// From the bottom of the stack to the top
+    pub branches: Vec<StackFileBranch>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StackFileBranch {
+    pub branch: String,
+    pub parent: String,
+    /// The commit the branch pointed to when it was exported
+    pub head: String,
+    /// The number of the PR of the branch, if it has one
+    #[serde(default)]
+    pub pr: Option<String>,
+}
+
+/// Returns the stack that contains `branch`, with the parents and PRs of its branches
+pub fn export(forge: &dyn Forge, branch: &str) -> Result<StackFile, Error> {
+    let base = main_branch()?;
+    let stack = whole_stack(branch)?;
+    let mut branches = Vec::new();
+    for (i, branch) in stack.iter().enumerate().rev() {
+        branches.push(StackFileBranch {
+            branch: branch.clone(),
+            parent: stack.get(i + 1).unwrap_or(&base).clone(),
+            head: rev_parse(branch)?,
+            pr: forge.pr_for_branch(branch)?.map(|pr| pr.number),
+        });
+    }
+    Ok(StackFile { base, branches })
+}
+
+/// Records the parents of the stack exported to `path`, or read from stdin if `path` is `-`.
+/// Branches that only exist on the remote are created from it first, and branches that are on
+/// neither are skipped. Returns every branch with its parent, from the bottom up. With `dry_run`,
+/// nothing is created or recorded.
+pub fn from_file(path: &Path, dry_run: bool) -> Result<Vec<(String, String)>, Error> {
+    let contents = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).context("failed to read stdin")?
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?
+    };
+    let file: StackFile = serde_json::from_str(&contents)
+        .with_context(|| format!("{} is not a stack exported by stackbuddy", path.display()))?;
+
+    let remote = config::load()?.remote().to_string();
+    let existing = local_branches()?;
+    let mut links = Vec::new();
+    for entry in file.branches {
+        if !existing.contains(&entry.branch) {
+            let tracking = format!("{remote}/{}", entry.branch);
+            if rev_parse(&tracking).is_err() {
+                info!("Skipping {}, which isn't here or on {remote}", entry.branch);
+                continue;
+            }
+            info!("Creating {} from {tracking}...", entry.branch);
+            if !dry_run {
+                let success =
+                    git::status(&["branch", "--quiet", "--track", &entry.branch, &tracking])
+                        .context("git branch failed")?;
+                if !success {
+                    return Err(eyre!("failed to create '{}'", entry.branch));
+                }
+            }
+        }
+        if !dry_run {
+            set_parent(&entry.branch, &entry.parent)?;
+        }
+        links.push((entry.branch, entry.parent));
+    }
+    Ok(links)
+}
+
+/// Records the parents Graphite keeps for the local branches. Graphite stores a JSON blob for
+/// every branch it tracks under `refs/branch-metadata/<branch>`, with the name of its parent in
+/// `parentBranchName`. Returns every branch with its parent, sorted by branch. With `dry_run`,
+/// nothing is recorded.
+pub fn graphite(dry_run: bool) -> Result<Vec<(String, String)>, Error> {
+    let output = git::output(&[
+        "for-each-ref",
+        "--format=%(refname)",
+        "refs/branch-metadata/",
+    ])
+    .context("git for-each-ref failed")?;
+    if !output.success {
+        return Err(eyre!("git for-each-ref failed: {}", output.stderr));
+    }
+    let refs: Vec<&str> = output.stdout.lines().collect();
+    if refs.is_empty() {
+        return Err(eyre!(
+            "there's no Graphite metadata in this repository, it's kept in refs/branch-metadata/"
+        ));
+    }
+
+    let existing = local_branches()?;
+    let mut links = Vec::new();
+    for reference in refs {
+        let Some(branch) = reference.strip_prefix("refs/branch-metadata/") else {
+            continue;
+        };
+        // Graphite keeps the metadata of branches that were deleted since
+        if !existing.iter().any(|b| b == branch) {
+            continue;
+        }
+        let blob = git::output(&["cat-file", "blob", reference]).context("git cat-file failed")?;
+        if !blob.success {
+            return Err(eyre!("failed to read {reference}: {}", blob.stderr));
+        }
+        let metadata: Value = serde_json::from_str(&blob.stdout)
+            .with_context(|| format!("the Graphite metadata of '{branch}' is not json"))?;
+        let Some(parent) = metadata["parentBranchName"].as_str() else {
+            continue;
+        };
+        links.push((branch.to_string(), parent.to_string()));
+    }
+    links.sort();
+
+    if !dry_run {
+        for (branch, parent) in &links {
+            set_parent(branch, parent)?;
+        }
+    }
+    Ok(links)
+}
@@ -0,0 +1,255 @@
+//! Every command leaves an entry in `.git/stackbuddy/journal/` with the branches and PR
+//! descriptions it changed, as they were before and after, so that `stackbuddy undo` can put them
+//! back. Commands that change nothing leave no entry.
+
+use crate::{
+    checkout, config, current_branch, forge::Forge, git, git_dir, main_branch, push::force_push,
+};
+use eyre::{eyre, Context, Error};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many entries are kept. Older ones are removed as new ones are written.
+const KEEP: usize = 100;
+
+/// A branch that a command created, moved or deleted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TipChange {
+    pub branch: String,
+    /// None if the branch was created
+    pub before: Option<String>,
+    /// None if the branch was deleted
+    pub after: Option<String>,
+}
+
+/// A PR description that a command edited
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BodyChange {
+    pub branch: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// What a single command changed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    /// The command line, e.g. `sync my-branch`
+    pub command: String,
+    pub tips: Vec<TipChange>,
+    pub bodies: Vec<BodyChange>,
+}
+
+/// Records what the running command changes. The tips of the branches are compared with the ones
+/// from when it started, and PR descriptions are recorded by [`Journaled`] as they're edited.
+///
+/// [`Journaled`]: crate::forge::Journaled
+pub struct Journal {
+    command: String,
+    tips: HashMap<String, String>,
+    bodies: Mutex<Vec<BodyChange>>,
+}
+
+impl Journal {
+    pub fn start(command: String) -> Result<Self, Error> {
+        Ok(Self {
+            command,
+            tips: branch_tips()?,
+            bodies: Mutex::default(),
+        })
+    }
+
+    pub fn record_body(&self, branch: &str, before: String, after: &str) {
+        if before != after {
+            self.bodies.lock().unwrap().push(BodyChange {
+                branch: branch.to_string(),
+                before,
+                after: after.to_string(),
+            });
+        }
+    }
+
+    /// Writes the entry of the command, if it changed anything. This should be called whether the
+    /// command succeeded or not, since a failed one may have changed things too.
+    pub fn finish(&self) -> Result<(), Error> {
+        let now = branch_tips()?;
+        let mut tips: Vec<TipChange> = self
+            .tips
+            .iter()
+            .filter(|(branch, tip)| now.get(*branch) != Some(tip))
+            .map(|(branch, tip)| TipChange {
+                branch: branch.clone(),
+                before: Some(tip.clone()),
+                after: now.get(branch).cloned(),
+            })
+            .collect();
+        tips.extend(
+            now.iter()
+                .filter(|(branch, _)| !self.tips.contains_key(*branch))
+                .map(|(branch, tip)| TipChange {
+                    branch: branch.clone(),
+                    before: None,
+                    after: Some(tip.clone()),
+                }),
+        );
+        tips.sort_by(|a, b| a.branch.cmp(&b.branch));
+
+        // Only the first and last versions of a body matter
+        let mut bodies: Vec<BodyChange> = Vec::new();
+        for change in self.bodies.lock().unwrap().drain(..) {
+            match bodies.iter_mut().find(|c| c.branch == change.branch) {
+                Some(existing) => existing.after = change.after,
+                None => bodies.push(change),
+            }
+        }
+        bodies.retain(|c| c.before != c.after);
+
+        if tips.is_empty() && bodies.is_empty() {
+            return Ok(());
+        }
+        let entry = Entry {
+            command: self.command.clone(),
+            tips,
+            bodies,
+        };
+        save(&entry)
+    }
+}
+
+/// Puts back the branches and PR descriptions changed by the last command in the journal, and
+/// removes its entry. Branches that are on the remote are force-pushed back too.
+///
+/// Fails if any of them changed again since, unless `force` is set. Merged, closed and newly
+/// opened PRs stay as they are.
+pub fn undo(forge: &dyn Forge, force: bool) -> Result<Entry, Error> {
+    let (path, entry) = last()?.ok_or_else(|| eyre!("there's nothing to undo"))?;
+
+    let now = branch_tips()?;
+    let mut changed: Vec<&str> = entry
+        .tips
+        .iter()
+        .filter(|change| now.get(&change.branch) != change.after.as_ref())
+        .map(|change| change.branch.as_str())
+        .collect();
+    for change in &entry.bodies {
+        if forge.pr_body(&change.branch)? != change.after {
+            changed.push(&change.branch);
+        }
+    }
+    if !changed.is_empty() && !force {
+        return Err(eyre!(
+            "{} changed since `stackbuddy {}`, pass --force to undo it anyway",
+            changed.join(", "),
+            entry.command
+        ));
+    }
+
+    // Branches can't be moved while they're checked out
+    let original = current_branch()?;
+    let success =
+        git::status(&["checkout", "--quiet", "--detach"]).context("git checkout failed")?;
+    if !success {
+        return Err(eyre!("failed to detach HEAD"));
+    }
+    let remote = config::load()?.push_remote();
+    let mut to_push = Vec::new();
+    for change in &entry.tips {
+        let reference = format!("refs/heads/{}", change.branch);
+        let (args, action) = match &change.before {
+            Some(tip) => (vec!["update-ref", &reference, tip], "move back"),
+            None => (vec!["update-ref", "-d", &reference], "delete"),
+        };
+        let success = git::status(&args).context("git update-ref failed")?;
+        if !success {
+            return Err(eyre!("failed to {action} '{}'", change.branch));
+        }
+
+        // Only what the command itself pushed is pushed back
+        let tracking = format!("refs/remotes/{remote}/{}", change.branch);
+        let remote_tip = git::output(&["rev-parse", "--verify", "--quiet", &tracking])
+            .context("git rev-parse failed")?;
+        if change.before.is_some()
+            && remote_tip.success
+            && Some(remote_tip.stdout.trim()) == change.after.as_deref()
+        {
+            to_push.push(change.branch.clone());
+        }
+    }
+    if branch_tips()?.contains_key(&original) {
+        checkout(&original)?;
+    } else {
+        checkout(&main_branch()?)?;
+    }
+
+    if !to_push.is_empty() {
+        info!("Pushing {}...", to_push.join(", "));
+        force_push(&to_push)?;
+    }
+    for change in &entry.bodies {
+        info!(
+            "Restoring the description of the PR of {}...",
+            change.branch
+        );
+        forge.set_pr_body(&change.branch, &change.before)?;
+    }
+
+    std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+    Ok(entry)
+}
+
+/// Returns the tip of every local branch
+fn branch_tips() -> Result<HashMap<String, String>, Error> {
+    Ok(git::backend().branch_tips()?.into_iter().collect())
+}
+
+fn dir() -> Result<PathBuf, Error> {
+    Ok(git_dir()?.join("stackbuddy").join("journal"))
+}
+
+/// Returns the paths of the entries, from the oldest to the newest
+fn entries() -> Result<Vec<PathBuf>, Error> {
+    let dir = dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    // The names are zero-padded timestamps, so they sort in the order they were written
+    paths.sort();
+    Ok(paths)
+}
+
+fn last() -> Result<Option<(PathBuf, Entry)>, Error> {
+    let Some(path) = entries()?.pop() else {
+        return Ok(None);
+    };
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let entry =
+        serde_json::from_str(&contents).with_context(|| format!("invalid {}", path.display()))?;
+    Ok(Some((path, entry)))
+}
+
+fn save(entry: &Entry) -> Result<(), Error> {
+    let dir = dir()?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    let path = dir.join(format!("{millis:016}.json"));
+    std::fs::write(&path, serde_json::to_string_pretty(entry)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    let paths = entries()?;
+    for old in &paths[..paths.len().saturating_sub(KEEP)] {
+        std::fs::remove_file(old).with_context(|| format!("failed to remove {}", old.display()))?;
+    }
+    Ok(())
+}
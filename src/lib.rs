@@ -1,283 +1,3087 @@
 use clap::ValueEnum;
+use config::Config;
 use eyre::{eyre, Context, Error, OptionExt};
+use forge::{Forge, MergeStrategy, PrState, PrStatus};
+use git::DiffStat;
+use operation::{Operation, Then};
+use progress::Progress;
+use push::{
+    delete_remote_branch, force_push, push_branch, remote_branch_exists, remote_state, RemoteState,
+};
+use rebase::{rebase_stack, stack_steps, RebaseStep};
+use serde::{Deserialize, Serialize};
 use std::{
-    io::Write,
-    process::{Command, Stdio},
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Prints what's going on to stderr, unless `--quiet` was passed, leaving stdout for the output
+/// of commands that scripts read
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub mod absorb;
+pub mod config;
+pub mod diff;
+pub mod doctor;
+pub mod forge;
+pub mod git;
+pub mod hooks;
+pub mod import;
+pub mod journal;
+pub mod note;
+pub mod operation;
+pub mod output;
+pub mod plugin;
+pub mod porcelain;
+pub mod progress;
+pub mod push;
+pub mod rebase;
+pub mod repo;
+pub mod template;
+pub mod ui;
+
+pub use note::{
+    note_block, remove_note, update_note, update_notes, update_some_notes, NoteFormat, NoteOptions,
+    UpdateReport,
 };
 
-pub fn current_stack() -> Vec<String> {
-    StackIter::new().collect()
+/// A stack of branches, as printed by `stackbuddy stack --json`
+#[derive(Serialize, Debug, Clone)]
+pub struct Stack {
+    /// The branch the stack is based on
+    pub base: String,
+    /// From the top of the stack to the bottom
+    pub branches: Vec<String>,
+    /// The name given with `stackbuddy stack --name`, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Stack {
+    /// Returns the stack that ends in `branch`
+    pub fn from_branch(branch: String) -> Result<Self, Error> {
+        let branches = stack_from(branch)?;
+        Ok(Stack {
+            base: main_branch()?,
+            name: branches
+                .last()
+                .map(|b| stack_name(b))
+                .transpose()?
+                .flatten(),
+            branches,
+        })
+    }
+
+    /// Returns the stack that ends in the current branch
+    pub fn current() -> Result<Self, Error> {
+        Self::from_branch(current_branch()?)
+    }
+
+    /// Returns the stack that ends in `branch`, found with `discovery`. When following PRs, the
+    /// base is the one of the bottom PR.
+    pub fn discover(
+        forge: &dyn Forge,
+        branch: String,
+        discovery: Discovery,
+    ) -> Result<Self, Error> {
+        let branches = discover_stack(forge, branch, discovery)?;
+        let base = match (discovery, branches.last()) {
+            (Discovery::Prs, Some(bottom)) => forge
+                .open_prs()?
+                .get(bottom)
+                .map(|pr| pr.base.clone())
+                .ok_or_eyre("the bottom of the stack has no open PR")?,
+            _ => main_branch()?,
+        };
+        let name = branches
+            .last()
+            .map(|b| stack_name(b))
+            .transpose()?
+            .flatten();
+        Ok(Stack {
+            base,
+            branches,
+            name,
+        })
+    }
+}
+
+/// A stack of the repository, as listed by `stackbuddy stacks`
+#[derive(Serialize, Debug, Clone)]
+pub struct StackSummary {
+    pub name: Option<String>,
+    /// The branch right above the main branch
+    pub bottom: String,
+    /// The branches at the top. There's more than one when the stack forks.
+    pub tips: Vec<String>,
+    /// Every branch of the stack with its PR, parents before their children
+    pub branches: Vec<StackEntry>,
+}
+
+impl StackSummary {
+    /// Returns every stack in the repository, one for each branch right above the main branch
+    pub fn all(forge: &dyn Forge) -> Result<Vec<Self>, Error> {
+        let main = main_branch()?;
+        let mut stacks = Vec::new();
+        for bottom in children(&main)? {
+            let tree = StackNode::from_branch(bottom.clone())?;
+            let branches: Vec<String> = tree
+                .edges(&main)
+                .into_iter()
+                .map(|(branch, _)| branch.to_string())
+                .collect();
+            stacks.push(StackSummary {
+                name: stack_name(&bottom)?,
+                tips: tree.tips(),
+                branches: StackEntry::of_stack(forge, &branches)?,
+                bottom,
+            });
+        }
+        Ok(stacks)
+    }
+
+    /// One line with the name of the stack (or its bottom branch), its tips, how many branches it
+    /// has, and how many of their PRs are in each state
+    pub fn line(&self) -> String {
+        let count = |state: Option<PrState>| {
+            self.branches
+                .iter()
+                .filter(|entry| entry.state == state)
+                .count()
+        };
+        let mut prs = Vec::new();
+        for (state, label) in [
+            (Some(PrState::Open), "open"),
+            (Some(PrState::Merged), "merged"),
+            (Some(PrState::Closed), "closed"),
+            (None, "without PR"),
+        ] {
+            let prs_in_state = match count(state) {
+                0 => continue,
+                n => format!("{n} {label}"),
+            };
+            prs.push(match state {
+                Some(state) => output::pr_state(&prs_in_state, state),
+                None => output::dim(&prs_in_state),
+            });
+        }
+        let current = current_branch().ok();
+        let tips: Vec<String> = self
+            .tips
+            .iter()
+            .map(|tip| output::branch(tip, current.as_deref()))
+            .collect();
+        format!(
+            "{}  {}  {} branches  {}",
+            self.name.as_deref().unwrap_or(&self.bottom),
+            tips.join(", "),
+            self.branches.len(),
+            prs.join(", ")
+        )
+    }
+}
+
+fn stack_name_key(bottom: &str) -> String {
+    format!("branch.{bottom}.stackbuddy-name")
+}
+
+/// Returns the name of the stack whose bottom branch is `bottom`, if it was given one
+pub fn stack_name(bottom: &str) -> Result<Option<String>, Error> {
+    let output =
+        git::output(&["config", "--get", &stack_name_key(bottom)]).context("git config failed")?;
+    // git config exits with 1 when the key is not set
+    Ok(Some(output.stdout.trim().to_string()).filter(|_| output.success))
+}
+
+/// Names the stack that contains `branch`. The name is recorded on its bottom branch, which stays
+/// the same as branches are added on top. Fails if another stack already has the name.
+pub fn set_stack_name(branch: &str, name: &str) -> Result<(), Error> {
+    let bottom = bottom_of(branch)?;
+    let output = git::output(&["config", "--get-regexp", r"^branch\..*\.stackbuddy-name$"])
+        .context("git config failed")?;
+    for line in output.stdout.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+        if value == name && key != stack_name_key(&bottom) {
+            return Err(eyre!("another stack is already called '{name}'"));
+        }
+    }
+
+    let success =
+        git::status(&["config", &stack_name_key(&bottom), name]).context("git config failed")?;
+    if !success {
+        return Err(eyre!("failed to name the stack of '{bottom}'"));
+    }
+    Ok(())
+}
+
+/// A branch of the stack and its PR, as printed by `stackbuddy prs`
+#[derive(Serialize, Debug, Clone)]
+pub struct StackEntry {
+    pub branch: String,
+    /// The PR fields are missing for branches that weren't submitted yet
+    pub number: Option<String>,
+    /// Only known for open PRs
+    pub title: Option<String>,
+    pub state: Option<PrState>,
+    pub url: Option<String>,
+}
+
+impl StackEntry {
+    /// Returns the entries of the branches in `stack`, which goes from the top to the bottom, in
+    /// the same order
+    pub fn of_stack(forge: &dyn Forge, stack: &[String]) -> Result<Vec<Self>, Error> {
+        let open = forge.open_prs()?;
+        let mut entries = Vec::new();
+        for branch in stack {
+            let pr = forge.pr_for_branch(branch)?;
+            entries.push(StackEntry {
+                branch: branch.clone(),
+                number: pr.as_ref().map(|pr| pr.number.clone()),
+                title: open.get(branch).map(|pr| pr.title.clone()),
+                state: pr.as_ref().map(|pr| pr.state),
+                url: pr.map(|pr| pr.url),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// One line with every field, separated by tabs. Missing fields are shown as `-`.
+    pub fn line(&self, forge: &dyn Forge) -> String {
+        let fields = [
+            self.branch.clone(),
+            self.number
+                .as_ref()
+                .map_or("-".to_string(), |number| forge.pr_reference(number)),
+            self.title.clone().unwrap_or("-".to_string()),
+            self.state.map_or("-".to_string(), |state| {
+                output::pr_state(&state.to_string(), state)
+            }),
+            self.url.clone().unwrap_or("-".to_string()),
+        ];
+        fields.join("\t")
+    }
+}
+
+/// A branch and every branch above it. Several branches can have the same parent, so a stack can
+/// fork into a tree, as printed by `stackbuddy stack --tree`.
+#[derive(Serialize, Debug, Clone)]
+pub struct StackNode {
+    pub branch: String,
+    pub children: Vec<StackNode>,
+}
+
+impl StackNode {
+    /// Returns the tree of branches above `branch`
+    pub fn from_branch(branch: String) -> Result<Self, Error> {
+        Self::walk(branch, &mut Vec::new(), config::load()?.max_stack_depth())
+    }
+
+    /// Builds the tree above `branch`, which was reached through the branches in `path`
+    fn walk(branch: String, path: &mut Vec<String>, max_depth: usize) -> Result<Self, Error> {
+        check_walk(path, &branch, max_depth)?;
+        path.push(branch.clone());
+        let children = children(&branch)?
+            .into_iter()
+            .map(|child| Self::walk(child, path, max_depth))
+            .collect::<Result<_, _>>()?;
+        path.pop();
+        Ok(StackNode { branch, children })
+    }
+
+    /// Returns the tree of the stack that contains `branch`, starting from its bottom branch
+    pub fn of_stack(branch: &str) -> Result<Self, Error> {
+        Self::from_branch(bottom_of(branch)?)
+    }
+
+    /// Draws the tree with box-drawing characters, one branch per line, under `base`, with the
+    /// current branch highlighted
+    pub fn render(&self, base: &str) -> String {
+        let current = current_branch().ok();
+        self.render_with(base, &|branch| output::branch(branch, current.as_deref()))
+    }
+
+    /// Like [`render`](Self::render), but each branch is shown as `label` returns
+    pub fn render_with(&self, base: &str, label: &dyn Fn(&str) -> String) -> String {
+        let mut lines = vec![base.to_string()];
+        self.render_into(&mut lines, label, "", true);
+        lines.join("\n")
+    }
+
+    fn render_into(
+        &self,
+        lines: &mut Vec<String>,
+        label: &dyn Fn(&str) -> String,
+        indent: &str,
+        last: bool,
+    ) {
+        let (branch, below) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        lines.push(format!("{indent}{branch}{}", label(&self.branch)));
+        let indent = format!("{indent}{below}");
+        for (i, child) in self.children.iter().enumerate() {
+            child.render_into(lines, label, &indent, i + 1 == self.children.len());
+        }
+    }
+
+    /// Returns the branches of the tree that have no children
+    pub fn tips(&self) -> Vec<String> {
+        if self.children.is_empty() {
+            return vec![self.branch.clone()];
+        }
+        self.children.iter().flat_map(StackNode::tips).collect()
+    }
+
+    /// Returns every branch of the tree with its parent, parents first. The parent of the root is
+    /// `base`.
+    pub fn edges<'a>(&'a self, base: &'a str) -> Vec<(&'a str, &'a str)> {
+        let mut edges = vec![(self.branch.as_str(), base)];
+        for child in &self.children {
+            edges.extend(child.edges(&self.branch));
+        }
+        edges
+    }
+}
+
+/// Draws the tree of the stack that contains `branch`, with how far each branch is ahead of and
+/// behind its parent, the state of its PR, and a `*` on the current branch
+pub fn graph(forge: &dyn Forge, branch: &str) -> Result<String, Error> {
+    let main = main_branch()?;
+    let current = current_branch().ok();
+    let tree = StackNode::of_stack(branch)?;
+    let stale = forge.staleness();
+
+    let mut labels = HashMap::new();
+    for (b, parent) in tree.edges(&main) {
+        let mut label = if current.as_deref() == Some(b) {
+            output::current(&format!("* {b}"))
+        } else {
+            b.to_string()
+        };
+        let (ahead, behind) = ahead_behind(b, parent)?;
+        label.push_str(&format!(" ({ahead} ahead"));
+        if behind > 0 {
+            let behind = format!("{behind} behind {parent}");
+            label.push_str(&format!(", {}", output::warning(&behind)));
+        }
+        label.push(')');
+        if let Some(pr) = forge.pr_for_branch(b)? {
+            let mut pr_label = format!("{} {}", forge.pr_reference(&pr.number), pr.state);
+            if stale.is_some() {
+                pr_label.push('*');
+            }
+            label.push_str(&format!(" {}", output::pr_state(&pr_label, pr.state)));
+        }
+        labels.insert(b.to_string(), label);
+    }
+    let mut graph = tree.render_with(&main, &|b| labels[b].clone());
+    if let Some(age) = stale {
+        graph.push_str(&stale_footer(age));
+    }
+    Ok(graph)
+}
+
+/// Returns how many commits `branch` has that `parent` doesn't, and the other way around
+pub fn ahead_behind(branch: &str, parent: &str) -> Result<(usize, usize), Error> {
+    let range = format!("{parent}...{branch}");
+    let output = git::output(&["rev-list", "--left-right", "--count", &range])
+        .context("git rev-list failed")?;
+
+    if !output.success {
+        return Err(eyre!("failed to compare '{branch}' to '{parent}'"));
+    }
+    let counts: Vec<usize> = output
+        .stdout
+        .split_whitespace()
+        .filter_map(|count| count.parse().ok())
+        .collect();
+    match counts[..] {
+        [behind, ahead] => Ok((ahead, behind)),
+        _ => Err(eyre!(
+            "unexpected output from git rev-list: {}",
+            output.stdout
+        )),
+    }
+}
+
+/// How big a branch of the stack is, as shown by `stack --stat` and `status --stat`
+#[derive(Serialize, Debug, Clone)]
+pub struct BranchStats {
+    pub branch: String,
+    pub parent: String,
+    /// Commits the branch has that its parent doesn't
+    pub ahead: usize,
+    /// Commits on the remote branch that the local one doesn't have, or None if it wasn't pushed
+    pub behind_remote: Option<usize>,
+    /// The changes of the branch relative to where it forked from its parent
+    #[serde(flatten)]
+    pub diff: DiffStat,
+}
+
+impl BranchStats {
+    /// Returns the stats of every branch in `stack`, which goes from the top to the bottom and
+    /// sits on the main branch
+    pub fn of_stack(stack: &[String]) -> Result<Vec<Self>, Error> {
+        let main = main_branch()?;
+        let remote = config::load()?.push_remote().to_string();
+        let mut stats = Vec::new();
+        for (i, branch) in stack.iter().enumerate() {
+            let parent = stack.get(i + 1).unwrap_or(&main);
+            let (ahead, _) = ahead_behind(branch, parent)?;
+            let remote_branch = format!("{remote}/{branch}");
+            let behind_remote = match rev_parse(&remote_branch) {
+                Ok(_) => Some(ahead_behind(branch, &remote_branch)?.1),
+                Err(_) => None,
+            };
+            let fork = merge_base(parent, branch)?;
+            stats.push(BranchStats {
+                branch: branch.clone(),
+                parent: parent.clone(),
+                ahead,
+                behind_remote,
+                diff: git::backend().diff_stat(&fork, branch)?,
+            });
+        }
+        Ok(stats)
+    }
+
+    /// The stats as table cells: commits ahead of the parent, behind the remote, files changed,
+    /// and lines added and deleted
+    fn cells(&self) -> [String; 4] {
+        [
+            self.ahead.to_string(),
+            self.behind_remote
+                .map_or("-".to_string(), |n| n.to_string()),
+            self.diff.files.to_string(),
+            format!(
+                "{} {}",
+                output::added(&format!("+{}", self.diff.additions)),
+                output::deleted(&format!("-{}", self.diff.deletions))
+            ),
+        ]
+    }
+}
+
+const STATS_HEADER: [&str; 4] = ["Ahead", "Behind remote", "Files", "Lines"];
+
+/// Renders the stats of the branches of a stack as a table, one row per branch
+pub fn stats_table(stats: &[BranchStats]) -> String {
+    let mut rows = vec![std::iter::once("Branch")
+        .chain(STATS_HEADER)
+        .map(str::to_string)
+        .collect::<Vec<_>>()];
+    for stat in stats {
+        rows.push(
+            std::iter::once(stat.branch.clone())
+                .chain(stat.cells())
+                .collect(),
+        );
+    }
+    render_table(&rows)
+}
+
+/// Returns the stack that ends in the current branch, from the top to the bottom
+pub fn current_stack() -> Result<Vec<String>, Error> {
+    StackIter::new()?.collect()
 }
 
-pub fn stack_from(branch: String) -> Vec<String> {
-    StackIter::from(branch).collect()
+/// Returns the stack that ends in `branch`, from the top to the bottom
+pub fn stack_from(branch: String) -> Result<Vec<String>, Error> {
+    StackIter::from(branch)?.collect()
 }
 
 /// StackIter is an iterator that yields the current branch and then its parent, and so on, until
 /// the main branch is reached.
+///
+/// A wrong parent can send it around in circles, so it fails when it sees a branch twice, or when
+/// the stack gets deeper than `max-stack-depth` in the config.
 #[derive(Debug, Default)]
 struct StackIter {
     main: String,
     current: Option<String>,
+    /// The branches yielded so far, from the top
+    visited: Vec<String>,
+    max_depth: usize,
 }
 
 impl StackIter {
-    pub fn new() -> Self {
-        Self::from(current_branch().expect("failed to get current branch"))
+    pub fn new() -> Result<Self, Error> {
+        Self::from(current_branch()?)
     }
 
-    pub fn from(branch: String) -> Self {
-        Self {
-            main: main_branch().expect("failed to get main branch"),
+    pub fn from(branch: String) -> Result<Self, Error> {
+        Ok(Self {
+            main: main_branch()?,
             current: Some(branch),
+            visited: Vec::new(),
+            max_depth: config::load()?.max_stack_depth(),
+        })
+    }
+
+    /// The parent of `branch`, skipping over branches excluded in the config
+    fn parent(&self, branch: &str) -> Result<Option<String>, Error> {
+        let config = config::load()?;
+        let mut skipped = Vec::new();
+        let mut next = parent(branch.to_string())?;
+        while let Some(candidate) = next {
+            if candidate == self.main || !config.is_excluded(&candidate) {
+                return Ok(Some(candidate));
+            }
+            if skipped.contains(&candidate) {
+                break;
+            }
+            next = parent(candidate.clone())?;
+            skipped.push(candidate);
         }
+        Ok(None)
     }
 }
 
 impl Iterator for StackIter {
-    type Item = String;
+    /// Finding the parent of a branch can fail, in which case the error is yielded and the
+    /// iteration stops
+    type Item = Result<String, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let current = self.current.take()?;
-        let next = parent(current.clone()).expect("failed to get parent branch");
+        if let Err(e) = check_walk(&self.visited, &current, self.max_depth) {
+            return Some(Err(e));
+        }
+        self.visited.push(current.clone());
+
+        let next = match self.parent(&current) {
+            Ok(next) => next,
+            Err(e) => {
+                return Some(Err(
+                    e.wrap_err(format!("failed to get the parent of '{current}'"))
+                ))
+            }
+        };
         self.current = next
             .filter(|next| next != &self.main)
             .filter(|next| next != &current);
-        Some(current)
+        Some(Ok(current))
+    }
+}
+
+/// Fails if a walk along parent links that went through `visited`, from where it started, would
+/// reach `branch` again, which means the parents go around in a cycle, or if it would get deeper
+/// than `max_depth`. Every walk checks this, so a wrong parent can't send it around forever.
+fn check_walk(visited: &[String], branch: &str, max_depth: usize) -> Result<(), Error> {
+    if let Some(start) = visited.iter().position(|b| b == branch) {
+        let cycle = visited[start..].join(" -> ");
+        return Err(eyre!(
+            "the parents of '{}' go around in a cycle: {cycle} -> {branch}. Record the right \
+             parent with `stackbuddy track`",
+            visited[0]
+        ));
     }
+    if visited.len() >= max_depth {
+        return Err(eyre!(
+            "the stack of '{}' has more than {max_depth} branches, so a parent is probably wrong. \
+             Raise `max-stack-depth` in the config if it's right",
+            visited[0]
+        ));
+    }
+    Ok(())
 }
 
+/// Returns the branch that is checked out. Fails outside of a repository, in a bare one, and
+/// when HEAD is detached, unless `guess-branch` is set in the config, in which case the closest
+/// branch that contains HEAD is used.
 pub fn current_branch() -> Result<String, Error> {
-    let current = Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", "HEAD"])
-        .output()
-        .context("git rev-parse failed")?
-        .stdout;
-    let current = String::from_utf8(current)
-        .context("git rev-parse output was not valid utf-8")?
-        .trim()
-        .to_string();
-    Ok(current)
+    let backend = git::backend();
+    if backend.is_bare()? {
+        return Err(eyre!(
+            "this is a bare repository, so no branch is checked out. Pass a branch explicitly or \
+             run stackbuddy in a worktree"
+        ));
+    }
+    if let Some(branch) = backend.head_branch()? {
+        return Ok(branch);
+    }
+
+    // git detaches HEAD while it rebases
+    if let Some(branch) = rebasing_branch()? {
+        return Err(eyre!(
+            "a rebase of '{branch}' is in progress. Finish it with `git rebase --continue` (or \
+             `stackbuddy continue`, if stackbuddy started it) or abort it first"
+        ));
+    }
+    if config::load()?.guess_branch {
+        let branch = closest_branch_containing("HEAD")?;
+        info!("HEAD is detached, using '{branch}', the closest branch that contains it");
+        return Ok(branch);
+    }
+    Err(eyre!(
+        "HEAD is detached, check out a branch, pass one explicitly, or pass --guess to use the \
+         closest branch that contains HEAD"
+    ))
+}
+
+/// Returns the branch being rebased, if a rebase is in progress and it started on a branch
+fn rebasing_branch() -> Result<Option<String>, Error> {
+    let dir = git_dir()?;
+    for state in ["rebase-merge", "rebase-apply"] {
+        let Ok(head) = std::fs::read_to_string(dir.join(state).join("head-name")) else {
+            continue;
+        };
+        // It's `detached HEAD` when the rebase started on one
+        let branch = head.trim().strip_prefix("refs/heads/").map(str::to_string);
+        return Ok(branch.or_else(|| Some("HEAD".to_string())));
+    }
+    Ok(None)
 }
 
+/// Returns the branch whose tip is the fewest commits above `rev`, among the ones that contain it
+fn closest_branch_containing(rev: &str) -> Result<String, Error> {
+    let output = git::output(&[
+        "for-each-ref",
+        "--format=%(refname:short)",
+        "--contains",
+        rev,
+        "refs/heads",
+    ])
+    .context("git for-each-ref failed")?;
+
+    if !output.success {
+        return Err(eyre!("git for-each-ref failed: {}", output.stderr));
+    }
+    let config = config::load()?;
+    let mut closest: Option<(String, usize)> = None;
+    for branch in output.stdout.lines() {
+        if config.is_excluded(branch) {
+            continue;
+        }
+        let (ahead, _) = ahead_behind(branch, rev)?;
+        if closest.as_ref().is_none_or(|(_, a)| ahead < *a) {
+            closest = Some((branch.to_string(), ahead));
+        }
+    }
+    closest
+        .map(|(branch, _)| branch)
+        .ok_or_else(|| eyre!("HEAD is detached and no branch contains it, check out a branch"))
+}
+
+/// Returns the branch stacks are based on: the `main-branch` in the config, or the default branch
+/// of the remote, or a local `main` or `master` branch, or the default branch according to gh, in
+/// this order.
 pub fn main_branch() -> Result<String, Error> {
-    let branches = Command::new("git")
-        .arg("branch")
-        .output()
-        .context("git branch failed")?
-        .stdout;
-    let branches = String::from_utf8(branches)?;
-
-    let main = branches
-        .lines()
-        .map(|b| b.trim_start_matches("* ").trim())
-        .find(|&b| b == "main" || b == "master");
+    let config = config::load()?;
+    if let Some(main) = &config.main_branch {
+        return Ok(main.clone());
+    }
+    if let Some(main) = remote_default_branch(config.remote())? {
+        return Ok(main);
+    }
+
+    let main = local_branches()?
+        .into_iter()
+        .find(|b| b == "main" || b == "master");
+
+    main.or_else(gh_default_branch).ok_or_else(|| {
+        eyre!(
+            "Main branch not found. Run `git remote set-head {} --auto` or set `main-branch` in \
+                 .stackbuddy.toml",
+            config.remote()
+        )
+    })
+}
+
+/// Returns the branch `refs/remotes/<remote>/HEAD` points to, which is set by `git clone` and
+/// `git remote set-head`
+fn remote_default_branch(remote: &str) -> Result<Option<String>, Error> {
+    let head = format!("refs/remotes/{remote}/HEAD");
+    let Some(target) = git::backend().symbolic_ref(&head)? else {
+        return Ok(None);
+    };
+
+    let main = target
+        .strip_prefix(remote)
+        .and_then(|head| head.strip_prefix('/'))
+        .map(str::to_string);
+    Ok(main)
+}
 
-    main.map(str::to_string)
-        .ok_or_eyre("Main branch not found. Is it named something other than `main` or `master`?")
+fn gh_default_branch() -> Option<String> {
+    let args = [
+        "repo",
+        "view",
+        "--json",
+        "defaultBranchRef",
+        "--jq",
+        ".defaultBranchRef.name",
+    ];
+    let main = forge::run_command("gh", &args, None).ok()?;
+    Some(main.trim().to_string()).filter(|main| !main.is_empty())
 }
 
+/// Returns the parent of the given branch. The parent recorded by `stackbuddy track` is used if
+/// there is one, otherwise it's guessed with the `parent-strategy` in the config.
 pub fn parent(branch: String) -> Result<Option<String>, Error> {
-    let log = Command::new("git")
-        .args(["log", "--oneline", "--graph", "--decorate"])
-        .args(["--simplify-by-decoration", "--first-parent", "-n", "32"])
-        .args(["--skip", "1"])
-        .arg(branch)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()
-        .context(r"git log failed")?;
-
-    let log = String::from_utf8(log.stdout)?;
+    if let Some(parent) = tracked_parent(&branch)? {
+        return Ok(Some(parent));
+    }
+    Ok(guess_parent(&branch, config::load()?.parent_strategy.unwrap_or_default())?.parent)
+}
 
-    let parent = log
-        .lines() // * commit (branch) message
-        .map(|line| line.trim_start_matches('*').trim()) // commit (branch) message
-        .filter_map(|line| line.split_once(' ')) // (branch) message
-        .filter_map(|(_commit, line)| extract_branch(line))
-        .map(str::to_string)
-        .next();
+/// How the parent of a branch is guessed when none was recorded with `stackbuddy track`
+#[derive(ValueEnum, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParentStrategy {
+    /// The closest branch in the git log, or the nearest one by merge-base if there's none
+    #[default]
+    Auto,
 
-    Ok(parent)
+    /// The closest branch in the git log. Fails to find it when the commit the parent pointed to
+    /// was rewritten, e.g. by `git commit --amend`
+    Log,
+
+    /// The branch whose merge-base with the branch is the most recent. Slower, since every local
+    /// branch is compared, but works after the parent was rewritten
+    MergeBase,
+
+    /// Tries both, and says how confident the guess is
+    Best,
 }
 
-fn extract_branch(line: &str) -> Option<&str> {
-    let from = line.find('(')? + 1;
-    let to = line.find(')')?;
+/// How much a guessed parent can be trusted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// The strategies disagree, or the parent has commits the branch doesn't
+    Low,
+    /// Only one strategy found a parent
+    Medium,
+    /// Every strategy found the same parent
+    High,
+}
 
-    #[allow(clippy::filter_next)]
-    line[from..to]
-        .split(", ")
-        .map(|branch| branch.strip_prefix("HEAD -> ").unwrap_or(branch))
-        .filter(|branch| !branch.starts_with("origin/"))
-        .filter(|branch| !branch.starts_with("tag: "))
-        .next()
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let confidence = match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        };
+        write!(f, "{confidence}")
+    }
 }
 
-pub fn pr_for_branch(branch: String) -> Result<Option<String>, Error> {
-    let output = Command::new("gh")
-        .args([
-            "pr",
-            "view",
-            &branch,
-            "--json",
-            "number",
-            "--template",
-            "{{.number}}",
-        ])
-        .output()
-        .context("gh pr view failed")?;
-
-    if !output.status.success() {
-        let stderr =
-            String::from_utf8(output.stderr).context("gh pr view stderr was not valid utf-8")?;
-        if stderr.contains("no pull requests found") {
-            return Ok(None);
-        }
-        return Err(eyre!("gh pr view failed: {}", stderr));
-    }
-
-    let pr = String::from_utf8(output.stdout).context("gh pr view stdout was not valid utf-8")?;
-    Ok(Some(pr).filter(|pr| !pr.is_empty()))
-}
-
-pub fn pr_body(branch: String) -> Result<String, Error> {
-    let output = Command::new("gh")
-        .args(["pr", "view", &branch, "--json", "body", "--jq", ".body"])
-        .output()
-        .context("gh pr view failed")?;
-
-    if !output.status.success() {
-        let stderr =
-            String::from_utf8(output.stderr).context("gh pr view stderr was not valid utf-8")?;
-        return Err(eyre!("gh pr view failed: {}", stderr));
-    }
-
-    let body = String::from_utf8(output.stdout).context("gh pr view stdout was not valid utf-8")?;
-    Ok(body)
-}
-
-pub fn set_pr_body(branch: String, body: String) -> Result<(), Error> {
-    Command::new("gh")
-        .args(["pr", "edit", &branch, "--body-file", "-"])
-        .stdout(Stdio::null())
-        .stdin(Stdio::piped())
-        .spawn()
-        .context("gh pr edit failed")?
-        .stdin
-        .ok_or_else(|| eyre!("gh pr edit stdin was not captured"))?
-        .write_all(body.as_bytes())
-        .context("failed to write to gh pr edit stdin")?;
-    Ok(())
+/// A parent found by [`guess_parent`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParentGuess {
+    pub parent: Option<String>,
+    pub confidence: Confidence,
 }
 
-#[derive(ValueEnum, Default, Clone, Copy)]
-pub enum NoteFormat {
-    /// Displays the previous and next PRs, like a doubly linked list
-    #[default]
-    Double,
+/// Guesses the parent of `branch` with `strategy`, ignoring the one recorded by `stackbuddy
+/// track`. Only [`ParentStrategy::Best`] really measures its confidence; the others report a high
+/// one whenever they find a parent.
+pub fn guess_parent(branch: &str, strategy: ParentStrategy) -> Result<ParentGuess, Error> {
+    let certain = |parent: Option<String>| ParentGuess {
+        confidence: if parent.is_some() {
+            Confidence::High
+        } else {
+            Confidence::Low
+        },
+        parent,
+    };
+    let guess = match strategy {
+        ParentStrategy::Log => certain(parent_from_log(branch.to_string())?),
+        ParentStrategy::MergeBase => certain(parent_from_merge_base(branch)?),
+        ParentStrategy::Auto => match parent_from_log(branch.to_string())? {
+            Some(parent) => certain(Some(parent)),
+            None => certain(parent_from_merge_base(branch)?),
+        },
+        ParentStrategy::Best => {
+            let log = parent_from_log(branch.to_string())?;
+            let (parent, confidence) = match (log, nearest_branch(branch)?) {
+                (Some(log), Some((nearest, _))) if log == nearest => (Some(log), Confidence::High),
+                // The nearest branch is at least as close as the one in the log, so it wins
+                (Some(_), Some((nearest, _))) => (Some(nearest), Confidence::Low),
+                (Some(log), None) => (Some(log), Confidence::Medium),
+                (None, Some((nearest, 0))) => (Some(nearest), Confidence::Medium),
+                (None, Some((nearest, _))) => (Some(nearest), Confidence::Low),
+                (None, None) => (None, Confidence::Low),
+            };
+            ParentGuess { parent, confidence }
+        }
+    };
+    Ok(guess)
+}
+
+fn parent_config_key(branch: &str) -> String {
+    format!("branch.{branch}.stackbuddy-parent")
+}
+
+/// Guesses the parents of `tip` and of every branch below it down to the main branch, ignoring the
+/// recorded ones, and records them. Unless `yes` is set, the guesses are opened in the editor
+/// first, so the wrong ones can be fixed. Returns every branch with the parent recorded for it,
+/// from the bottom up.
+pub fn adopt(tip: String, yes: bool) -> Result<Vec<(String, String)>, Error> {
+    let config = config::load()?;
+    let main = main_branch()?;
+    let strategy = config.parent_strategy.unwrap_or_default();
+
+    // From the tip down
+    let mut chain: Vec<(String, String, Confidence)> = Vec::new();
+    let mut branch = tip;
+    while branch != main {
+        if chain.iter().any(|(b, _, _)| b == &branch) {
+            return Err(eyre!(
+                "the guessed parents go around in circles at '{branch}'"
+            ));
+        }
+        if chain.len() >= config.max_stack_depth() {
+            return Err(eyre!(
+                "the stack is deeper than {} branches",
+                config.max_stack_depth()
+            ));
+        }
+        let guess = guess_parent(&branch, strategy)?;
+        let parent = guess
+            .parent
+            .ok_or_else(|| eyre!("could not guess the parent of '{branch}'"))?;
+        chain.push((branch, parent.clone(), guess.confidence));
+        branch = parent;
+    }
 
-    /// Displays the entire stack of PRs in a list
-    List,
+    let mut links: Vec<(String, String)> = chain
+        .iter()
+        .rev()
+        .map(|(branch, parent, _)| (branch.clone(), parent.clone()))
+        .collect();
+    if !yes {
+        links = edit_links(&chain, &main)?;
+    }
+
+    let existing = local_branches()?;
+    for (branch, parent) in &links {
+        if !existing.contains(branch) {
+            return Err(eyre!("there's no branch named '{branch}'"));
+        }
+        if parent != &main && !existing.contains(parent) {
+            return Err(eyre!("there's no branch named '{parent}'"));
+        }
+    }
+    for (branch, parent) in &links {
+        set_parent(branch, parent)?;
+    }
+    Ok(links)
+}
 
-    /// Displays the previous and next PRs, formatted in two columns of a table
-    Table,
+/// Opens the guessed `chain` (from the top down) in the editor, one `<branch> <parent>` line per
+/// branch from the bottom up, and returns the lines as they were left
+fn edit_links(
+    chain: &[(String, String, Confidence)],
+    main: &str,
+) -> Result<Vec<(String, String)>, Error> {
+    let path = git_dir()?.join("STACKBUDDY_ADOPT");
+    let mut contents: String = chain
+        .iter()
+        .rev()
+        .map(|(branch, parent, confidence)| {
+            format!("{branch} {parent}  # {confidence} confidence\n")
+        })
+        .collect();
+    contents.push_str(&format!(
+        "\n# The branches of the stack on {main} with their guessed parents, from the bottom to\n\
+         # the top. Fix the parents that are wrong, and remove the lines of the branches that\n\
+         # shouldn't be recorded. Text after # is ignored.\n"
+    ));
+    std::fs::write(&path, contents)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    edit_file(&path)?;
+    let edited = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let _ = std::fs::remove_file(&path);
+
+    let mut links = Vec::new();
+    for line in edited.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            [] => {}
+            [branch, parent] => links.push((branch.to_string(), parent.to_string())),
+            _ => {
+                return Err(eyre!(
+                    "expected a branch and its parent, got '{}'",
+                    line.trim()
+                ))
+            }
+        }
+    }
+    Ok(links)
 }
 
-pub fn note_block(branch: String, format: NoteFormat) -> Result<String, Error> {
-    let stack = current_stack();
+/// Opens the editor with a line for each of `choices`, a branch and a comment describing it, and
+/// returns the branches whose lines were kept, in the order of `choices`
+pub fn pick_branches(choices: &[(String, String)], help: &str) -> Result<Vec<String>, Error> {
+    let path = git_dir()?.join("STACKBUDDY_PICK");
+    let mut contents: String = choices
+        .iter()
+        .map(|(branch, comment)| format!("{branch}  # {comment}\n"))
+        .collect();
+    contents.push_str(&format!(
+        "\n# {help}\n# Remove the lines of the branches to leave out. Text after # is ignored.\n"
+    ));
+    std::fs::write(&path, contents)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    edit_file(&path)?;
+    let edited = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let _ = std::fs::remove_file(&path);
 
-    let branch_index = stack
+    let mut kept = HashSet::new();
+    for line in edited.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !choices.iter().any(|(branch, _)| branch == line) {
+            return Err(eyre!("'{line}' isn't one of the branches to pick from"));
+        }
+        kept.insert(line);
+    }
+    Ok(choices
         .iter()
-        .position(|b| b == &branch)
-        .ok_or(eyre!("branch '{}' is not in the stack", branch))?;
+        .map(|(branch, _)| branch)
+        .filter(|branch| kept.contains(branch.as_str()))
+        .cloned()
+        .collect())
+}
 
-    let prev_pr = stack
-        .get(branch_index + 1)
-        .map(|b| pr_for_branch(b.clone()))
-        .transpose()?
-        .flatten();
-    let next_pr = stack
-        .get(branch_index.wrapping_sub(1))
-        .map(|b| pr_for_branch(b.clone()))
-        .transpose()?
-        .flatten();
+/// Returns the parent recorded in `git config branch.<branch>.stackbuddy-parent`, if any
+pub fn tracked_parent(branch: &str) -> Result<Option<String>, Error> {
+    let output = git::output(&["config", "--get", &parent_config_key(branch)])
+        .context("git config failed")?;
 
-    match format {
-        NoteFormat::Double => note_double(prev_pr, next_pr),
-        NoteFormat::List => note_list(&branch, &stack),
-        NoteFormat::Table => note_table(prev_pr, next_pr),
+    // git config exits with 1 when the key is not set
+    if !output.success {
+        return Ok(None);
     }
+
+    let parent = output.stdout.trim().to_string();
+    Ok(Some(parent).filter(|p| !p.is_empty()))
 }
 
-fn note_double(prev_pr: Option<String>, next_pr: Option<String>) -> Result<String, Error> {
-    let mut note = "> [!Note]".to_string();
-    if let Some(prev_pr) = prev_pr {
-        note.push_str(&format!("\n> - Previous PR: #{prev_pr}"));
+/// Records `parent` as the parent of `branch` in the git config
+pub fn set_parent(branch: &str, parent: &str) -> Result<(), Error> {
+    let success = git::status(&["config", &parent_config_key(branch), parent])
+        .context("git config failed")?;
+
+    if !success {
+        return Err(eyre!("failed to set the parent of '{branch}'"));
     }
-    if let Some(next_pr) = next_pr {
-        note.push_str(&format!("\n> - Next PR: #{next_pr}"));
+    Ok(())
+}
+
+/// How the branches of a stack are found
+#[derive(ValueEnum, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Discovery {
+    /// Follows the parent of every branch: the one recorded by `stackbuddy track`, or else the
+    /// closest branch in the git log
+    #[default]
+    Log,
+
+    /// Follows the base branches of the open PRs. Works without the local branches, e.g. when
+    /// they're missing or checked out in another worktree
+    Prs,
+}
+
+/// Returns the stack that ends in `branch`, from the top to the bottom, found with `discovery`
+pub fn discover_stack(
+    forge: &dyn Forge,
+    branch: String,
+    discovery: Discovery,
+) -> Result<Vec<String>, Error> {
+    match discovery {
+        Discovery::Log => stack_from(branch),
+        Discovery::Prs => prs_below(&forge.open_prs()?, &branch),
     }
-    if note == "> [!Note]" {
-        note.push_str("\n> This is currently the only PR in the stack");
+}
+
+/// Follows the bases of the PRs in `prs`, from the PR of `branch` down to the first base without an
+/// open PR. The result goes from `branch` to the bottom, and is empty if `branch` has no open PR.
+fn prs_below(prs: &HashMap<String, PrStatus>, branch: &str) -> Result<Vec<String>, Error> {
+    let config = config::load()?;
+    let mut below: Vec<String> = Vec::new();
+    let mut current = branch;
+    while let Some(pr) = prs.get(current) {
+        if below.iter().any(|b| b == current) || config.is_excluded(current) {
+            break;
+        }
+        below.push(current.to_string());
+        current = &pr.base;
     }
-    Ok(note)
+    Ok(below)
 }
 
-fn note_list(branch: &str, stack: &[String]) -> Result<String, Error> {
-    let mut items = Vec::new();
-    for b in stack.iter().rev() {
-        if let Some(pr) = pr_for_branch(b.clone())? {
-            items.push(format!("- #{pr}"));
-            if b == branch {
-                items.last_mut().unwrap().push_str(" (this)");
+/// Returns the stack that contains `branch` by following the bases of open PRs instead of the git
+/// history, so no local branches are needed: down from the PR of `branch` (see [`Discovery::Prs`]),
+/// and up through the PRs that target the top of the stack, as long as there's only one. The stack
+/// is ordered from top to bottom, and is empty if `branch` has no open PR.
+pub fn stack_from_prs(forge: &dyn Forge, branch: &str) -> Result<Vec<String>, Error> {
+    let prs = forge.open_prs()?;
+    let below = prs_below(&prs, branch)?;
+    if below.is_empty() {
+        return Ok(below);
+    }
+
+    let mut above = Vec::new();
+    let mut top = branch.to_string();
+    loop {
+        let children: Vec<&String> = prs
+            .iter()
+            .filter(|(_, pr)| pr.base == top)
+            .map(|(head, _)| head)
+            .collect();
+        match children[..] {
+            [child] if !above.contains(child) && !below.contains(child) => {
+                above.push(child.clone());
+                top = child.clone();
             }
+            _ => break,
         }
     }
-    Ok(items.join("\n"))
+
+    above.reverse();
+    above.extend(below);
+    Ok(above)
 }
 
-fn note_table(prev_pr: Option<String>, next_pr: Option<String>) -> Result<String, Error> {
-    let prev_pr = prev_pr
-        .map(|pr| format!("#{pr}"))
-        .unwrap_or_else(|| "None".to_string());
-    let next_pr = next_pr
-        .map(|pr| format!("#{pr}"))
-        .unwrap_or_else(|| "None".to_string());
+/// Returns the names of every local branch
+pub fn local_branches() -> Result<Vec<String>, Error> {
+    let tips = git::backend().branch_tips()?;
+    Ok(tips.into_iter().map(|(branch, _)| branch).collect())
+}
 
-    let mut note = String::new();
-    note.push_str("| Previous PR | Next PR |\n");
-    note.push_str("|-------------|---------|\n");
-    note.push_str(&format!("| {prev_pr} | {next_pr} |"));
-    Ok(note)
+/// Returns the branches whose parent is `branch`. There's no way to ask git for this directly,
+/// so the parent of every local branch is computed.
+pub fn children(branch: &str) -> Result<Vec<String>, Error> {
+    let main = main_branch()?;
+    let config = config::load()?;
+    let mut children = Vec::new();
+    for candidate in local_branches()? {
+        if candidate == branch || candidate == main || config.is_excluded(&candidate) {
+            continue;
+        }
+        if parent(candidate.clone())?.as_deref() == Some(branch) {
+            children.push(candidate);
+        }
+    }
+    Ok(children)
 }
 
-pub fn update_note(branch: String, note_format: NoteFormat, dry_run: bool) -> Result<(), Error> {
-    let body = pr_body(branch.clone())
-        .with_context(|| format!("failed to get PR body for branch '{branch}'"))?;
-    let note = note_block(branch.clone(), note_format)?;
-    let new_body = replace_note(&body, &note);
-    if dry_run {
-        println!("New PR body:\n{}", new_body);
-    } else if new_body != body {
-        set_pr_body(branch, new_body)?;
+/// Returns every branch above `branch`, parents before their children
+pub fn descendants(branch: &str) -> Result<Vec<String>, Error> {
+    let tree = StackNode::from_branch(branch.to_string())?;
+    let mut descendants = Vec::new();
+    let mut queue: VecDeque<&StackNode> = tree.children.iter().collect();
+    while let Some(node) = queue.pop_front() {
+        descendants.push(node.branch.clone());
+        queue.extend(&node.children);
     }
-    Ok(())
+    Ok(descendants)
+}
+
+/// Returns the other branches with the same parent as `branch`. Branches right above the main
+/// branch belong to different stacks, so they have no siblings.
+pub fn siblings(branch: &str) -> Result<Vec<String>, Error> {
+    let main = main_branch()?;
+    match parent(branch.to_string())? {
+        Some(parent) if parent != main => {
+            let mut siblings = children(&parent)?;
+            siblings.retain(|b| b != branch);
+            Ok(siblings)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Returns the only child of `branch`, failing if it has none or more than one
+pub fn child(branch: &str) -> Result<String, Error> {
+    let mut children = children(branch)?;
+    match children.len() {
+        0 => Err(eyre!("'{branch}' has no children")),
+        1 => Ok(children.remove(0)),
+        _ => Err(eyre!(
+            "'{branch}' has more than one child: {}",
+            children.join(", ")
+        )),
+    }
+}
+
+/// Returns the branch at the top of the stack that contains `branch`, following its children
+/// upwards
+pub fn top_of(branch: &str) -> Result<String, Error> {
+    let max_depth = config::load()?.max_stack_depth();
+    let mut path = Vec::new();
+    let mut top = branch.to_string();
+    loop {
+        check_walk(&path, &top, max_depth)?;
+        path.push(top.clone());
+        let mut children = children(&top)?;
+        match children.len() {
+            0 => return Ok(top),
+            1 => top = children.remove(0),
+            _ => {
+                return Err(eyre!(
+                    "the stack forks at '{top}', into {}",
+                    children.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+/// Returns the whole stack that contains `branch`, from the top to the bottom. The branches above
+/// `branch` are only included if the stack doesn't fork above it.
+pub fn whole_stack(branch: &str) -> Result<Vec<String>, Error> {
+    stack_from(top_of(branch).unwrap_or(branch.to_string()))
+}
+
+/// Returns the branch at `position` in the stack that contains `branch`, counting from 1 at the
+/// bottom, like the "PR N of M" in notes
+pub fn branch_at(branch: &str, position: usize) -> Result<String, Error> {
+    let stack = whole_stack(branch)?;
+    position
+        .checked_sub(1)
+        .and_then(|i| stack.iter().rev().nth(i))
+        .cloned()
+        .ok_or_else(|| eyre!("the stack only has {} branches", stack.len()))
+}
+
+/// Returns the URL of the PR of every branch in `branches`, failing if one of them has no PR
+pub fn pr_urls(forge: &dyn Forge, branches: &[String]) -> Result<Vec<String>, Error> {
+    branches
+        .iter()
+        .map(|branch| {
+            forge
+                .pr_url(branch)?
+                .ok_or_else(|| eyre!("'{branch}' has no PR"))
+        })
+        .collect()
+}
+
+/// Returns the branch at the bottom of the stack that ends in `branch`, the one right above the
+/// main branch
+pub fn bottom_of(branch: &str) -> Result<String, Error> {
+    stack_from(branch.to_string())?
+        .pop()
+        .ok_or_eyre("the stack is empty")
+}
+
+/// Guesses the parent of the given branch by finding the closest decorated commit in its history
+pub fn parent_from_log(branch: String) -> Result<Option<String>, Error> {
+    let log = git::output(&[
+        "log",
+        "--oneline",
+        "--graph",
+        // Full ref names tell local branches apart from the ones of any remote
+        "--decorate=full",
+        "--simplify-by-decoration",
+        "--first-parent",
+        "-n",
+        "32",
+        "--skip",
+        "1",
+        &branch,
+    ])
+    .context(r"git log failed")?
+    .stdout;
+    let config = config::load()?;
+
+    let parent = log
+        .lines() // * commit (branch) message
+        .map(|line| line.trim_start_matches('*').trim()) // commit (branch) message
+        .filter_map(|line| line.split_once(' ')) // (branch) message
+        .filter_map(|(_commit, line)| extract_branch(line, config))
+        .map(str::to_string)
+        .next();
+
+    Ok(parent)
 }
 
-fn replace_note(pr_body: &str, note: &str) -> String {
-    const OPEN: &str = "<!-- stackbuddy note -->";
-    const CLOSE: &str = "<!-- /stackbuddy note -->";
+/// Guesses the parent of the given branch as the one its history forked from most recently: the
+/// branch with the fewest commits between their merge-base and `branch`
+pub fn parent_from_merge_base(branch: &str) -> Result<Option<String>, Error> {
+    Ok(nearest_branch(branch)?.map(|(parent, _)| parent))
+}
 
-    let open = pr_body.find(OPEN);
-    let close = pr_body.find(CLOSE);
+/// Returns the branch found by [`parent_from_merge_base`], and how many commits it has that
+/// `branch` doesn't
+fn nearest_branch(branch: &str) -> Result<Option<(String, usize)>, Error> {
+    let config = config::load()?;
+    let mut candidates: Vec<(String, String)> = local_branches()?
+        .into_iter()
+        .map(|b| (b.clone(), b))
+        .collect();
+    // The main branch may only exist on the remote
+    let main = main_branch()?;
+    let tracking = format!("{}/{main}", config.remote());
+    if !candidates.iter().any(|(b, _)| b == &main) && rev_parse(&tracking).is_ok() {
+        candidates.push((main, tracking));
+    }
 
-    match (open, close) {
-        (Some(open), Some(close)) if open < close => {
-            let before = &pr_body[..open];
-            let after = &pr_body[close + CLOSE.len()..];
-            format!("{before}{OPEN}\n{note}\n{CLOSE}\n{after}")
+    // Sorted by the commits of `branch` since the merge-base, then by the commits of the candidate
+    // since it, so that a parent wins over its other children
+    let mut nearest: Option<(String, usize, usize)> = None;
+    for (candidate, rev) in candidates {
+        if candidate == branch || config.is_excluded(&candidate) {
+            continue;
         }
-        _ => format!("{OPEN}\n{note}\n{CLOSE}\n{pr_body}"),
+        let (ahead, behind) = ahead_behind(branch, &rev)?;
+        // Branches that have every commit of `branch`, like its children, can't be its parent
+        if ahead == 0 {
+            continue;
+        }
+        if nearest
+            .as_ref()
+            .is_none_or(|(_, a, b)| (ahead, behind) < (*a, *b))
+        {
+            nearest = Some((candidate, ahead, behind));
+        }
+    }
+    Ok(nearest.map(|(parent, _, behind)| (parent, behind)))
+}
+
+fn extract_branch<'a>(line: &'a str, config: &Config) -> Option<&'a str> {
+    let from = line.find('(')? + 1;
+    let to = line.find(')')?;
+
+    #[allow(clippy::filter_next)]
+    line[from..to]
+        .split(", ")
+        .map(|branch| branch.strip_prefix("HEAD -> ").unwrap_or(branch))
+        .filter_map(|branch| branch.strip_prefix("refs/heads/"))
+        .filter(|branch| !config.is_excluded(branch))
+        .next()
+}
+
+/// Returns `arg` itself, or the head branch of the PR it refers to, if it's a reference like `#12`
+/// or the URL of a PR
+pub fn resolve_branch(forge: &dyn Forge, arg: String) -> Result<String, Error> {
+    match forge::parse_pr_arg(&arg) {
+        Some(number) => forge
+            .pr_head(&number)
+            .with_context(|| format!("failed to find the branch of {arg}")),
+        None => Ok(arg),
+    }
+}
+
+pub fn rev_parse(rev: &str) -> Result<String, Error> {
+    let output = git::output(&["rev-parse", rev]).context("git rev-parse failed")?;
+
+    if !output.success {
+        return Err(eyre!("could not resolve '{rev}'"));
+    }
+    Ok(output.stdout.trim().to_string())
+}
+
+/// Returns the commit `a` and `b` forked from, the one `git diff a...b` compares `b` to
+pub fn merge_base(a: &str, b: &str) -> Result<String, Error> {
+    let output = git::output(&["merge-base", a, b]).context("git merge-base failed")?;
+
+    if !output.success {
+        return Err(eyre!("'{a}' and '{b}' have no common history"));
+    }
+    Ok(output.stdout.trim().to_string())
+}
+
+/// Shows the changes of the whole stack that contains `branch` relative to the main branch, or,
+/// with `layer`, only the changes of `branch` relative to its parent. The diff is printed by git,
+/// so it's paged and colored like `git diff`.
+pub fn show_diff(branch: &str, layer: bool, stat: bool) -> Result<(), Error> {
+    let main = main_branch()?;
+    let (base, tip) = if layer {
+        let parent = parent(branch.to_string())?.unwrap_or(main);
+        (parent, branch.to_string())
+    } else {
+        (main, top_of(branch).unwrap_or(branch.to_string()))
+    };
+
+    // Like `git diff base...tip`, so the commits that landed on the base since don't show up
+    let fork = merge_base(&base, &tip)?;
+    let mut args = vec!["diff"];
+    if stat {
+        args.push("--stat");
+    }
+    args.extend([fork.as_str(), tip.as_str()]);
+    if !git::status(&args).context("git diff failed")? {
+        return Err(eyre!("git diff failed"));
+    }
+    Ok(())
+}
+
+/// Returns the `.git` directory of the repository
+pub fn git_dir() -> Result<PathBuf, Error> {
+    // Absolute, since the repository may not be in the current directory
+    let output =
+        git::output(&["rev-parse", "--absolute-git-dir"]).context("git rev-parse failed")?;
+
+    if !output.success {
+        return Err(eyre!("not inside a git repository"));
+    }
+    Ok(PathBuf::from(output.stdout.trim()))
+}
+
+/// Opens `path` in the editor git is configured to use, and waits for it to be closed
+pub fn edit_file(path: &Path) -> Result<(), Error> {
+    let editor = git::output(&["var", "GIT_EDITOR"]).context("git var failed")?;
+    let editor = editor.stdout.trim();
+    if editor.is_empty() {
+        return Err(eyre!("no editor is configured, set $EDITOR or core.editor"));
+    }
+
+    // Like git, the editor goes through the shell, so it can have arguments
+    let status = repo::current()
+        .command("sh")
+        .args(["-c", &format!("{editor} \"$@\""), editor])
+        .arg(path)
+        .status()
+        .with_context(|| format!("failed to run {editor}"))?;
+    if !status.success() {
+        return Err(eyre!("{editor} exited with {status}"));
+    }
+    Ok(())
+}
+
+pub fn checkout(branch: &str) -> Result<(), Error> {
+    let success = git::status(&["checkout", "--quiet", branch]).context("git checkout failed")?;
+
+    if !success {
+        return Err(eyre!("failed to checkout '{branch}'"));
+    }
+    Ok(())
+}
+
+/// Rebases every branch in the stack that ends in `branch` onto its parent, starting from the
+/// bottom of the stack. The branch that was checked out before restacking is restored at the end.
+pub fn restack(branch: String) -> Result<(), Error> {
+    let original = current_branch()?;
+    let main = main_branch()?;
+    let stack = stack_from(branch)?;
+    let steps = stack_steps(&stack, &main, &main)?;
+    let mut op = Operation::start(original, steps, Then::Restack)?;
+    op.run()?;
+    checkout(&op.original)?;
+    op.clear()
+}
+
+/// Resumes the operation that stopped because of conflicts, once they're resolved, running the
+/// rebases that are left and what the operation does afterwards. Returns the reports of `sync`,
+/// if that's the operation that stopped.
+pub fn continue_operation(forge: &dyn Forge) -> Result<Vec<SyncReport>, Error> {
+    let mut op = Operation::resume()?;
+    op.run()?;
+    match op.then.clone() {
+        Then::Restack => {
+            checkout(&op.original)?;
+            op.clear()?;
+            Ok(Vec::new())
+        }
+        Then::Sync { .. } => finish_sync(op),
+        Then::Move { .. } => finish_move(forge, op).map(|()| Vec::new()),
+        Then::Reorder { .. } => finish_reorder(forge, op).map(|()| Vec::new()),
+    }
+}
+
+/// Stops the operation that stopped because of conflicts, moving every branch back to where it was
+pub fn abort_operation() -> Result<(), Error> {
+    Operation::abort()
+}
+
+/// What `exec` got from running the command on a branch
+pub struct ExecReport {
+    pub branch: String,
+    /// The exit code of the command, or None if it was killed by a signal
+    pub code: Option<i32>,
+}
+
+impl ExecReport {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl std::fmt::Display for ExecReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(0) => write!(f, "{}: ok", self.branch),
+            Some(code) => write!(f, "{}: failed with exit code {code}", self.branch),
+            None => write!(f, "{}: killed by a signal", self.branch),
+        }
+    }
+}
+
+/// Checks out every branch in the stack that ends in `branch`, from the bottom to the top, and
+/// runs `command` on it. With `fail_fast`, branches above the first one where the command fails
+/// are skipped. The branch that was checked out before is restored at the end.
+pub fn exec(branch: String, command: &[String], fail_fast: bool) -> Result<Vec<ExecReport>, Error> {
+    let original = current_branch()?;
+    let stack = stack_from(branch)?;
+
+    let mut reports = Vec::new();
+    let result = exec_each(&stack, command, fail_fast, &mut reports);
+    // The original branch is restored even if a checkout failed midway
+    checkout(&original)?;
+    result?;
+    Ok(reports)
+}
+
+fn exec_each(
+    stack: &[String],
+    command: &[String],
+    fail_fast: bool,
+    reports: &mut Vec<ExecReport>,
+) -> Result<(), Error> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_eyre("there is no command to run")?;
+    for branch in stack.iter().rev() {
+        checkout(branch)?;
+        info!("==> {branch}: {}", command.join(" "));
+        let status = repo::current()
+            .command(program)
+            .args(args)
+            .status()
+            .with_context(|| format!("failed to run {program}"))?;
+        reports.push(ExecReport {
+            branch: branch.clone(),
+            code: status.code(),
+        });
+        if !status.success() && fail_fast {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Fast-forwards the local main branch to the one in the remote
+pub fn update_main(main: &str) -> Result<(), Error> {
+    let remote = config::load()?.remote();
+    let refspec = format!("{main}:{main}");
+    let args: &[&str] = if current_branch()? == main {
+        &["pull", "--quiet", "--ff-only", remote, main]
+    } else {
+        &["fetch", "--quiet", remote, &refspec]
+    };
+    let success = git::status(args).context("failed to update the main branch")?;
+
+    if !success {
+        return Err(eyre!("failed to fast-forward '{main}' to {remote}/{main}"));
+    }
+    Ok(())
+}
+
+pub fn delete_branch(branch: &str) -> Result<(), Error> {
+    let success =
+        git::status(&["branch", "--quiet", "-D", branch]).context("git branch -D failed")?;
+
+    if !success {
+        return Err(eyre!("failed to delete '{branch}'"));
+    }
+    Ok(())
+}
+
+/// Opens the stack that ends in `branch` in the editor, from the bottom to the top, and then
+/// rebases its branches into the order they were left in. Their parents are recorded, the PRs are
+/// force-pushed and retargeted, and the notes are updated to match.
+pub fn reorder(forge: &dyn Forge, branch: String, note_format: NoteFormat) -> Result<(), Error> {
+    let original = current_branch()?;
+    let main = main_branch()?;
+    let stack = stack_from(branch)?;
+    if stack.len() < 2 {
+        info!(
+            "There's nothing to reorder in a stack of {} branches",
+            stack.len()
+        );
+        return Ok(());
+    }
+
+    let path = git_dir()?.join("STACKBUDDY_REORDER");
+    let mut contents: String = stack.iter().rev().map(|b| format!("{b}\n")).collect();
+    contents.push_str(&format!(
+        "\n# The branches of the stack on {main}, from the bottom to the top.\n\
+         # Rearrange the lines to reorder them. Lines starting with # are ignored,\n\
+         # and leaving the order as is changes nothing.\n"
+    ));
+    std::fs::write(&path, contents)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    edit_file(&path)?;
+    let edited = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let _ = std::fs::remove_file(&path);
+
+    // Bottom to top, like in the file
+    let order: Vec<String> = edited
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    let mut sorted = order.clone();
+    sorted.sort();
+    let mut expected = stack.clone();
+    expected.sort();
+    if sorted != expected {
+        return Err(eyre!(
+            "the reordered stack must have the same branches, each in its own line"
+        ));
+    }
+    if order.iter().eq(stack.iter().rev()) {
+        info!("The order didn't change, there's nothing to do");
+        return Ok(());
+    }
+
+    // Every branch keeps the commits between its old parent and itself, and gets its new parent
+    // under them
+    let old_parent = |b: &String| -> &str {
+        let i = stack.iter().position(|s| s == b).unwrap();
+        stack.get(i + 1).unwrap_or(&main)
+    };
+    let mut steps = Vec::new();
+    for (i, b) in order.iter().enumerate() {
+        steps.push(RebaseStep {
+            branch: b.clone(),
+            onto: i.checked_sub(1).map_or(main.clone(), |i| order[i].clone()),
+            upstream: rev_parse(old_parent(b))?,
+        });
+    }
+    let then = Then::Reorder {
+        order,
+        format: note_format,
+    };
+    let mut op = Operation::start(original, steps, then)?;
+    op.run()?;
+    finish_reorder(forge, op)
+}
+
+/// Splits `branch` into several branches stacked on each other. The commits of the branch are
+/// listed in the editor, and a `branch <name>` line after a commit creates a branch that ends
+/// there. The commits after the last of those lines stay on `branch`. No commit moves, so nothing
+/// is rebased: the new branches are created and recorded as the parents of each other, with
+/// `branch` on top. With `open_pr`, they're pushed and get draft PRs, and the PR of `branch` is
+/// retargeted to the one right below it.
+pub fn split(forge: &dyn Forge, branch: String, open_pr: bool) -> Result<(), Error> {
+    let main = main_branch()?;
+    let parent = parent(branch.clone())?.unwrap_or(main);
+    let output = git::output(&[
+        "log",
+        "--reverse",
+        "--format=%H %s",
+        &format!("{parent}..{branch}"),
+    ])
+    .context("git log failed")?;
+    if !output.success {
+        return Err(eyre!("failed to list the commits of '{branch}'"));
+    }
+    let commits: Vec<(&str, &str)> = output
+        .stdout
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .collect();
+    if commits.len() < 2 {
+        info!(
+            "There's nothing to split in a branch with {} commits",
+            commits.len()
+        );
+        return Ok(());
+    }
+
+    let path = git_dir()?.join("STACKBUDDY_SPLIT");
+    let mut contents: String = commits
+        .iter()
+        .map(|(commit, subject)| format!("{} {subject}\n", &commit[..12]))
+        .collect();
+    contents.push_str(&format!(
+        "\n# The commits of {branch} on {parent}, from the oldest to the newest.\n\
+         # Add a line `branch <name>` after the last commit of each new branch.\n\
+         # The commits after the last of those lines stay on {branch}.\n\
+         # Lines starting with # are ignored, and adding no branches changes nothing.\n"
+    ));
+    std::fs::write(&path, contents)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    edit_file(&path)?;
+    let edited = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let _ = std::fs::remove_file(&path);
+
+    // The new branches from the bottom up, with the commit they end in
+    let mut parts: Vec<(String, &str)> = Vec::new();
+    let mut seen: usize = 0;
+    for line in edited.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("branch ") {
+            let Some(end) = seen.checked_sub(1).map(|i| commits[i].0) else {
+                return Err(eyre!("'{name}' must come after at least one commit"));
+            };
+            if parts.last().is_some_and(|(_, last)| *last == end) {
+                return Err(eyre!("'{name}' has no commits of its own"));
+            }
+            parts.push((name.trim().to_string(), end));
+            continue;
+        }
+        let abbrev = line.split(' ').next().unwrap_or_default();
+        match commits.get(seen) {
+            Some((commit, _)) if commit.starts_with(abbrev) && !abbrev.is_empty() => seen += 1,
+            _ => {
+                return Err(eyre!(
+                "the commits can't be changed, reordered or removed, use `git rebase -i` for that"
+            ))
+            }
+        }
+    }
+    if seen != commits.len() {
+        return Err(eyre!(
+            "the commits can't be changed, reordered or removed, use `git rebase -i` for that"
+        ));
+    }
+    if parts.is_empty() {
+        info!("No branches were added, there's nothing to do");
+        return Ok(());
+    }
+    if parts
+        .last()
+        .is_some_and(|(_, end)| *end == commits[commits.len() - 1].0)
+    {
+        return Err(eyre!("the last commit has to stay on '{branch}'"));
+    }
+    let existing = local_branches()?;
+    if let Some((name, _)) = parts.iter().find(|(name, _)| existing.contains(name)) {
+        return Err(eyre!("a branch named '{name}' already exists"));
+    }
+
+    let mut below = parent.clone();
+    for (name, end) in &parts {
+        info!("Creating {name} (parent: {below})...");
+        let success = git::status(&["branch", name, end]).context("git branch failed")?;
+        if !success {
+            return Err(eyre!("failed to create '{name}'"));
+        }
+        set_parent(name, &below)?;
+        below = name.clone();
+    }
+    set_parent(&branch, &below)?;
+
+    if open_pr {
+        let mut base = parent;
+        for (name, _) in &parts {
+            info!("Creating PR for {name} (base: {base})...");
+            push_branch(name)?;
+            let pr = forge.create_pr(name, &base, true)?;
+            println!("Created {}", forge.pr_reference(&pr));
+            base = name.clone();
+        }
+        if forge.pr_state(&branch)? == Some(PrState::Open) {
+            info!("Retargeting the PR of {branch} to {base}...");
+            forge.set_pr_base(&branch, &base)?;
+        }
+    }
+    Ok(())
+}
+
+/// Renames the branch `old` to `new`, locally and on the remote, and records `new` as the parent
+/// of the branches whose parent was `old`. The PR of the branch follows it (see
+/// [`Forge::rename_branch`]), and the notes of the stack are updated to match.
+pub fn rename(
+    forge: &dyn Forge,
+    old: &str,
+    new: &str,
+    note_format: NoteFormat,
+) -> Result<(), Error> {
+    let existing = local_branches()?;
+    if !existing.iter().any(|b| b == old) {
+        return Err(eyre!("there's no branch named '{old}'"));
+    }
+    if existing.iter().any(|b| b == new) {
+        return Err(eyre!("a branch named '{new}' already exists"));
+    }
+    if old == main_branch()? {
+        return Err(eyre!("the main branch can't be renamed"));
+    }
+    let pushed = remote_branch_exists(old)?;
+    let mut children = Vec::new();
+    for branch in &existing {
+        if tracked_parent(branch)?.as_deref() == Some(old) {
+            children.push(branch.clone());
+        }
+    }
+
+    info!("Renaming {old} to {new}...");
+    // The config of the branch, like its parent, is renamed with it
+    let success = git::status(&["branch", "-m", old, new]).context("git branch failed")?;
+    if !success {
+        return Err(eyre!("failed to rename '{old}' to '{new}'"));
+    }
+    for child in &children {
+        set_parent(child, new)?;
+    }
+
+    if pushed {
+        info!("Renaming {old} to {new} on the remote...");
+        forge.rename_branch(old, new)?;
+        let remote = config::load()?.push_remote();
+        let success =
+            git::status(&["fetch", "--quiet", "--prune", remote]).context("git fetch failed")?;
+        if !success {
+            return Err(eyre!("failed to fetch from {remote}"));
+        }
+        let upstream = format!("{remote}/{new}");
+        let success = git::status(&["branch", "--quiet", "--set-upstream-to", &upstream, new])
+            .context("git branch failed")?;
+        if !success {
+            return Err(eyre!("failed to set the upstream of '{new}' to {upstream}"));
+        }
+
+        let prs = forge.open_prs()?;
+        let stack = whole_stack(new)?;
+        let options = NoteOptions::new(note_format)?;
+        for branch in stack.iter().filter(|b| prs.contains_key(*b)) {
+            info!("Updating notes for {branch}...");
+            update_note(forge, &stack, branch.clone(), &options, false)?;
+        }
+    }
+    Ok(())
+}
+
+/// Records the new parents of a reordered stack once it's rebased, and fixes the PRs
+fn finish_reorder(forge: &dyn Forge, op: Operation) -> Result<(), Error> {
+    let Then::Reorder {
+        order,
+        format: note_format,
+    } = &op.then
+    else {
+        return Err(eyre!("the operation in progress is not a reorder"));
+    };
+    let main = main_branch()?;
+    // Bottom to top, with their new parents
+    let parents: Vec<(&String, &String)> = order
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b, i.checked_sub(1).map_or(&main, |i| &order[i])))
+        .collect();
+    for (b, parent) in &parents {
+        set_parent(b, parent)?;
+    }
+    checkout(&op.original)?;
+    op.clear()?;
+
+    let mut with_prs = Vec::new();
+    for b in order {
+        if forge.pr_state(b)? == Some(PrState::Open) {
+            with_prs.push(b.clone());
+        }
+    }
+    if !with_prs.is_empty() {
+        info!("Pushing {}...", with_prs.join(", "));
+        force_push(&with_prs)?;
+    }
+    for (b, parent) in &parents {
+        if with_prs.contains(b) {
+            info!("Retargeting the PR of {b} to {parent}...");
+            forge.set_pr_base(b, parent)?;
+        }
+    }
+
+    let new_stack: Vec<String> = order.iter().rev().cloned().collect();
+    let options = NoteOptions::new(*note_format)?;
+    for b in &with_prs {
+        info!("Updating notes for {b}...");
+        update_note(forge, &new_stack, b.clone(), &options, false)?;
+    }
+    Ok(())
+}
+
+/// Folds `branch` into its parent: the parent is fast-forwarded to include the commits of
+/// `branch`, the branches on top of `branch` are moved onto the parent, the PRs of those are
+/// retargeted to it and the PR of `branch` is closed. Then `branch` is deleted, the parent is
+/// pushed and the notes of the stack are updated.
+pub fn fold(forge: &dyn Forge, branch: String, note_format: NoteFormat) -> Result<(), Error> {
+    let original = current_branch()?;
+    let main = main_branch()?;
+    let into = parent(branch.clone())?
+        .filter(|parent| parent != &main)
+        .ok_or_else(|| {
+            eyre!("'{branch}' is at the bottom of the stack, there's nothing to fold it into")
+        })?;
+    let above = children(&branch)?;
+
+    info!("Folding {branch} into {into}...");
+    checkout(&into)?;
+    let success =
+        git::status(&["merge", "--quiet", "--ff-only", &branch]).context("git merge failed")?;
+    if !success {
+        return Err(eyre!(
+            "'{branch}' is not on top of '{into}', run `stackbuddy restack` and try again"
+        ));
+    }
+
+    let has_pr = forge.pr_state(&branch)? == Some(PrState::Open);
+    if has_pr || forge.pr_state(&into)? == Some(PrState::Open) {
+        info!("Pushing {into}...");
+        force_push(std::slice::from_ref(&into))?;
+    }
+    for child in &above {
+        if tracked_parent(child)?.is_some() {
+            set_parent(child, &into)?;
+        }
+        if forge.pr_state(child)? == Some(PrState::Open) {
+            info!("Retargeting the PR of {child} to {into}...");
+            forge.set_pr_base(child, &into)?;
+        }
+    }
+    if has_pr {
+        info!("Closing the PR of {branch}...");
+        forge.close_pr(&branch)?;
+        delete_remote_branch(&branch)?;
+    }
+
+    checkout(if original == branch { &into } else { &original })?;
+    delete_branch(&branch)?;
+
+    // The branches above don't need to be rebased, since the parent now points to where
+    // `branch` was
+    let top = match above.first() {
+        Some(child) => top_of(child)?,
+        None => into.clone(),
+    };
+    let stack = stack_from(top)?;
+    let options = NoteOptions::new(note_format)?;
+    for b in &stack {
+        if forge.pr_state(b)? == Some(PrState::Open) {
+            info!("Updating notes for {b}...");
+            update_note(forge, &stack, b.clone(), &options, false)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves `branch` and every branch above it onto `onto`: they are rebased, `onto` is recorded as
+/// the parent of `branch`, and if `branch` has a PR, it's retargeted to `onto`. The rebased
+/// branches that have PRs are force-pushed, and the notes of the stacks `branch` left and joined
+/// are updated.
+pub fn move_branch(
+    forge: &dyn Forge,
+    branch: String,
+    onto: &str,
+    note_format: NoteFormat,
+) -> Result<(), Error> {
+    let original = current_branch()?;
+    let main = main_branch()?;
+    let old_parent = parent(branch.clone())?.unwrap_or_else(|| main.clone());
+    let above = descendants(&branch)?;
+    if onto == branch || above.iter().any(|b| b == onto) {
+        return Err(eyre!(
+            "can't move '{branch}' onto itself or a branch above it"
+        ));
+    }
+
+    // Every branch is moved from where its parent was to where its parent is now, so the old tips
+    // are taken before anything moves
+    let mut steps = vec![RebaseStep {
+        branch: branch.clone(),
+        onto: onto.to_string(),
+        upstream: rev_parse(&old_parent)?,
+    }];
+    for b in &above {
+        let parent = parent(b.clone())?.ok_or_else(|| eyre!("'{b}' has no parent"))?;
+        steps.push(RebaseStep {
+            branch: b.clone(),
+            upstream: rev_parse(&parent)?,
+            onto: parent,
+        });
+    }
+    let then = Then::Move {
+        branch,
+        onto: onto.to_string(),
+        old_parent,
+        moved: steps.iter().map(|step| step.branch.clone()).collect(),
+        format: note_format,
+    };
+    let mut op = Operation::start(original, steps, then)?;
+    op.run()?;
+    finish_move(forge, op)
+}
+
+/// Records the new parent of a moved branch once it's rebased, and fixes the PRs
+fn finish_move(forge: &dyn Forge, op: Operation) -> Result<(), Error> {
+    let Then::Move {
+        branch,
+        onto,
+        old_parent,
+        moved,
+        format: note_format,
+    } = &op.then
+    else {
+        return Err(eyre!("the operation in progress is not a move"));
+    };
+    let (branch, onto) = (branch.clone(), onto.as_str());
+    set_parent(&branch, onto)?;
+    checkout(&op.original)?;
+    op.clear()?;
+
+    let main = main_branch()?;
+    let mut with_prs = Vec::new();
+    for b in moved {
+        if forge.pr_state(b)? == Some(PrState::Open) {
+            with_prs.push(b.clone());
+        }
+    }
+    if !with_prs.is_empty() {
+        info!("Pushing {}...", with_prs.join(", "));
+        force_push(&with_prs)?;
+    }
+    if with_prs.contains(&branch) {
+        info!("Retargeting the PR of {branch} to {onto}...");
+        forge.set_pr_base(&branch, onto)?;
+    }
+
+    let mut stacks = vec![stack_from(top_of(&branch).unwrap_or(branch.clone()))?];
+    if *old_parent != main {
+        stacks.push(stack_from(old_parent.clone())?);
+    }
+    let options = NoteOptions::new(*note_format)?;
+    for stack in &stacks {
+        for b in stack {
+            if forge.pr_state(b)? == Some(PrState::Open) {
+                info!("Updating notes for {b}...");
+                update_note(forge, stack, b.clone(), &options, false)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Merges the PR at the bottom of the stack that ends in `branch`, deletes its branch, and moves
+/// the rest of the stack onto the main branch: the next PR is retargeted, the remaining branches
+/// are rebased and force-pushed, and their notes are updated.
+pub fn land(
+    forge: &dyn Forge,
+    branch: String,
+    strategy: MergeStrategy,
+    note_format: NoteFormat,
+) -> Result<(), Error> {
+    let main = main_branch()?;
+    let stack = stack_from(branch)?;
+    let (bottom, rest) = stack.split_last().ok_or_eyre("there is nothing to land")?;
+
+    let pr = forge
+        .pr_for_branch(bottom)?
+        .filter(|pr| pr.state == PrState::Open)
+        .ok_or_else(|| eyre!("branch '{bottom}' has no open PR to land"))?;
+
+    // Retarget first, so the next PR isn't closed when its base branch is deleted
+    retarget_next(forge, rest, &main)?;
+
+    info!("Merging {}...", forge.pr_reference(&pr.number));
+    forge.merge_pr(bottom, strategy)?;
+    finish_land(forge, bottom, rest, &main, note_format)
+}
+
+/// Points the PR of the branch at the bottom of `rest` to `main`, and records `main` as its parent
+/// if it had one recorded, before the branch below it goes away
+fn retarget_next(forge: &dyn Forge, rest: &[String], main: &str) -> Result<(), Error> {
+    let Some(next) = rest.last() else {
+        return Ok(());
+    };
+    if forge.pr_state(next)? == Some(PrState::Open) {
+        info!("Retargeting the PR of {next} to {main}...");
+        forge.set_pr_base(next, main)?;
+    }
+    if tracked_parent(next)?.is_some() {
+        set_parent(next, main)?;
+    }
+    Ok(())
+}
+
+/// Cleans up after the PR of `bottom` was merged: deletes its branch, moves `rest`, the branches
+/// above it, onto the updated main branch, force-pushes them and updates their notes
+fn finish_land(
+    forge: &dyn Forge,
+    bottom: &str,
+    rest: &[String],
+    main: &str,
+    note_format: NoteFormat,
+) -> Result<(), Error> {
+    let original = current_branch()?;
+    // The forge may have deleted it already when it merged the PR
+    if remote_branch_exists(bottom)? {
+        delete_remote_branch(bottom)?;
+    }
+
+    let bottom_tip = rev_parse(bottom)?;
+    if original == bottom {
+        checkout(main)?;
+    }
+    update_main(main)?;
+    rebase_stack(rest, main, &bottom_tip)?;
+    checkout(if original == bottom { main } else { &original })?;
+    delete_branch(bottom)?;
+
+    if !rest.is_empty() {
+        info!("Pushing {}...", rest.join(", "));
+        force_push(rest)?;
+    }
+    let options = NoteOptions::new(note_format)?;
+    for branch in rest {
+        info!("Updating notes for {branch}...");
+        update_note(forge, rest, branch.clone(), &options, false)?;
+    }
+    Ok(())
+}
+
+/// How [`land_queued`] and [`automerge`] wait for a PR to be merged
+#[derive(Debug, Clone, Copy)]
+pub struct Wait {
+    /// How long to wait between checks of the forge
+    pub interval: Duration,
+    /// How long to wait for each PR before giving up
+    pub timeout: Duration,
+}
+
+/// Adds the bottom PR of the stack that ends in `branch` to the merge queue. Once it's merged, the
+/// stack is moved onto the main branch like [`land`] does, and with `next`, the next PR is queued
+/// too, and so on up the stack.
+///
+/// With `wait`, the forge is polled until the PR leaves the queue, failing if that takes longer than
+/// its timeout. Otherwise it returns right after queueing it, and running it again once it's merged
+/// moves the stack. Which PR was queued is recorded in the git config, so it isn't queued twice.
+pub fn land_queued(
+    forge: &dyn Forge,
+    branch: String,
+    next: bool,
+    wait: Option<Wait>,
+    note_format: NoteFormat,
+) -> Result<(), Error> {
+    let top = top_of(&branch).unwrap_or(branch);
+    merge_bottom_up(forge, top, next, wait, note_format, &|b| {
+        forge.enqueue_pr(b)?;
+        info!("Added the PR of {b} to the merge queue");
+        Ok(())
+    })
+}
+
+/// Enables auto-merge on the PRs of the stack that ends in `branch`, from the bottom up, so the
+/// forge merges each one into its base once it's ready.
+///
+/// With `cascade`, it's only enabled on the bottom PR instead, and the forge is polled as `wait`
+/// says until it's merged. The stack is then moved onto the main branch like [`land`] does,
+/// and the same is done with the next PR, until the whole stack is merged. Everything is read from
+/// the forge, so running it again picks up where an interrupted run stopped.
+pub fn automerge(
+    forge: &dyn Forge,
+    branch: String,
+    strategy: MergeStrategy,
+    cascade: bool,
+    wait: Wait,
+    note_format: NoteFormat,
+) -> Result<(), Error> {
+    let top = top_of(&branch).unwrap_or(branch);
+    if !cascade {
+        for b in stack_from(top)?.iter().rev() {
+            let Some(pr) = forge
+                .pr_for_branch(b)?
+                .filter(|pr| pr.state == PrState::Open)
+            else {
+                info!("Skipping {b}, which has no open PR");
+                continue;
+            };
+            info!(
+                "Enabling auto-merge on {}...",
+                forge.pr_reference(&pr.number)
+            );
+            forge.enable_auto_merge(b, strategy)?;
+        }
+        return Ok(());
+    }
+
+    merge_bottom_up(forge, top, true, Some(wait), note_format, &|b| {
+        info!("Enabling auto-merge on the PR of {b}...");
+        forge.enable_auto_merge(b, strategy)
+    })
+}
+
+fn merging_config_key(branch: &str) -> String {
+    format!("branch.{branch}.stackbuddy-merging")
+}
+
+/// Has the forge merge the PRs of the stack that ends in `top` one at a time, from the bottom up.
+/// `start` is called on the bottom PR to have it merged, and once the forge merged it, the rest of
+/// the stack is moved onto the main branch. With `walk`, the same is then done with the next PR.
+/// With `wait`, the forge is polled until the PR is merged, failing once a PR was waited for longer
+/// than its timeout, and otherwise it returns while the PR is still open.
+///
+/// The branch `start` was called on is recorded in `git config branch.<branch>.stackbuddy-merging`,
+/// which goes away with the branch, so running it again doesn't start the same PR twice.
+fn merge_bottom_up(
+    forge: &dyn Forge,
+    top: String,
+    walk: bool,
+    wait: Option<Wait>,
+    note_format: NoteFormat,
+    start: &dyn Fn(&str) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let main = main_branch()?;
+    // The branch being waited for, and since when
+    let mut waiting: Option<(String, Instant)> = None;
+    loop {
+        let stack = stack_from(top.clone())?;
+        let (bottom, rest) = stack.split_last().ok_or_eyre("there is nothing to merge")?;
+        let pr = forge
+            .pr_for_branch(bottom)?
+            .ok_or_else(|| eyre!("branch '{bottom}' has no PR to merge"))?;
+        let reference = forge.pr_reference(&pr.number);
+        match pr.state {
+            PrState::Merged => {
+                info!("{reference} was merged");
+                retarget_next(forge, rest, &main)?;
+                finish_land(forge, bottom, rest, &main, note_format)?;
+                if rest.is_empty() {
+                    info!("The whole stack was merged");
+                    return Ok(());
+                }
+                if !walk {
+                    return Ok(());
+                }
+            }
+            PrState::Closed => {
+                return Err(eyre!("{reference} was closed without being merged"));
+            }
+            PrState::Open => {
+                let key = merging_config_key(bottom);
+                let started = git::output(&["config", "--get", &key])
+                    .context("git config failed")?
+                    .success;
+                if !started {
+                    start(bottom)?;
+                    let success =
+                        git::status(&["config", &key, "true"]).context("git config failed")?;
+                    if !success {
+                        return Err(eyre!("failed to record that {reference} is being merged"));
+                    }
+                }
+                let Some(wait) = wait else {
+                    info!(
+                        "Run this again once {reference} is merged to move the rest of the stack"
+                    );
+                    return Ok(());
+                };
+                let since = match &waiting {
+                    Some((branch, since)) if branch == bottom => *since,
+                    _ => waiting.insert((bottom.clone(), Instant::now())).1,
+                };
+                if since.elapsed() >= wait.timeout {
+                    return Err(eyre!(
+                        "{reference} wasn't merged after {} seconds, run this again to keep waiting",
+                        wait.timeout.as_secs()
+                    ));
+                }
+                info!("Waiting for {reference} to be merged...");
+                std::thread::sleep(wait.interval);
+                forge.refresh();
+            }
+        }
+    }
+}
+
+/// Returns the local branches whose PR was merged, each one after its parent when both are
+pub fn merged_branches(forge: &dyn Forge) -> Result<Vec<String>, Error> {
+    let main = main_branch()?;
+    let config = config::load()?;
+    let mut merged = Vec::new();
+    for branch in local_branches()? {
+        if branch == main || config.is_excluded(&branch) {
+            continue;
+        }
+        if forge.pr_state(&branch)? == Some(PrState::Merged) {
+            merged.push(branch);
+        }
+    }
+
+    // Cleaning a branch moves its children onto its parent, so parents have to go first
+    let mut ordered: Vec<String> = Vec::new();
+    while !merged.is_empty() {
+        let mut next = Vec::new();
+        for branch in &merged {
+            let parent = parent(branch.clone())?;
+            let waiting = parent.is_some_and(|p| merged.contains(&p) && !ordered.contains(&p));
+            if !waiting {
+                next.push(branch.clone());
+            }
+        }
+        if next.is_empty() {
+            // The parents go around in a cycle, so any order is as good as another
+            next = std::mem::take(&mut merged);
+        }
+        merged.retain(|branch| !next.contains(branch));
+        ordered.extend(next);
+    }
+    Ok(ordered)
+}
+
+/// Deletes `branch`, whose PR was merged, both locally and on the remote. Its children are moved
+/// onto its parent, with their PRs retargeted to it, and the parent recorded for it goes away with
+/// the branch.
+pub fn clean(forge: &dyn Forge, branch: &str) -> Result<(), Error> {
+    let main = main_branch()?;
+    let new_parent = parent(branch.to_string())?.unwrap_or_else(|| main.clone());
+
+    // Retarget first, so the PRs aren't closed when their base branch is deleted
+    for child in children(branch)? {
+        set_parent(&child, &new_parent)?;
+        if forge.pr_state(&child)? == Some(PrState::Open) {
+            info!("Retargeting the PR of {child} to {new_parent}...");
+            forge.set_pr_base(&child, &new_parent)?;
+        }
+    }
+
+    if remote_branch_exists(branch)? {
+        delete_remote_branch(branch)?;
+    }
+    if current_branch()? == branch {
+        checkout(&new_parent)?;
+    }
+    delete_branch(branch)
+}
+
+/// What `sync` did to a branch
+pub struct SyncReport {
+    pub branch: String,
+    /// Whether the tip of the branch changed when rebasing it
+    pub rebased: bool,
+    pub pushed: Result<(), Error>,
+}
+
+impl std::fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rebased = if self.rebased {
+            "rebased"
+        } else {
+            "up to date"
+        };
+        match &self.pushed {
+            Ok(()) => write!(f, "{}: {rebased}, pushed", self.branch),
+            Err(e) => write!(f, "{}: {rebased}, push failed: {e}", self.branch),
+        }
+    }
+}
+
+/// Fetches from the remote, fast-forwards the main branch, rebases every branch in the stack that
+/// ends in `branch` onto its parent and force-pushes them. A failed push doesn't stop the other
+/// branches from being pushed, and is reported instead.
+pub fn sync(branch: String) -> Result<Vec<SyncReport>, Error> {
+    let original = current_branch()?;
+    let main = main_branch()?;
+    let stack = stack_from(branch)?;
+
+    info!("Fetching from {}...", config::load()?.remote());
+    push::fetch()?;
+    update_main(&main)?;
+
+    let steps = stack_steps(&stack, &main, &main)?;
+    let mut op = Operation::start(original, steps, Then::Sync { stack })?;
+    op.run()?;
+    finish_sync(op)
+}
+
+/// Amends the tip commit of the current branch with the staged changes, and rebases every branch
+/// above it onto the amended commit, so none of them is left on the old one. Unless `no_push` is
+/// set, the branch and the ones above it are force-pushed afterwards, like `sync` does.
+pub fn amend(no_push: bool) -> Result<Vec<SyncReport>, Error> {
+    let branch = current_branch()?;
+    let descendants = descendants(&branch)?;
+
+    // The old tips say which commits belong to each branch once its parent is rewritten
+    let mut old_tips = HashMap::new();
+    for b in std::iter::once(&branch).chain(&descendants) {
+        old_tips.insert(b.clone(), rev_parse(b)?);
+    }
+    let mut steps = Vec::new();
+    for child in &descendants {
+        let parent = parent(child.clone())?.ok_or_else(|| eyre!("'{child}' has no parent"))?;
+        steps.push(RebaseStep {
+            upstream: old_tips[&parent].clone(),
+            onto: parent,
+            branch: child.clone(),
+        });
+    }
+
+    let then = if no_push {
+        Then::Restack
+    } else {
+        let stack = std::iter::once(branch.clone()).chain(descendants).collect();
+        Then::Sync { stack }
+    };
+    // Started before amending, so it fails early if another operation is in progress
+    let mut op = Operation::start(branch, steps, then)?;
+    let amended =
+        git::status(&["commit", "--quiet", "--amend", "--no-edit"]).context("git commit failed");
+    if !matches!(amended, Ok(true)) {
+        op.clear()?;
+        amended?;
+        return Err(eyre!("failed to amend the commit"));
+    }
+
+    op.run()?;
+    finish_restack(op)
+}
+
+/// Cherry-picks `commit`, from a branch of the stack that contains the current branch, onto the
+/// lower branch `to`, and rebases the branches above `to` onto it. Rebasing drops the original
+/// commit from its branch, since git skips commits whose changes are already upstream. Unless
+/// `no_push` is set, `to` and the branches above it are force-pushed afterwards, like `sync` does.
+pub fn backport(commit: &str, to: &str, no_push: bool) -> Result<Vec<SyncReport>, Error> {
+    let original = current_branch()?;
+    let main = main_branch()?;
+    let stack = whole_stack(&original)?;
+
+    let sha = rev_parse(commit)?;
+    let owners = absorb::commit_owners(&stack, &main)?;
+    let owner = owners
+        .get(&sha)
+        .ok_or_else(|| eyre!("{commit} isn't a commit of the stack"))?;
+    let lower = stack
+        .iter()
+        .position(|b| b == to)
+        .ok_or_else(|| eyre!("'{to}' isn't part of the stack"))?;
+    let upper = stack.iter().position(|b| b == owner).unwrap_or(lower);
+    if upper >= lower {
+        return Err(eyre!("'{to}' isn't below '{owner}', where {commit} is"));
+    }
+
+    // The steps are computed before `to` moves, since they need its old tip. `to` itself only
+    // gets a new commit on top, so it doesn't need to be rebased.
+    let mut steps = stack_steps(&stack[..=lower], &main, &main)?;
+    steps.remove(0);
+    let then = if no_push {
+        Then::Restack
+    } else {
+        let stack = stack[..=lower].iter().rev().cloned().collect();
+        Then::Sync { stack }
+    };
+    // Started before cherry-picking, so that after a conflict `stackbuddy continue` rebases the
+    // branches above
+    let mut op = Operation::start(original, steps, then)?;
+
+    checkout(to)?;
+    info!("Cherry-picking {commit} onto {to}...");
+    let success = git::status(&["cherry-pick", &sha]).context("git cherry-pick failed")?;
+    if !success {
+        return Err(eyre!(
+            "cherry-picking {commit} onto '{to}' stopped because of conflicts. Resolve them, run `git cherry-pick --continue` and then `stackbuddy continue`, or run `git cherry-pick --abort` and `stackbuddy abort`"
+        ));
+    }
+
+    op.run()?;
+    finish_restack(op)
+}
+
+/// Finishes an operation that only restacks, or restacks and pushes like `sync`
+fn finish_restack(op: Operation) -> Result<Vec<SyncReport>, Error> {
+    match op.then {
+        Then::Sync { .. } => finish_sync(op),
+        _ => {
+            checkout(&op.original)?;
+            op.clear()?;
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Pushes the branches of a `sync` once they're rebased
+fn finish_sync(op: Operation) -> Result<Vec<SyncReport>, Error> {
+    let Then::Sync { stack } = &op.then else {
+        return Err(eyre!("the operation in progress is not a sync"));
+    };
+    checkout(&op.original)?;
+    op.clear()?;
+
+    let progress = Progress::new("Pushing", stack.len());
+    let mut reports = Vec::new();
+    for branch in stack {
+        progress.start(format!("Pushing {branch}"));
+        let old_tip = op
+            .tips
+            .iter()
+            .find(|(b, _)| b == branch)
+            .map(|(_, tip)| tip);
+        let rebased = Some(&rev_parse(branch)?) != old_tip;
+        let pushed = force_push(std::slice::from_ref(branch));
+        reports.push(SyncReport {
+            branch: branch.clone(),
+            rebased,
+            pushed,
+        });
+        progress.finish_step();
+    }
+    Ok(reports)
+}
+
+/// What `push` did to a branch
+pub struct PushReport {
+    pub branch: String,
+    pub state: RemoteState,
+    /// None if the branch was skipped, because it was up to date or had diverged
+    pub pushed: Option<Result<(), Error>>,
+}
+
+impl std::fmt::Display for PushReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.pushed, self.state) {
+            (Some(Ok(())), state) => write!(f, "{}: {state}, pushed", self.branch),
+            (Some(Err(e)), state) => write!(f, "{}: {state}, push failed: {e}", self.branch),
+            (None, RemoteState::Diverged) => write!(
+                f,
+                "{}: diverged, skipped. The remote has commits that aren't here, pull them or \
+                 pass --force to overwrite them",
+                self.branch
+            ),
+            (None, state) => write!(f, "{}: {state}", self.branch),
+        }
+    }
+}
+
+/// Fetches from the remote and force-pushes every branch in the stack that ends in `branch`, with
+/// a lease. Branches whose remote has commits that were never seen locally are skipped, unless
+/// `force` is set, so that a teammate's work isn't overwritten.
+pub fn push(branch: String, force: bool) -> Result<Vec<PushReport>, Error> {
+    let stack = stack_from(branch)?;
+
+    info!("Fetching from {}...", config::load()?.remote());
+    push::fetch()?;
+
+    let mut reports = Vec::new();
+    for branch in stack.into_iter().rev() {
+        let state = remote_state(&branch)?;
+        let pushed = if state.safe_to_push() || (force && state == RemoteState::Diverged) {
+            info!("Pushing {branch}...");
+            Some(force_push(std::slice::from_ref(&branch)))
+        } else {
+            None
+        };
+        reports.push(PushReport {
+            branch,
+            state,
+            pushed,
+        });
+    }
+    Ok(reports)
+}
+
+/// Creates PRs for every branch in the stack that ends in `branch` that doesn't have one yet, each
+/// targeting its parent branch, and then updates the notes of the whole stack.
+pub fn submit(
+    forge: &dyn Forge,
+    branch: String,
+    note_format: NoteFormat,
+    draft: bool,
+) -> Result<(), Error> {
+    let main = main_branch()?;
+    let stack = stack_from(branch)?;
+
+    // Creating the PRs and then updating their notes is two steps per branch
+    let progress = Progress::new("Submitting", 2 * stack.len());
+    for i in (0..stack.len()).rev() {
+        let branch = &stack[i];
+        // Branches whose PR was closed or merged get a new one
+        if forge.pr_state(branch)? == Some(PrState::Open) {
+            progress.skip();
+            continue;
+        }
+
+        let base = stack.get(i + 1).unwrap_or(&main);
+        progress.start(format!("Creating PR for {branch} (base: {base})"));
+        push_branch(branch)?;
+        let pr = forge.create_pr(branch, base, draft)?;
+        progress.println(format!("Created {}", forge.pr_reference(&pr)));
+        progress.finish_step();
+    }
+
+    let options = NoteOptions::new(note_format)?;
+    for branch in &stack {
+        progress.start(format!("Updating notes for {branch}"));
+        update_note(forge, &stack, branch.clone(), &options, false)?;
+        progress.finish_step();
+    }
+    Ok(())
+}
+
+/// Creates `name` on top of the current branch, checks it out and records the current branch as
+/// its parent. With a `message`, the staged changes are committed to the new branch. With
+/// `push`, the branch is pushed, and with `open_pr` a draft PR targeting the parent is opened
+/// too.
+pub fn create(
+    forge: &dyn Forge,
+    name: &str,
+    message: Option<&str>,
+    push: bool,
+    open_pr: bool,
+) -> Result<(), Error> {
+    let parent = current_branch()?;
+    let success =
+        git::status(&["checkout", "--quiet", "-b", name]).context("git checkout failed")?;
+
+    if !success {
+        return Err(eyre!("failed to create '{name}'"));
+    }
+    set_parent(name, &parent)?;
+
+    if let Some(message) = message {
+        let success =
+            git::status(&["commit", "--quiet", "-m", message]).context("git commit failed")?;
+
+        if !success {
+            return Err(eyre!("failed to commit to '{name}'"));
+        }
+    }
+
+    if push || open_pr {
+        push_branch(name)?;
+    }
+    if open_pr {
+        let pr = forge.create_pr(name, &parent, true)?;
+        println!("Created {}", forge.pr_reference(&pr));
+    }
+    Ok(())
+}
+
+/// Creates `name` on top of `after` and moves the branches that were on top of `after` onto it,
+/// recording the new parents and rebasing them. With a `message`, the staged changes are committed
+/// to the new branch first. With `push` or `open_pr`, the new branch is pushed, a draft PR is
+/// opened for it with `open_pr`, and the PRs of the branches above are retargeted to it. The new
+/// branch is checked out at the end.
+pub fn insert(
+    forge: &dyn Forge,
+    name: &str,
+    after: &str,
+    message: Option<&str>,
+    push: bool,
+    open_pr: bool,
+) -> Result<(), Error> {
+    // Found before the new branch exists, since it would look like a child of `after` too
+    let above = children(after)?;
+    let old_tip = rev_parse(after)?;
+
+    let success =
+        git::status(&["checkout", "--quiet", "-b", name, after]).context("git checkout failed")?;
+    if !success {
+        return Err(eyre!("failed to create '{name}'"));
+    }
+    set_parent(name, after)?;
+
+    if let Some(message) = message {
+        let success =
+            git::status(&["commit", "--quiet", "-m", message]).context("git commit failed")?;
+        if !success {
+            return Err(eyre!("failed to commit to '{name}'"));
+        }
+    }
+
+    for child in &above {
+        set_parent(child, name)?;
+        let stack: Vec<String> = stack_from(top_of(child)?)?
+            .into_iter()
+            .take_while(|b| b != name)
+            .collect();
+        rebase_stack(&stack, name, &old_tip)?;
+    }
+    checkout(name)?;
+
+    if push || open_pr {
+        push_branch(name)?;
+    }
+    if open_pr {
+        let pr = forge.create_pr(name, after, true)?;
+        println!("Created {}", forge.pr_reference(&pr));
+    }
+    for child in &above {
+        if forge.pr_state(child)? != Some(PrState::Open) {
+            continue;
+        }
+        if push || open_pr {
+            info!("Retargeting the PR of {child} to {name}...");
+            forge.set_pr_base(child, name)?;
+        } else {
+            info!(
+                "The PR of {child} still targets {after}. Push {name} and run `stackbuddy retarget` to change it"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Changes the base of every PR in the stack that ends in `branch` that doesn't target its parent
+/// branch
+pub fn retarget(forge: &dyn Forge, branch: String, dry_run: bool) -> Result<(), Error> {
+    retarget_stack(forge, &stack_from(branch)?, dry_run)
+}
+
+/// Changes the base of every PR in `stack` (ordered from top to bottom) that doesn't target the
+/// branch below it
+fn retarget_stack(forge: &dyn Forge, stack: &[String], dry_run: bool) -> Result<(), Error> {
+    let main = main_branch()?;
+    let prs = forge.open_prs()?;
+
+    for (i, branch) in stack.iter().enumerate() {
+        let Some(pr) = prs.get(branch) else {
+            continue;
+        };
+        let parent = stack.get(i + 1).unwrap_or(&main);
+        if &pr.base == parent {
+            continue;
+        }
+
+        let pr_ref = forge.pr_reference(&pr.number);
+        info!(
+            "Retargeting {pr_ref} ({branch}) from {} to {parent}...",
+            pr.base
+        );
+        if !dry_run {
+            forge.set_pr_base(branch, parent)?;
+        }
+    }
+    Ok(())
+}
+
+/// What [`watch`] compares between polls to tell whether the stack changed
+#[derive(PartialEq, Eq)]
+struct WatchSnapshot {
+    /// Every branch of the stack with the commit it points to
+    tips: Vec<(String, String)>,
+    /// The branches of the stack with an open PR, with its number, base and title
+    prs: Vec<(String, String, String, String)>,
+}
+
+impl WatchSnapshot {
+    fn of_stack(forge: &dyn Forge, stack: &[String]) -> Result<Self, Error> {
+        let tips = stack
+            .iter()
+            .map(|b| Ok((b.clone(), rev_parse(b)?)))
+            .collect::<Result<_, Error>>()?;
+        let open_prs = forge.open_prs()?;
+        let prs = stack
+            .iter()
+            .filter_map(|b| {
+                let pr = open_prs.get(b)?;
+                Some((
+                    b.clone(),
+                    pr.number.clone(),
+                    pr.base.clone(),
+                    pr.title.clone(),
+                ))
+            })
+            .collect();
+        Ok(Self { tips, prs })
+    }
+}
+
+/// Keeps the PRs of the stack that contains `branch` up to date until it's interrupted. The forge
+/// and the local branches are checked every `interval`, and whenever a PR was merged, retargeted
+/// or renamed, or a branch moved, the PRs are retargeted to their parents like [`retarget`] does
+/// and their notes are updated. Errors are printed and retried on the next check, so a flaky
+/// connection doesn't stop it.
+pub fn watch(
+    forge: &dyn Forge,
+    branch: &str,
+    interval: Duration,
+    options: &NoteOptions,
+    jobs: usize,
+) -> Result<(), Error> {
+    let mut previous: Option<WatchSnapshot> = None;
+    loop {
+        forge.refresh();
+        match watch_check(forge, branch, previous.as_ref(), options, jobs) {
+            Ok(snapshot) => previous = Some(snapshot),
+            Err(e) => eprintln!("{} {e:#}", output::error("Error:")),
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// One check of [`watch`]: updates the PRs of the stack that contains `branch` unless it looks the
+/// same as in the `previous` check, and returns how it looks now
+fn watch_check(
+    forge: &dyn Forge,
+    branch: &str,
+    previous: Option<&WatchSnapshot>,
+    options: &NoteOptions,
+    jobs: usize,
+) -> Result<WatchSnapshot, Error> {
+    let stack = whole_stack(branch)?;
+    let snapshot = WatchSnapshot::of_stack(forge, &stack)?;
+    if previous == Some(&snapshot) {
+        return Ok(snapshot);
+    }
+    if previous.is_some() {
+        info!("The stack changed, updating its PRs...");
+    }
+    // The branches of merged PRs stay around until they're cleaned, but the PRs above them should
+    // target the branch below instead
+    let open_prs = forge.open_prs()?;
+    let mut unmerged = Vec::new();
+    for b in &stack {
+        if open_prs.contains_key(b) || forge.pr_state(b)? != Some(PrState::Merged) {
+            unmerged.push(b.clone());
+        }
+    }
+    retarget_stack(forge, &unmerged, false)?;
+    let results = update_notes(forge, &stack, options, false, jobs, false);
+    for (b, result) in stack.iter().zip(results) {
+        if let Err(e) = result {
+            eprintln!("{} {e}", output::error(&format!("Error in branch {b}:")));
+        }
+    }
+    // Retargeting changed the bases, which shouldn't count as a change on the next check
+    WatchSnapshot::of_stack(forge, &stack)
+}
+
+/// Marks the PRs of the stack that ends in `branch` whose parent isn't merged yet as drafts, and
+/// the others as ready for review, so they can't be merged out of order. Running it again after a
+/// parent lands makes the PR above it ready.
+pub fn guard(forge: &dyn Forge, branch: String, dry_run: bool) -> Result<(), Error> {
+    let main = main_branch()?;
+    let stack = stack_from(branch)?;
+    let prs = forge.open_prs()?;
+
+    for (i, branch) in stack.iter().enumerate() {
+        let Some(pr) = prs.get(branch) else {
+            continue;
+        };
+        let parent = stack.get(i + 1).unwrap_or(&main);
+        let draft = parent != &main && forge.pr_state(parent)? != Some(PrState::Merged);
+        if pr.draft == draft {
+            continue;
+        }
+
+        let pr_ref = forge.pr_reference(&pr.number);
+        if draft {
+            info!("Marking {pr_ref} ({branch}) as a draft until {parent} is merged...");
+        } else {
+            info!("Marking {pr_ref} ({branch}) as ready for review...");
+        }
+        if !dry_run {
+            forge.set_pr_draft(branch, draft)?;
+        }
+    }
+    Ok(())
+}
+
+/// Requests reviews from `users` on every open PR of the stack that ends in `branch`, and also
+/// assigns them if `assign` is set. With `sync`, the reviewers (and assignees) of the PR at the
+/// bottom of the stack are copied to the PRs above it as well. People who were already asked are
+/// skipped.
+pub fn request_reviewers(
+    forge: &dyn Forge,
+    branch: String,
+    users: &[String],
+    sync: bool,
+    assign: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let stack = stack_from(branch)?;
+    let prs = forge.open_prs()?;
+    let stack: Vec<&String> = stack.iter().filter(|b| prs.contains_key(*b)).collect();
+
+    let mut reviewers = users.to_vec();
+    let mut assignees = if assign { users.to_vec() } else { Vec::new() };
+    if sync {
+        if let Some(bottom) = stack.last() {
+            let participants = forge.pr_participants(bottom)?;
+            reviewers.extend(participants.reviewers);
+            if assign {
+                assignees.extend(participants.assignees);
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    reviewers.retain(|user| seen.insert(user.clone()));
+    let mut seen = HashSet::new();
+    assignees.retain(|user| seen.insert(user.clone()));
+
+    // The stack goes from top to bottom, but requests are made from the bottom up, in the order
+    // the PRs will be reviewed
+    for branch in stack.into_iter().rev() {
+        let pr_ref = forge.pr_reference(&prs[branch].number);
+        let current = forge.pr_participants(branch)?;
+
+        let missing: Vec<String> = reviewers
+            .iter()
+            .filter(|user| !current.reviewers.contains(user))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            info!(
+                "Requesting reviews from {} on {pr_ref} ({branch})...",
+                missing.join(", ")
+            );
+            if !dry_run {
+                forge.add_pr_reviewers(branch, &missing)?;
+            }
+        }
+
+        let missing: Vec<String> = assignees
+            .iter()
+            .filter(|user| !current.assignees.contains(user))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            info!("Assigning {pr_ref} ({branch}) to {}...", missing.join(", "));
+            if !dry_run {
+                forge.add_pr_assignees(branch, &missing)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds `label` to every open PR of `stack`, and removes it from the open PRs that have it but
+/// aren't part of the stack anymore, so the label always finds the PRs of the stack
+pub fn label_stack(
+    forge: &dyn Forge,
+    stack: &[String],
+    label: &str,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let prs = forge.open_prs()?;
+    let mut branches: Vec<&String> = prs.keys().collect();
+    branches.sort();
+
+    for branch in branches {
+        let pr = &prs[branch];
+        let labeled = pr.labels.iter().any(|l| l == label);
+        let in_stack = stack.contains(branch);
+        if labeled == in_stack {
+            continue;
+        }
+
+        let pr_ref = forge.pr_reference(&pr.number);
+        if in_stack {
+            info!("Labeling {pr_ref} ({branch}) with {label}...");
+            if !dry_run {
+                forge.add_pr_label(branch, label)?;
+            }
+        } else {
+            info!("Removing {label} from {pr_ref} ({branch}), which left the stack...");
+            if !dry_run {
+                forge.remove_pr_label(branch, label)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renders a table with the PR, CI and review state of every branch in `stack`, followed by the
+/// stats of each branch if `stats` are given
+pub fn status_table(
+    forge: &dyn Forge,
+    stack: &[String],
+    stats: Option<&[BranchStats]>,
+) -> Result<String, Error> {
+    let prs = forge.open_prs()?;
+
+    let mut header = vec![
+        "Branch",
+        "PR",
+        "Title",
+        "Draft",
+        "Mergeable",
+        "Checks",
+        "Review",
+    ];
+    if stats.is_some() {
+        header.extend(STATS_HEADER);
+    }
+    // With --offline, the columns that can change on the forge are marked as out of date
+    let stale = forge.staleness();
+    let header: Vec<String> = header
+        .into_iter()
+        .map(|column| match column {
+            "Draft" | "Mergeable" | "Checks" | "Review" if stale.is_some() => format!("{column}*"),
+            column => column.to_string(),
+        })
+        .collect();
+    let current = current_branch().ok();
+    let mut rows = vec![header];
+    for (i, branch) in stack.iter().enumerate() {
+        let name = output::branch(branch, current.as_deref());
+        let mut row = match prs.get(branch) {
+            Some(pr) => vec![
+                name,
+                forge.pr_reference(&pr.number),
+                truncate(&pr.title, 40),
+                if pr.draft { "yes" } else { "no" }.to_string(),
+                match pr.mergeable.as_str() {
+                    "CONFLICTING" => output::error("conflicting"),
+                    mergeable => mergeable.to_lowercase(),
+                },
+                output::checks(&pr.checks),
+                output::review(&pr.review),
+            ],
+            None => std::iter::once(name)
+                .chain(std::iter::repeat_n(output::dim("-"), 6))
+                .collect(),
+        };
+        if let Some(stat) = stats.and_then(|stats| stats.get(i)) {
+            row.extend(stat.cells());
+        }
+        rows.push(row);
+    }
+    let mut table = render_table(&rows);
+    if let Some(age) = stale {
+        table.push_str(&stale_footer(age));
+    }
+    Ok(table)
+}
+
+/// The line under the output of `--offline` that explains what the fields marked with `*` are
+fn stale_footer(age: Duration) -> String {
+    let footer = format!("* from the cache, as of {}", output::ago(age));
+    format!("\n\n{}", output::dim(&footer))
+}
+
+/// Lines up the cells of `rows` in columns, two spaces apart
+fn render_table(rows: &[Vec<String>]) -> String {
+    let mut widths = vec![0; rows.iter().map(Vec::len).max().unwrap_or(0)];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(output::width(cell));
+        }
+    }
+
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                // Padded by hand, since the escape sequences of colors take no space
+                .map(|(cell, &width)| format!("{cell}{}", " ".repeat(width - output::width(cell))))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    lines.join("\n")
+}
+
+pub(crate) fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let truncated: String = text.chars().take(max - 1).collect();
+    format!("{truncated}…")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git::{GitOutput, Mock};
+    use std::sync::Arc;
+
+    /// Answers like a repository whose main branch is `main`, where `feature-b` was stacked on
+    /// `feature-a` with `stackbuddy track`
+    fn repository(args: &[&str]) -> GitOutput {
+        match args {
+            ["config", "--get-regexp", ..] => GitOutput::ok("stackbuddy.mainbranch main\n"),
+            ["config", "--get", "branch.feature-b.stackbuddy-parent"] => {
+                GitOutput::ok("feature-a\n")
+            }
+            ["config", "--get", "branch.feature-a.stackbuddy-parent"] => GitOutput::ok("main\n"),
+            ["config", _, _] => GitOutput::ok(""),
+            _ => GitOutput::failed("not mocked"),
+        }
+    }
+
+    /// The calls that read or write the parent of a branch, leaving out the ones that load the
+    /// config, which only the first test to need it makes
+    fn parent_calls(mock: &Mock<fn(&[&str]) -> GitOutput>) -> Vec<Vec<String>> {
+        mock.calls()
+            .into_iter()
+            .filter(|call| call.iter().any(|arg| arg.ends_with(".stackbuddy-parent")))
+            .collect()
+    }
+
+    #[test]
+    fn stack_from_follows_tracked_parents() {
+        let mock = Arc::new(Mock::new(repository as fn(&[&str]) -> GitOutput));
+        let stack = git::with_backend(mock.clone(), || stack_from("feature-b".to_string()));
+
+        assert_eq!(stack.unwrap(), vec!["feature-b", "feature-a"]);
+        assert_eq!(
+            parent_calls(&mock),
+            vec![
+                vec!["config", "--get", "branch.feature-b.stackbuddy-parent"],
+                vec!["config", "--get", "branch.feature-a.stackbuddy-parent"],
+            ]
+        );
+    }
+
+    #[test]
+    fn set_parent_writes_the_git_config() {
+        let mock = Arc::new(Mock::new(repository as fn(&[&str]) -> GitOutput));
+        git::with_backend(mock.clone(), || set_parent("feature-b", "feature-a")).unwrap();
+
+        assert_eq!(
+            parent_calls(&mock),
+            vec![vec![
+                "config",
+                "branch.feature-b.stackbuddy-parent",
+                "feature-a"
+            ]]
+        );
+    }
+
+    /// Answers like a repository where `a` and `b` were tracked as each other's parent
+    fn cycle(args: &[&str]) -> GitOutput {
+        match args {
+            ["config", "--get-regexp", ..] => GitOutput::ok("stackbuddy.mainbranch main\n"),
+            ["for-each-ref", ..] => GitOutput::ok("a 1111\nb 2222\nmain 3333\n"),
+            ["config", "--get", "branch.a.stackbuddy-parent"] => GitOutput::ok("b\n"),
+            ["config", "--get", "branch.b.stackbuddy-parent"] => GitOutput::ok("a\n"),
+            ["config", _, _] => GitOutput::ok(""),
+            _ => GitOutput::failed("not mocked"),
+        }
+    }
+
+    #[test]
+    fn walks_up_the_stack_fail_on_parent_cycles() {
+        let mock = Arc::new(Mock::new(cycle as fn(&[&str]) -> GitOutput));
+        git::with_backend(mock, || {
+            for result in [
+                top_of("a").map(|_| ()),
+                descendants("a").map(|_| ()),
+                StackNode::from_branch("a".to_string()).map(|_| ()),
+                stack_from("a".to_string()).map(|_| ()),
+            ] {
+                let error = result.unwrap_err().to_string();
+                assert!(error.contains("go around in a cycle"), "{error}");
+            }
+        });
+    }
+
+    #[test]
+    fn set_parent_fails_when_git_config_fails() {
+        let mock = Arc::new(Mock::new(|_: &[&str]| {
+            GitOutput::failed("error: could not lock")
+        }));
+        let result = git::with_backend(mock, || set_parent("feature-b", "feature-a"));
+        assert!(result.is_err());
     }
 }
@@ -1,11 +1,142 @@
 use clap::{Parser, Subcommand};
-use eyre::Error;
-use stackbuddy::NoteFormat;
+use eyre::{eyre, Error};
+use serde::Serialize;
+use serde_json::json;
+use stackbuddy::{
+    config::Config,
+    forge::{Backend, CacheMode, Cached, Forge, ForgeKind, Journaled, MergeStrategy, PrState},
+    hooks, info,
+    journal::Journal,
+    note::{NoteAction, NoteMode, NoteReferences, NoteUpdate},
+    output::{self, ColorChoice},
+    porcelain,
+    push::RemoteState,
+    repo::Repo,
+    BranchStats, Discovery, NoteFormat, NoteOptions, ParentStrategy, Stack, StackEntry, StackNode,
+    StackSummary, UpdateReport, Wait,
+};
+use std::{
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
 
 /// stackbuddy helps you manage your PR stacks
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Runs as if stackbuddy was started in PATH instead of the current directory, like git -C
+    #[arg(short = 'C', global = true, value_name = "PATH")]
+    directory: Option<PathBuf>,
+
+    /// The service that hosts the PRs. If not given, it's detected from the URL of the remote
+    #[arg(long, global = true, value_enum)]
+    forge: Option<ForgeKind>,
+
+    /// How to talk to the forge. Defaults to the forge's CLI
+    #[arg(long, global = true, value_enum)]
+    backend: Option<Backend>,
+
+    /// The remote that branches are pushed to and PRs are opened against. Defaults to `remote` in
+    /// the config, or origin
+    #[arg(long, global = true)]
+    remote: Option<String>,
+
+    /// The branch stacks are based on, like a release or long-lived feature branch. Defaults to
+    /// `main-branch` in the config, or the default branch of the remote
+    #[arg(long, global = true)]
+    base: Option<String>,
+
+    /// A template for notes in the custom format. See the README for its syntax
+    #[arg(long, global = true)]
+    template: Option<PathBuf>,
+
+    /// Shows the titles of the PRs in notes, next to their numbers
+    #[arg(long, global = true, default_value_t = false)]
+    titles: bool,
+
+    /// Shows where each PR is in the stack in notes, like "PR 2 of 5"
+    #[arg(long, global = true, default_value_t = false)]
+    position: bool,
+
+    /// Links each PR in notes to the diff between its branch and its parent
+    #[arg(long, global = true, default_value_t = false)]
+    compare: bool,
+
+    /// Shows the status of the CI checks and the review decision of each open PR in notes
+    #[arg(long, global = true, default_value_t = false)]
+    badges: bool,
+
+    /// How notes refer to PRs. Defaults to `note-references` in the config, or the PR number
+    #[arg(long, global = true, value_enum)]
+    references: Option<NoteReferences>,
+
+    /// How the branches of a stack are found, for the commands that only read it. Defaults to the
+    /// one in the config, or log
+    #[arg(long, global = true, value_enum)]
+    discovery: Option<Discovery>,
+
+    /// How the parent of a branch is guessed when none was recorded with track. Defaults to
+    /// `parent-strategy` in the config, or auto. With best, parent also prints how confident the
+    /// guess is
+    #[arg(long, global = true, value_enum)]
+    strategy: Option<ParentStrategy>,
+
+    /// When HEAD is detached, uses the closest branch that contains it as the current branch
+    #[arg(long, global = true, default_value_t = false)]
+    guess: bool,
+
+    /// Prints the output of parent, stack, note, update-notes and check-notes as JSON
+    #[arg(long, global = true, default_value_t = false)]
+    json: bool,
+
+    /// Prints the output of parent, stack, status and update-notes in a stable format for
+    /// scripts, which never changes for a given version. Defaults to version 1
+    #[arg(
+        long,
+        global = true,
+        value_name = "VERSION",
+        num_args = 0..=1,
+        default_missing_value = "1",
+        conflicts_with = "json"
+    )]
+    porcelain: Option<u32>,
+
+    /// When to color the output. Colors are also turned off by setting NO_COLOR, unless this is
+    /// always
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t)]
+    color: ColorChoice,
+
+    /// Doesn't use or save the PRs kept in .git/stackbuddy/cache.json between invocations
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        conflicts_with = "refresh"
+    )]
+    no_cache: bool,
+
+    /// Fetches the PRs from the forge again instead of using the ones that note, status, stack
+    /// and other commands that only read them kept in .git/stackbuddy/cache.json
+    #[arg(long, global = true, default_value_t = false)]
+    refresh: bool,
+
+    /// Uses the PRs kept in .git/stackbuddy/cache.json, however old, without reaching the forge.
+    /// Works with the commands that only read PRs, like note, status, stack and graph
+    #[arg(long, global = true, default_value_t = false, conflicts_with_all = ["refresh", "no_cache"])]
+    offline: bool,
+
+    /// Only prints the output of commands, and not what they're doing or progress bars
+    #[arg(short, long, global = true, default_value_t = false)]
+    quiet: bool,
+
+    /// Logs every git and forge command that is run and how long it took. Pass it twice to also
+    /// log what the commands printed
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -18,17 +149,252 @@ enum Command {
         branch: Option<String>,
     },
 
-    /// Prints the stack of branches that ends in the current branch
+    /// Prints the branches whose parent is the given branch
+    Children {
+        /// The branch to find the children of. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// Prints every branch above it instead, parents before their children
+        #[clap(short, long, default_value_t = false)]
+        all: bool,
+    },
+
+    /// Checks out the child of the current branch
+    Up,
+
+    /// Checks out the parent of the current branch
+    Down,
+
+    /// Checks out the branch at the top of the current stack
+    Top,
+
+    /// Checks out the branch at the bottom of the current stack
+    Bottom,
+
+    /// Checks out a branch, or lets you pick one of the stacked branches by typing part of its name
+    /// or of the title of its PR
+    Checkout {
+        /// The branch to check out, or a PR reference like #12. If not given, a picker is shown
+        branch: Option<String>,
+    },
+
+    /// Creates a branch on top of the current one and records the current branch as its parent
+    Create {
+        /// The name of the new branch
+        name: String,
+
+        /// Commits the staged changes to the new branch with this message
+        #[clap(short, long)]
+        message: Option<String>,
+
+        /// Pushes the new branch
+        #[clap(short, long, default_value_t = false)]
+        push: bool,
+
+        /// Pushes the new branch and opens a draft PR targeting the current branch. GitHub
+        /// doesn't accept PRs without commits, so this is usually combined with --message
+        #[clap(long, default_value_t = false)]
+        pr: bool,
+    },
+
+    /// Creates a branch in the middle of a stack, on top of another branch, and moves the branches
+    /// that were on top of that one onto the new branch
+    Insert {
+        /// The name of the new branch
+        name: String,
+
+        /// The branch the new one goes on top of
+        #[clap(short, long)]
+        after: String,
+
+        /// Commits the staged changes to the new branch with this message
+        #[clap(short, long)]
+        message: Option<String>,
+
+        /// Pushes the new branch and retargets the PRs above it
+        #[clap(short, long, default_value_t = false)]
+        push: bool,
+
+        /// Also opens a draft PR for the new branch, targeting the branch it goes on top of
+        #[clap(long, default_value_t = false)]
+        pr: bool,
+    },
+
+    /// Records the parent of a branch, so it doesn't have to be guessed from the git log anymore
+    Track {
+        /// The branch to record the parent of. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// The parent branch. If not given, it's guessed with --strategy
+        #[clap(short, long)]
+        parent: Option<String>,
+    },
+
+    /// Guesses the parents of a branch and of every branch below it, lets you fix them in the
+    /// editor and records them all, like running track on each of them
+    Adopt {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// Records the guessed parents without opening the editor
+        #[clap(short, long, default_value_t = false)]
+        yes: bool,
+    },
+
+    /// Prints the stack as json, with the parents and PRs of its branches, to be tracked elsewhere
+    /// with `stackbuddy import`
+    Export {
+        /// Any branch of the stack. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Records the parents of the branches tracked by another tool, or of a stack exported with
+    /// `stackbuddy export`
+    Import {
+        /// Where to import from: `graphite`, which reads the metadata Graphite keeps in
+        /// refs/branch-metadata/, or a file written by `stackbuddy export`, or - for stdin
+        source: String,
+
+        /// Only prints the parents that would be recorded
+        #[clap(short, long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// Prints the stack of branches that ends in the current branch, or every branch of the stack
+    /// as a tree with --tree
     Stack {
         /// The branch to start the stack from. If not given, the current branch is used
         branch: Option<String>,
+
+        /// Finds the stack both from the git log and from the bases of the PRs, and fails if they
+        /// disagree
+        #[clap(long, default_value_t = false)]
+        check: bool,
+
+        /// Prints every branch of the stack as a tree, including the ones on other forks, from
+        /// its bottom branch up
+        #[clap(long, default_value_t = false)]
+        tree: bool,
+
+        /// Shows how many commits each branch is ahead of its parent and behind its remote
+        /// branch, and how many files and lines it changes
+        #[clap(long, default_value_t = false, conflicts_with = "tree")]
+        stat: bool,
+
+        /// Names the stack, so it can be told apart from the others in `stackbuddy stacks`
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Lists every stack in the repository, with its name, the branches at its top, how many
+    /// branches it has and the state of their PRs
+    Stacks,
+
+    /// Draws the stack as a tree, with how many commits each branch is ahead of and behind its
+    /// parent, the state of its PR, and a * on the current branch
+    Graph {
+        /// Any branch of the stack. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Shows the combined changes of the stack relative to the main branch, or the changes of a
+    /// single branch relative to its parent
+    Diff {
+        /// Only shows the changes of this branch relative to its parent. If not given, the changes
+        /// of the whole stack that contains the current branch are shown
+        branch: Option<String>,
+
+        /// Shows which files changed and how much, instead of the full diff
+        #[clap(long, default_value_t = false)]
+        stat: bool,
+    },
+
+    /// Changes the base of every PR in the stack that doesn't target its parent branch
+    Retarget {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+
+        #[clap(short, long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// Marks the PRs of the stack whose parent PR isn't merged yet as drafts, and the rest as
+    /// ready for review, so they can't be merged out of order
+    Guard {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+
+        #[clap(short, long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// Requests reviews from the same people on every PR in the stack
+    Reviewers {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// The users to request reviews from
+        #[arg(long, num_args = 1.., value_name = "USER")]
+        add: Vec<String>,
+
+        /// Also copy the reviewers of the PR at the bottom of the stack to the PRs above it
+        #[clap(long, default_value_t = false)]
+        sync: bool,
+
+        /// Also assign the PRs to the same people
+        #[clap(long, default_value_t = false)]
+        assign: bool,
+
+        #[clap(short, long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// Shows the PR, CI and review state of every branch in the stack
+    Status {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// Also shows how many commits each branch is ahead of its parent and behind its remote
+        /// branch, and how many files and lines it changes
+        #[clap(long, default_value_t = false)]
+        stat: bool,
+    },
+
+    /// Prints the branches of the stack with their PRs, from the bottom to the top: the branch, the
+    /// number, title, state and URL of its PR, separated by tabs
+    Prs {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Opens the PR of a branch of the stack in the browser
+    Open {
+        /// The branch, a PR reference like #12, or the position of the branch in the stack,
+        /// counting from 1 at the bottom. If not given, the current branch is used
+        target: Option<String>,
+
+        /// Opens the PR of every branch in the stack
+        #[clap(long, default_value_t = false, conflicts_with_all = ["target", "next", "prev"])]
+        all: bool,
+
+        /// Opens the PR of the branch above the current one
+        #[clap(long, default_value_t = false, conflicts_with_all = ["target", "prev"])]
+        next: bool,
+
+        /// Opens the PR of the branch below the current one
+        #[clap(long, default_value_t = false, conflicts_with = "target")]
+        prev: bool,
+
+        /// Prints the URLs instead of opening them
+        #[clap(long, default_value_t = false)]
+        print: bool,
     },
 
     /// Generates a [!Note] block for the PR of the given branch
     Note {
-        /// The format to display the note in
-        #[arg(value_enum, default_value_t = NoteFormat::default())]
-        format: NoteFormat,
+        /// The format to display the note in. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
 
         branch: Option<String>,
     },
@@ -36,51 +402,1559 @@ enum Command {
     /// Updates all PRs in a stack, starting from the given branch, with a note. For more
     /// information about notes, see stackbuddy note --help
     UpdateNotes {
-        /// The format to display the notes in
-        #[arg(value_enum, default_value_t = NoteFormat::default())]
-        format: NoteFormat,
+        /// The format to display the notes in. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        branch: Option<String>,
+
+        #[clap(short, long, default_value_t = false)]
+        dry_run: bool,
+
+        /// How many PRs to update at the same time
+        #[clap(short, long, default_value_t = 4)]
+        jobs: usize,
 
+        /// Whether the notes go in the PR bodies or in comments. Defaults to the one in the config
+        #[arg(long, value_enum)]
+        mode: Option<NoteMode>,
+
+        /// Also runs guard, keeping PRs as drafts until their parent is merged
+        #[arg(long, default_value_t = false)]
+        auto_draft: bool,
+
+        /// Adds this label to every PR of the stack, and removes it from PRs that left it, e.g.
+        /// stack:my-feature
+        #[arg(long)]
+        label: Option<String>,
+
+        /// Stops updating notes as soon as one fails, instead of updating the rest first. Either
+        /// way, it exits with an error if any of them failed
+        #[arg(long, default_value_t = false, conflicts_with = "keep_going")]
+        fail_fast: bool,
+
+        /// Updates the rest of the notes when one fails, which is the default
+        #[arg(long, default_value_t = false)]
+        keep_going: bool,
+
+        /// Only updates the PRs of these branches. The notes still list the whole stack
+        #[arg(long, num_args = 1.., value_name = "BRANCH")]
+        only: Vec<String>,
+
+        /// Opens the editor to pick which of the PRs that would change get updated
+        #[arg(long, default_value_t = false)]
+        pick: bool,
+
+        /// Updates the PRs without showing what will change and asking first. Without it,
+        /// update-notes fails when stdin isn't a terminal, so scripts need to pass it
+        #[clap(short, long, default_value_t = false)]
+        yes: bool,
+    },
+
+    /// Checks that the notes of every PR in the stack are up to date, without editing them. Exits
+    /// with an error if any of them is stale, which is useful as a CI step
+    CheckNotes {
+        /// The format the notes should be in. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        branch: Option<String>,
+
+        /// How many PRs to check at the same time
+        #[clap(short, long, default_value_t = 4)]
+        jobs: usize,
+
+        /// Whether the notes go in the PR bodies or in comments. Defaults to the one in the config
+        #[arg(long, value_enum)]
+        mode: Option<NoteMode>,
+    },
+
+    /// Keeps the PRs of the stack up to date until it's interrupted: every few seconds, checks
+    /// whether a PR was merged, retargeted or renamed or a branch moved, and if so, retargets the
+    /// PRs and updates their notes
+    Watch {
+        /// The format to display the notes in. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        /// A branch of the stack. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// How many seconds to wait between checks
+        #[arg(long, default_value_t = 60, value_name = "SECONDS")]
+        interval: u64,
+
+        /// How many PRs to update at the same time
+        #[clap(short, long, default_value_t = 4)]
+        jobs: usize,
+    },
+
+    /// Removes the stackbuddy notes from every PR in the stack
+    CleanNotes {
+        branch: Option<String>,
+
+        /// Only remove the notes of PRs that were merged or closed
+        #[clap(long, default_value_t = false)]
+        merged: bool,
+    },
+
+    /// Updates the notes of every PR in the stack from GitHub Actions. The stack is found by
+    /// following the bases of the open PRs, so only the pushed branch needs to be checked out, and
+    /// the GitHub API is called with the token in GITHUB_TOKEN
+    CiUpdate {
+        /// The format to display the notes in. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        /// The branch that was pushed. Defaults to the one in GITHUB_HEAD_REF or GITHUB_REF
+        branch: Option<String>,
+
+        /// How many PRs to update at the same time
+        #[clap(short, long, default_value_t = 4)]
+        jobs: usize,
+
+        /// Whether the notes go in the PR bodies or in comments. Defaults to the one in the config
+        #[arg(long, value_enum)]
+        mode: Option<NoteMode>,
+    },
+
+    /// Rebases every branch in the stack onto its parent, starting from the bottom of the stack
+    Restack {
+        /// The branch at the top of the stack. If not given, the current branch is used
         branch: Option<String>,
+    },
 
+    /// Moves the staged changes into the branches of the stack that last changed the same lines, as
+    /// fixup commits, and restacks the branches above them. Changes that can't be traced to a
+    /// single commit of the stack stay staged
+    Absorb {
+        /// Only shows where the changes would go
         #[clap(short, long, default_value_t = false)]
         dry_run: bool,
     },
+
+    /// Amends the tip commit of the current branch with the staged changes, rebases the branches
+    /// above it onto the amended commit and force-pushes them
+    Amend {
+        /// Doesn't push the branches after rebasing them
+        #[clap(long, default_value_t = false)]
+        no_push: bool,
+    },
+
+    /// Moves a commit from a branch of the stack to a branch below it, and rebases the branches in
+    /// between, which drops the commit from its original branch. The branches are force-pushed
+    /// afterwards
+    Backport {
+        /// The commit to move
+        commit: String,
+
+        /// The branch to move it to
+        #[arg(long)]
+        to: String,
+
+        /// Doesn't push the branches after rebasing them
+        #[clap(long, default_value_t = false)]
+        no_push: bool,
+    },
+
+    /// Runs a command on every branch in the stack, from the bottom to the top, and then checks
+    /// out the original branch again. By default, it stops at the first branch where it fails
+    Exec {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        #[clap(short, long)]
+        branch: Option<String>,
+
+        /// Stop at the first branch where the command fails. This is the default
+        #[clap(long, default_value_t = false, conflicts_with = "keep_going")]
+        fail_fast: bool,
+
+        /// Run the command on every branch, even after it fails on one
+        #[clap(long, default_value_t = false)]
+        keep_going: bool,
+
+        /// The command to run, e.g. `stackbuddy exec -- cargo test`
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Moves a branch and every branch above it onto another parent, retargeting its PR and
+    /// updating the notes of the stacks it left and joined
+    Move {
+        /// The format of the notes that are updated afterwards. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        /// The branch to move. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// The new parent of the branch
+        #[clap(short, long)]
+        onto: String,
+    },
+
+    /// Opens the stack in the editor, from the bottom to the top, and rebases its branches into
+    /// the order they were left in, retargeting the PRs and updating the notes
+    Reorder {
+        /// The format of the notes that are updated afterwards. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Opens the commits of a branch in the editor to split it into several branches stacked on
+    /// each other, one for every `branch <name>` line added after a commit
+    Split {
+        /// The branch to split. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// Pushes the new branches and opens a draft PR for each of them
+        #[clap(long, default_value_t = false)]
+        pr: bool,
+    },
+
+    /// Renames a branch locally and on the remote, keeping its PR, the parents of the branches
+    /// above it and the notes of the stack up to date
+    Rename {
+        old: String,
+        new: String,
+
+        /// The format of the notes that are updated afterwards. Defaults to the one in the config
+        #[arg(long, value_enum)]
+        format: Option<NoteFormat>,
+    },
+
+    /// Folds a branch into its parent, moving the branches on top of it onto the parent and
+    /// closing its PR
+    Fold {
+        /// The format of the notes that are updated afterwards. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        /// The branch to fold. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Force-pushes every branch in the stack with a lease, skipping the ones whose remote has
+    /// commits that were never seen locally, like ones a teammate pushed
+    Push {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+
+        /// Also push the branches whose remote has diverged, overwriting what's there
+        #[clap(short, long, default_value_t = false)]
+        force: bool,
+    },
+
+    /// Fetches from the remote, updates the main branch, rebases the stack onto it and force-pushes
+    /// every branch in the stack
+    Sync {
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Resumes a restack, sync, move or reorder that stopped because of conflicts, after they're
+    /// resolved and staged
+    Continue,
+
+    /// Stops a restack, sync, move or reorder that stopped because of conflicts, and moves every
+    /// branch back to where it was before it started
+    Abort,
+
+    /// Merges the bottom PR of the stack, or adds it to the merge queue with --queue, deletes its
+    /// branch and moves the rest of the stack onto the main branch, updating their notes
+    Land {
+        /// How to merge the PR. Ignored with --queue, where the queue decides
+        #[arg(short, long, value_enum, default_value_t = MergeStrategy::default())]
+        strategy: MergeStrategy,
+
+        /// Adds the PR to the merge queue instead of merging it. Run it again once the PR is
+        /// merged to move the rest of the stack, or pass --wait
+        #[clap(long, default_value_t = false)]
+        queue: bool,
+
+        /// Waits for the queued PR to be merged, checking every --interval seconds
+        #[clap(long, default_value_t = false, requires = "queue")]
+        wait: bool,
+
+        /// Queues the next PR once the queued one is merged, walking up the stack
+        #[clap(long, default_value_t = false, requires = "queue")]
+        next: bool,
+
+        /// How many seconds to wait between checks of whether the queued PR was merged
+        #[arg(long, default_value_t = 60, value_name = "SECONDS", requires = "wait")]
+        interval: u64,
+
+        /// How many seconds to wait for each queued PR to be merged before failing
+        #[arg(
+            long,
+            default_value_t = 3600,
+            value_name = "SECONDS",
+            requires = "wait"
+        )]
+        timeout: u64,
+
+        /// The format to display the notes in. Defaults to the one in the config
+        #[arg(short, long, value_enum)]
+        format: Option<NoteFormat>,
+
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Enables auto-merge on every PR of the stack, from the bottom up. With --cascade, only on the
+    /// bottom one, and waits for it to be merged to move the stack onto the main branch and do the
+    /// same with the next one
+    Automerge {
+        /// How to merge the PRs
+        #[arg(short, long, value_enum, default_value_t = MergeStrategy::default())]
+        strategy: MergeStrategy,
+
+        /// Walks up the stack, enabling auto-merge on each PR after the one below it was merged
+        #[clap(long, default_value_t = false)]
+        cascade: bool,
+
+        /// How many seconds to wait between checks of whether the PR was merged, with --cascade
+        #[arg(long, default_value_t = 60, value_name = "SECONDS")]
+        interval: u64,
+
+        /// How many seconds to wait for each PR to be merged before failing, with --cascade
+        #[arg(long, default_value_t = 3600, value_name = "SECONDS")]
+        timeout: u64,
+
+        /// The format to display the notes in. Defaults to the one in the config
+        #[arg(short, long, value_enum)]
+        format: Option<NoteFormat>,
+
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Deletes the branches whose PRs were merged, locally and on the remote, after asking.
+    /// Their children are moved onto their parents, with their PRs retargeted
+    Clean {
+        /// Don't ask before deleting
+        #[clap(short, long, default_value_t = false)]
+        yes: bool,
+    },
+
+    /// Creates PRs for the branches in the stack that don't have one yet, each targeting its
+    /// parent branch, and updates the notes of every PR in the stack
+    Submit {
+        /// The format to display the notes in. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        branch: Option<String>,
+
+        /// Creates the new PRs as drafts
+        #[clap(short, long, default_value_t = false)]
+        draft: bool,
+
+        /// Also runs guard, keeping PRs as drafts until their parent is merged
+        #[arg(long, default_value_t = false)]
+        auto_draft: bool,
+
+        /// Adds this label to every PR of the stack, and removes it from PRs that left it, e.g.
+        /// stack:my-feature
+        #[arg(long)]
+        label: Option<String>,
+    },
+
+    /// Opens an interactive view of the stack, from which branches can be checked out, notes
+    /// updated, PRs opened in the browser and the stack restacked
+    Ui {
+        /// The format to display the notes in. Defaults to the one in the config
+        #[arg(value_enum)]
+        format: Option<NoteFormat>,
+
+        /// The branch at the top of the stack. If not given, the current branch is used
+        branch: Option<String>,
+    },
+
+    /// Puts back the branches and PR descriptions changed by the last command, force-pushing the
+    /// branches it pushed. Merged, closed and newly opened PRs stay as they are
+    Undo {
+        /// Undo it even if the branches or PR descriptions changed again since
+        #[clap(short, long, default_value_t = false)]
+        force: bool,
+    },
+
+    /// Checks that git, the forge CLI, the remote, the main branch and the recorded parents are
+    /// all set up, and says how to fix what isn't
+    Doctor,
+
+    /// Installs git hooks that record the parent of new branches on their first commit and warn
+    /// when an amend leaves the branches above behind, or uninstalls them
+    Hooks {
+        /// `install` or `uninstall`
+        #[arg(value_parser = ["install", "uninstall"])]
+        action: String,
+
+        /// Also installs a pre-push hook that lists the PRs of the stacks being pushed whose notes
+        /// are stale. It only edits them with `git config stackbuddy.pushNotes true`
+        #[clap(long, default_value_t = false)]
+        update_notes: bool,
+
+        /// Replaces hooks that weren't installed by stackbuddy
+        #[clap(short, long, default_value_t = false)]
+        force: bool,
+    },
+
+    /// Runs a git hook installed by `stackbuddy hooks install`
+    #[command(hide = true)]
+    Hook {
+        name: String,
+
+        /// The arguments git passed to the hook
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Any other command runs the `stackbuddy-<command>` plugin from the PATH
+    #[command(external_subcommand)]
+    Plugin(Vec<String>),
 }
 
-fn main() -> Result<(), Error> {
+fn main() -> ExitCode {
     let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // `{:#}` prints the whole chain of causes in a single line
+            eprintln!("{} {e:#}", output::error("error:"));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(mut args: Args) -> Result<(), Error> {
+    init_logging(args.verbose);
+    output::init(args.color);
+    output::set_quiet(args.quiet);
+    // The repository has to be set before anything runs git, including reading the config
+    if let Some(dir) = args.directory.take() {
+        if !dir.is_dir() {
+            return Err(eyre!(
+                "cannot change to '{}': no such directory",
+                dir.display()
+            ));
+        }
+        stackbuddy::repo::init(Repo::at(dir));
+    }
+    let mut config = Config::read()?;
+    if let Some(template) = args.template.take() {
+        config.note_template = Some(template);
+        config.note_format = Some(NoteFormat::Custom);
+    }
+    config.note_titles |= args.titles;
+    config.note_position |= args.position;
+    config.note_compare |= args.compare;
+    config.note_badges |= args.badges;
+    config.guess_branch |= args.guess;
+    if let Some(references) = args.references {
+        config.note_references = Some(references);
+    }
+    if let Some(remote) = args.remote.take() {
+        config.remote = Some(remote);
+    }
+    if let Some(base) = args.base.take() {
+        config.main_branch = Some(base);
+    }
+    if let Some(strategy) = args.strategy {
+        config.parent_strategy = Some(strategy);
+    }
+    if let Command::UpdateNotes {
+        mode: Some(mode), ..
+    }
+    | Command::CheckNotes {
+        mode: Some(mode), ..
+    }
+    | Command::CiUpdate {
+        mode: Some(mode), ..
+    } = args.command
+    {
+        config.note_mode = Some(mode);
+    }
+    let config = stackbuddy::config::init(config);
+    if let Some(version) = args.porcelain {
+        porcelain::check_version(version)?;
+    }
+    // Plugins do their own thing with the forge, and their own journaling
+    if let Command::Plugin(args) = &args.command {
+        let status = stackbuddy::plugin::run(&args[0], &args[1..])?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    // The doctor has to run even when the forge can't be set up
+    if let Command::Doctor = args.command {
+        return doctor(args.forge, args.backend, args.json);
+    }
+    // Commands that change PRs or depend on them being up to date always fetch them again
+    let reads_only = matches!(
+        args.command,
+        Command::Note { .. }
+            | Command::Status { .. }
+            | Command::Stack { .. }
+            | Command::Stacks
+            | Command::Prs { .. }
+            | Command::Graph { .. }
+            | Command::Open { .. }
+    );
+    if args.offline && !reads_only {
+        return Err(eyre!(
+            "--offline only works with note, status, stack, stacks, prs, graph and open"
+        ));
+    }
+    let cache = if args.offline {
+        CacheMode::Offline
+    } else if args.no_cache {
+        CacheMode::Off
+    } else if args.refresh || !reads_only {
+        CacheMode::Refresh
+    } else {
+        CacheMode::Use
+    };
+    let forge = stackbuddy::forge::forge(args.forge, args.backend, cache)?;
+    if let Some(age) = forge.staleness() {
+        eprintln!(
+            "{}",
+            output::warning(&format!(
+                "Offline: the PRs are from the cache, as they were {}",
+                output::ago(age)
+            ))
+        );
+    }
+    // Undoing is the one command that doesn't leave an entry in the journal
+    if let Command::Undo { force } = args.command {
+        return undo(forge.as_ref(), force);
+    }
+    // Hooks run on every commit, so they'd flood the journal
+    if let Command::Hook { name, .. } = &args.command {
+        return stackbuddy::hooks::run(forge.as_ref(), name);
+    }
+
+    let command = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+    let journal = Arc::new(Journal::start(command)?);
+    let forge: Box<dyn Forge> = Box::new(Journaled::new(forge, journal.clone()));
+    let result = execute(args, forge, config);
+    // What a failed command changed can be undone too
+    let finished = journal.finish();
+    result.and(finished)
+}
+
+fn execute(args: Args, forge: Box<dyn Forge>, config: &Config) -> Result<(), Error> {
+    let discovery = args
+        .discovery
+        .or(config.stack_discovery)
+        .unwrap_or_default();
 
     match args.command {
         Command::Parent { branch } => {
-            let branch = branch.unwrap_or_else(|| stackbuddy::current_branch().unwrap());
-            let parent = stackbuddy::parent(branch)?;
-            println!("{}", parent.unwrap_or_else(|| "None".to_string()));
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let strategy = config.parent_strategy.unwrap_or_default();
+            // A recorded parent isn't a guess, so it has no confidence
+            let (parent, confidence) = match stackbuddy::tracked_parent(&branch)? {
+                Some(parent) => (Some(parent), None),
+                None => {
+                    let guess = stackbuddy::guess_parent(&branch, strategy)?;
+                    let confidence =
+                        Some(guess.confidence).filter(|_| strategy == ParentStrategy::Best);
+                    (guess.parent, confidence)
+                }
+            };
+            if args.json {
+                let confidence = confidence.map(|c| c.to_string());
+                print_json(
+                    &json!({ "branch": branch, "parent": parent, "confidence": confidence }),
+                )?;
+            } else if args.porcelain.is_some() {
+                let confidence = confidence.map(|c| c.to_string());
+                println!(
+                    "{}",
+                    porcelain::parent(&branch, parent.as_deref(), confidence.as_deref())
+                );
+            } else {
+                match (parent, confidence) {
+                    (Some(parent), Some(confidence)) => {
+                        println!("{parent} ({confidence} confidence)")
+                    }
+                    (parent, _) => println!("{}", parent.unwrap_or_else(|| "None".to_string())),
+                }
+            }
+        }
+        Command::Children { branch, all } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let children = if all {
+                stackbuddy::descendants(&branch)?
+            } else {
+                stackbuddy::children(&branch)?
+            };
+            if args.json {
+                print_json(&json!({ "branch": branch, "children": children }))?;
+            } else {
+                for child in children {
+                    println!("{child}");
+                }
+            }
+        }
+        Command::Up => {
+            let target = stackbuddy::child(&stackbuddy::current_branch()?)?;
+            stackbuddy::checkout(&target)?;
+            println!("{target}");
+        }
+        Command::Down => {
+            let branch = stackbuddy::current_branch()?;
+            let target = stackbuddy::parent(branch.clone())?
+                .ok_or_else(|| eyre!("'{branch}' has no parent"))?;
+            stackbuddy::checkout(&target)?;
+            println!("{target}");
+        }
+        Command::Top => {
+            let target = stackbuddy::top_of(&stackbuddy::current_branch()?)?;
+            stackbuddy::checkout(&target)?;
+            println!("{target}");
+        }
+        Command::Bottom => {
+            let target = stackbuddy::bottom_of(&stackbuddy::current_branch()?)?;
+            stackbuddy::checkout(&target)?;
+            println!("{target}");
+        }
+        Command::Checkout { branch } => {
+            let target = match branch {
+                Some(branch) => stackbuddy::resolve_branch(forge.as_ref(), branch)?,
+                None => match pick_branch(forge.as_ref())? {
+                    Some(branch) => branch,
+                    None => return Ok(()),
+                },
+            };
+            stackbuddy::checkout(&target)?;
+            println!("{target}");
+        }
+        Command::Create {
+            name,
+            message,
+            push,
+            pr,
+        } => {
+            stackbuddy::create(forge.as_ref(), &name, message.as_deref(), push, pr)?;
+        }
+        Command::Insert {
+            name,
+            after,
+            message,
+            push,
+            pr,
+        } => {
+            stackbuddy::insert(forge.as_ref(), &name, &after, message.as_deref(), push, pr)?;
+        }
+        Command::Track { branch, parent } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let parent = match parent {
+                Some(parent) => parent,
+                None => {
+                    let strategy = config.parent_strategy.unwrap_or_default();
+                    stackbuddy::guess_parent(&branch, strategy)?
+                        .parent
+                        .ok_or_else(|| {
+                            eyre!("could not guess the parent of '{branch}', pass it with --parent")
+                        })?
+                }
+            };
+            stackbuddy::set_parent(&branch, &parent)?;
+            println!("{branch} -> {parent}");
+        }
+        Command::Adopt { branch, yes } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            for (branch, parent) in stackbuddy::adopt(branch, yes)? {
+                println!("{branch} -> {parent}");
+            }
+        }
+        Command::Export { branch } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            print_json(&stackbuddy::import::export(forge.as_ref(), &branch)?)?;
+        }
+        Command::Import { source, dry_run } => {
+            let links = match source.as_str() {
+                "graphite" => stackbuddy::import::graphite(dry_run)?,
+                path => stackbuddy::import::from_file(Path::new(path), dry_run)?,
+            };
+            for (branch, parent) in links {
+                println!("{branch} -> {parent}");
+            }
+        }
+        Command::Stack {
+            branch,
+            check,
+            tree,
+            stat,
+            name,
+        } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            if let Some(name) = name {
+                stackbuddy::set_stack_name(&branch, &name)?;
+            }
+            if check {
+                let from_log =
+                    stackbuddy::discover_stack(forge.as_ref(), branch.clone(), Discovery::Log)?;
+                let from_prs =
+                    stackbuddy::discover_stack(forge.as_ref(), branch.clone(), Discovery::Prs)?;
+                if from_log != from_prs {
+                    return Err(eyre!(
+                        "the git log and the PR bases disagree on the stack\n  log: {}\n  prs: {}",
+                        from_log.join(" -> "),
+                        from_prs.join(" -> ")
+                    ));
+                }
+            }
+            if args.porcelain.is_some() {
+                let stack = Stack::discover(forge.as_ref(), branch, discovery)?;
+                println!("{}", porcelain::stack(&stack));
+                return Ok(());
+            }
+            if tree {
+                let base = stackbuddy::main_branch()?;
+                let tree = StackNode::of_stack(&branch)?;
+                if args.json {
+                    print_json(&json!({ "base": base, "tree": tree }))?;
+                } else {
+                    println!("{}", tree.render(&base));
+                }
+                return Ok(());
+            }
+            let stack = Stack::discover(forge.as_ref(), branch, discovery)?;
+            if stat {
+                let stats = BranchStats::of_stack(&stack.branches)?;
+                if args.json {
+                    print_json(&json!({ "base": stack.base, "branches": stats }))?;
+                } else {
+                    println!("{}", stackbuddy::stats_table(&stats));
+                }
+            } else if args.json {
+                print_json(&stack)?;
+            } else {
+                let current = stackbuddy::current_branch().ok();
+                for branch in stack.branches {
+                    println!("{}", output::branch(&branch, current.as_deref()))
+                }
+            }
+        }
+        Command::Stacks => {
+            let stacks = StackSummary::all(forge.as_ref())?;
+            if args.json {
+                print_json(&stacks)?;
+            } else {
+                for stack in stacks {
+                    println!("{}", stack.line());
+                }
+            }
         }
-        Command::Stack { branch } => {
-            let branch = branch.unwrap_or_else(|| stackbuddy::current_branch().unwrap());
-            for branch in stackbuddy::stack_from(branch) {
-                println!("{branch}")
+        Command::Graph { branch } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            println!("{}", stackbuddy::graph(forge.as_ref(), &branch)?);
+        }
+        Command::Diff { branch, stat } => match branch {
+            Some(branch) => {
+                let branch = stackbuddy::resolve_branch(forge.as_ref(), branch)?;
+                stackbuddy::show_diff(&branch, true, stat)?;
+            }
+            None => stackbuddy::show_diff(&stackbuddy::current_branch()?, false, stat)?,
+        },
+        Command::Retarget { branch, dry_run } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::retarget(forge.as_ref(), branch, dry_run)?;
+        }
+        Command::Guard { branch, dry_run } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::guard(forge.as_ref(), branch, dry_run)?;
+        }
+        Command::Reviewers {
+            branch,
+            add,
+            sync,
+            assign,
+            dry_run,
+        } => {
+            if add.is_empty() && !sync {
+                return Err(eyre!("nothing to do, pass --add or --sync"));
+            }
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::request_reviewers(forge.as_ref(), branch, &add, sync, assign, dry_run)?;
+        }
+        Command::Status { branch, stat } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let stack = stackbuddy::discover_stack(forge.as_ref(), branch, discovery)?;
+            let stats = if stat {
+                Some(BranchStats::of_stack(&stack)?)
+            } else {
+                None
+            };
+            if args.porcelain.is_some() {
+                let status = porcelain::status(forge.as_ref(), &stack, stats.as_deref())?;
+                println!("{status}");
+                return Ok(());
+            }
+            let table = stackbuddy::status_table(forge.as_ref(), &stack, stats.as_deref())?;
+            println!("{table}");
+        }
+        Command::Prs { branch } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let mut stack = stackbuddy::discover_stack(forge.as_ref(), branch, discovery)?;
+            // The order the PRs are reviewed and merged in
+            stack.reverse();
+            let entries = StackEntry::of_stack(forge.as_ref(), &stack)?;
+            if args.json {
+                print_json(&entries)?;
+            } else {
+                for entry in entries {
+                    println!("{}", entry.line(forge.as_ref()));
+                }
+            }
+        }
+        Command::Open {
+            target,
+            all,
+            next,
+            prev,
+            print,
+        } => {
+            let branches = match target {
+                _ if all => {
+                    let stack = stackbuddy::whole_stack(&stackbuddy::current_branch()?)?;
+                    // Bottom up, skipping the branches that weren't submitted yet
+                    let mut branches = Vec::new();
+                    for branch in stack.into_iter().rev() {
+                        if forge.pr_url(&branch)?.is_some() {
+                            branches.push(branch);
+                        }
+                    }
+                    branches
+                }
+                _ if next => vec![stackbuddy::child(&stackbuddy::current_branch()?)?],
+                _ if prev => {
+                    let branch = stackbuddy::current_branch()?;
+                    let main = stackbuddy::main_branch()?;
+                    let parent = stackbuddy::parent(branch.clone())?
+                        .filter(|parent| parent != &main)
+                        .ok_or_else(|| eyre!("'{branch}' is at the bottom of the stack"))?;
+                    vec![parent]
+                }
+                // A number is a position in the stack, unless there's a branch with that name
+                Some(target)
+                    if target.parse::<usize>().is_ok()
+                        && !stackbuddy::local_branches()?.contains(&target) =>
+                {
+                    let position = target.parse()?;
+                    vec![stackbuddy::branch_at(
+                        &stackbuddy::current_branch()?,
+                        position,
+                    )?]
+                }
+                target => vec![branch_or_current(forge.as_ref(), target)?],
+            };
+
+            for url in stackbuddy::pr_urls(forge.as_ref(), &branches)? {
+                if print {
+                    println!("{url}");
+                } else {
+                    stackbuddy::forge::open_url(&url)?;
+                }
             }
         }
         Command::Note { format, branch } => {
-            let branch = branch.unwrap_or_else(|| stackbuddy::current_branch().unwrap());
-            let note = stackbuddy::note_block(branch, format)?;
-            println!("{note}");
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            // The current stack also has the PRs above the branch, if it's part of it
+            let stack = match (discovery, stackbuddy::current_stack()) {
+                (Discovery::Log, Ok(stack)) if stack.contains(&branch) => stack,
+                _ => stackbuddy::discover_stack(forge.as_ref(), branch.clone(), discovery)?,
+            };
+            let options = NoteOptions::new(format)?;
+            let note = stackbuddy::note_block(forge.as_ref(), &stack, branch.clone(), &options)?;
+            if args.json {
+                print_json(&json!({ "branch": branch, "format": format, "note": note }))?;
+            } else {
+                println!("{note}");
+            }
         }
         Command::UpdateNotes {
             format,
             branch,
             dry_run,
+            jobs,
+            auto_draft,
+            label,
+            fail_fast,
+            only,
+            pick,
+            yes,
+            ..
         } => {
-            let branch = branch.unwrap_or_else(|| stackbuddy::current_branch().unwrap());
-            for branch in stackbuddy::stack_from(branch) {
-                println!("Updating notes for {branch}...");
-                if let Err(e) = stackbuddy::update_note(branch.clone(), format, dry_run) {
-                    println!("Error in branch {branch}: {e}")
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            hooks::around("update-notes", &branch, || {
+                let branches =
+                    stackbuddy::discover_stack(forge.as_ref(), branch.clone(), discovery)?;
+                let options = NoteOptions::new(format)?;
+                let mut targets = branches.clone();
+                if !only.is_empty() {
+                    let mut picked = Vec::new();
+                    for branch in only {
+                        let branch = stackbuddy::resolve_branch(forge.as_ref(), branch)?;
+                        if !branches.contains(&branch) {
+                            return Err(eyre!("'{branch}' isn't part of the stack"));
+                        }
+                        picked.push(branch);
+                    }
+                    targets.retain(|branch| picked.contains(branch));
+                }
+                if !dry_run && (pick || !yes) {
+                    let Some(picked) = plan_note_updates(
+                        forge.as_ref(),
+                        &branches,
+                        &targets,
+                        &options,
+                        jobs,
+                        pick,
+                        yes,
+                    )?
+                    else {
+                        return Ok(());
+                    };
+                    targets = picked;
+                }
+
+                if auto_draft || config.auto_draft {
+                    stackbuddy::guard(forge.as_ref(), branch.clone(), dry_run)?;
+                }
+                if let Some(label) = label {
+                    stackbuddy::label_stack(forge.as_ref(), &branches, &label, dry_run)?;
+                }
+                let results = stackbuddy::update_some_notes(
+                    forge.as_ref(),
+                    &branches,
+                    &targets,
+                    &options,
+                    dry_run,
+                    jobs,
+                    fail_fast,
+                );
+                let mut report = UpdateReport::default();
+                for (branch, result) in targets.into_iter().zip(results) {
+                    if !args.json && args.porcelain.is_none() {
+                        match &result {
+                            Ok(update) if dry_run => print_dry_run(&branch, update),
+                            Ok(_) => {}
+                            Err(e) => {
+                                eprintln!(
+                                    "{} {e}",
+                                    output::error(&format!("Error in branch {branch}:"))
+                                )
+                            }
+                        }
+                    }
+                    report.push(branch, &result);
+                }
+                if args.json {
+                    print_json(&report)?;
+                } else if args.porcelain.is_some() {
+                    println!("{}", porcelain::update_report(&report));
+                } else if !report.branches.is_empty() {
+                    println!("\nSummary:");
+                    for line in report.summary().lines() {
+                        println!("  {line}");
+                    }
+                }
+
+                let failed = report.failed();
+                if !failed.is_empty() {
+                    return Err(eyre!("failed to update the notes of {}", failed.join(", ")));
+                }
+                Ok(())
+            })?;
+        }
+        Command::Watch {
+            format,
+            branch,
+            interval,
+            jobs,
+        } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let options = NoteOptions::new(format)?;
+            info!("Watching the stack of {branch}, press Ctrl-C to stop");
+            stackbuddy::watch(
+                forge.as_ref(),
+                &branch,
+                Duration::from_secs(interval),
+                &options,
+                jobs,
+            )?;
+        }
+        Command::CheckNotes {
+            format,
+            branch,
+            jobs,
+            ..
+        } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let branches = stackbuddy::discover_stack(forge.as_ref(), branch, discovery)?;
+            let options = NoteOptions::new(format)?;
+            let results =
+                stackbuddy::update_notes(forge.as_ref(), &branches, &options, true, jobs, false);
+            let mut report = UpdateReport::default();
+            for (branch, result) in branches.into_iter().zip(results) {
+                if !args.json {
+                    match &result {
+                        Ok(update) => print_dry_run(&branch, update),
+                        Err(e) => {
+                            eprintln!(
+                                "{} {e}",
+                                output::error(&format!("Error in branch {branch}:"))
+                            )
+                        }
+                    }
+                }
+                report.push(branch, &result);
+            }
+            if args.json {
+                print_json(&report)?;
+            }
+
+            let stale = report.stale();
+            if !stale.is_empty() {
+                return Err(eyre!(
+                    "the notes of {} are not up to date, run `stackbuddy update-notes`",
+                    stale.join(", ")
+                ));
+            }
+        }
+        Command::CleanNotes { branch, merged } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let branches = stackbuddy::discover_stack(forge.as_ref(), branch, discovery)?;
+            for branch in branches {
+                let Some(pr) = forge.pr_for_branch(&branch)? else {
+                    continue;
+                };
+                if merged && pr.state == PrState::Open {
+                    continue;
+                }
+                if stackbuddy::remove_note(forge.as_ref(), &branch)? {
+                    println!("Removed the note of {branch}");
                 }
             }
         }
+        Command::CiUpdate {
+            format,
+            branch,
+            jobs,
+            ..
+        } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = match branch {
+                Some(branch) => branch,
+                None => github_actions_branch()?,
+            };
+            let forge = Cached::new(ForgeKind::Github.forge(Backend::Api)?);
+            let stack = stackbuddy::stack_from_prs(&forge, &branch)?;
+            if stack.is_empty() {
+                info!("{branch} has no open PR, there are no notes to update");
+                return Ok(());
+            }
+
+            let options = NoteOptions::new(format)?;
+            let results = stackbuddy::update_notes(&forge, &stack, &options, false, jobs, false);
+            let mut report = UpdateReport::default();
+            for (branch, result) in stack.into_iter().zip(results) {
+                if !args.json {
+                    match &result {
+                        Ok(update) if update.action == NoteAction::Unchanged => {
+                            println!("{branch}: up to date")
+                        }
+                        Ok(_) => println!("{branch}: updated"),
+                        Err(e) => {
+                            eprintln!(
+                                "{} {e}",
+                                output::error(&format!("Error in branch {branch}:"))
+                            )
+                        }
+                    }
+                }
+                report.push(branch, &result);
+            }
+            if args.json {
+                print_json(&report)?;
+            }
+
+            let failed = report.failed();
+            if !failed.is_empty() {
+                return Err(eyre!("failed to update the notes of {}", failed.join(", ")));
+            }
+        }
+        Command::Restack { branch } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            hooks::around("restack", &branch, || stackbuddy::restack(branch.clone()))?;
+        }
+        Command::Absorb { dry_run } => {
+            let report = stackbuddy::absorb::absorb(dry_run)?;
+            let verb = if dry_run { "Would absorb" } else { "Absorbed" };
+            for fixup in &report.fixups {
+                println!(
+                    "{verb} {} hunk(s) of {} into {} {} ({})",
+                    fixup.hunks,
+                    fixup.files.join(", "),
+                    &fixup.target[..7.min(fixup.target.len())],
+                    fixup.subject,
+                    fixup.branch
+                );
+            }
+            if report.left > 0 {
+                println!(
+                    "{} hunk(s) stayed staged, since they don't belong to a single commit of the stack",
+                    report.left
+                );
+            }
+        }
+        Command::Amend { no_push } => {
+            let reports = stackbuddy::amend(no_push)?;
+            if !reports.is_empty() {
+                println!("\nSummary:");
+                for report in reports {
+                    println!("  {report}");
+                }
+            }
+        }
+        Command::Backport {
+            commit,
+            to,
+            no_push,
+        } => {
+            let to = stackbuddy::resolve_branch(forge.as_ref(), to)?;
+            let reports = stackbuddy::backport(&commit, &to, no_push)?;
+            if !reports.is_empty() {
+                println!("\nSummary:");
+                for report in reports {
+                    println!("  {report}");
+                }
+            }
+        }
+        Command::Exec {
+            branch,
+            fail_fast,
+            keep_going,
+            command,
+        } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            // Failing fast is the default, --keep-going turns it off
+            let fail_fast = fail_fast || !keep_going;
+            let reports = stackbuddy::exec(branch, &command, fail_fast)?;
+            println!("\nSummary:");
+            for report in &reports {
+                println!("  {report}");
+            }
+
+            let failed = reports.iter().filter(|report| !report.success()).count();
+            if failed > 0 {
+                return Err(eyre!("the command failed on {failed} branches"));
+            }
+        }
+        Command::Move {
+            format,
+            branch,
+            onto,
+        } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::move_branch(forge.as_ref(), branch, &onto, format)?;
+        }
+        Command::Reorder { format, branch } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::reorder(forge.as_ref(), branch, format)?;
+        }
+        Command::Split { branch, pr } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::split(forge.as_ref(), branch, pr)?;
+        }
+        Command::Rename { old, new, format } => {
+            let format = format.unwrap_or(config.note_format());
+            let old = stackbuddy::resolve_branch(forge.as_ref(), old)?;
+            stackbuddy::rename(forge.as_ref(), &old, &new, format)?;
+        }
+        Command::Fold { format, branch } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::fold(forge.as_ref(), branch, format)?;
+        }
+        Command::Push { branch, force } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let reports = stackbuddy::push(branch, force)?;
+            println!("\nSummary:");
+            for report in &reports {
+                println!("  {report}");
+            }
+
+            let failed = reports
+                .iter()
+                .filter(|report| !matches!(report.pushed, Some(Ok(())) | None))
+                .count();
+            let diverged = reports
+                .iter()
+                .filter(|report| report.pushed.is_none() && report.state == RemoteState::Diverged)
+                .count();
+            if failed + diverged > 0 {
+                return Err(eyre!(
+                    "{failed} branches failed to push and {diverged} were skipped"
+                ));
+            }
+        }
+        Command::Sync { branch } => {
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            let reports = stackbuddy::sync(branch.clone())?;
+            println!("\nSummary:");
+            for report in reports {
+                println!("  {report}");
+            }
+            if config.auto_draft {
+                stackbuddy::guard(forge.as_ref(), branch, false)?;
+            }
+        }
+        Command::Continue => {
+            let reports = stackbuddy::continue_operation(forge.as_ref())?;
+            if !reports.is_empty() {
+                println!("\nSummary:");
+                for report in reports {
+                    println!("  {report}");
+                }
+            }
+        }
+        Command::Abort => stackbuddy::abort_operation()?,
+        Command::Land {
+            strategy,
+            queue,
+            wait,
+            next,
+            interval,
+            timeout,
+            format,
+            branch,
+        } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            // The landed branches are deleted, so the drafts are guarded from the top of the stack,
+            // which is found before they're gone
+            let top = stackbuddy::top_of(&branch).unwrap_or_else(|_| branch.clone());
+            hooks::around("land", &branch, || {
+                if queue {
+                    let wait = wait.then(|| Wait {
+                        interval: Duration::from_secs(interval),
+                        timeout: Duration::from_secs(timeout),
+                    });
+                    stackbuddy::land_queued(forge.as_ref(), branch.clone(), next, wait, format)
+                } else {
+                    stackbuddy::land(forge.as_ref(), branch.clone(), strategy, format)
+                }
+            })?;
+            // Nothing is left to guard if the whole stack was landed
+            if config.auto_draft && stackbuddy::local_branches()?.contains(&top) {
+                stackbuddy::guard(forge.as_ref(), top, false)?;
+            }
+        }
+        Command::Automerge {
+            strategy,
+            cascade,
+            interval,
+            timeout,
+            format,
+            branch,
+        } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::automerge(
+                forge.as_ref(),
+                branch,
+                strategy,
+                cascade,
+                Wait {
+                    interval: Duration::from_secs(interval),
+                    timeout: Duration::from_secs(timeout),
+                },
+                format,
+            )?;
+        }
+        Command::Clean { yes } => {
+            let merged = stackbuddy::merged_branches(forge.as_ref())?;
+            if merged.is_empty() {
+                info!("No branch has a merged PR");
+                return Ok(());
+            }
+            info!("These branches have merged PRs:");
+            for branch in &merged {
+                info!("  {branch}");
+            }
+            if !yes && !confirm("Delete them locally and on the remote?")? {
+                info!("Nothing was deleted, pass --yes to delete them without asking");
+                return Ok(());
+            }
+            for branch in &merged {
+                info!("Deleting {branch}...");
+                stackbuddy::clean(forge.as_ref(), branch)?;
+            }
+        }
+        Command::Submit {
+            format,
+            branch,
+            draft,
+            auto_draft,
+            label,
+        } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::submit(forge.as_ref(), branch.clone(), format, draft)?;
+            if auto_draft || config.auto_draft {
+                stackbuddy::guard(forge.as_ref(), branch.clone(), false)?;
+            }
+            if let Some(label) = label {
+                let stack = stackbuddy::stack_from(branch)?;
+                stackbuddy::label_stack(forge.as_ref(), &stack, &label, false)?;
+            }
+        }
+        Command::Ui { format, branch } => {
+            let format = format.unwrap_or(config.note_format());
+            let branch = branch_or_current(forge.as_ref(), branch)?;
+            stackbuddy::ui::run(forge.as_ref(), branch, format)?;
+        }
+        Command::Hooks {
+            action,
+            update_notes,
+            force,
+        } => {
+            if action == "install" {
+                for path in stackbuddy::hooks::install(update_notes, force)? {
+                    println!("Installed {}", path.display());
+                }
+            } else {
+                let removed = stackbuddy::hooks::uninstall()?;
+                if removed.is_empty() {
+                    info!("No hooks installed by stackbuddy were found");
+                }
+                for path in removed {
+                    println!("Removed {}", path.display());
+                }
+            }
+        }
+        Command::Doctor => unreachable!("the doctor runs before the forge is set up"),
+        Command::Undo { .. } => unreachable!("undoing isn't journaled"),
+        Command::Hook { .. } => unreachable!("hooks aren't journaled"),
+        Command::Plugin(_) => unreachable!("plugins aren't journaled"),
     }
 
     Ok(())
 }
+
+/// Prints whether the PR of `branch` would change, and how
+fn print_dry_run(branch: &str, update: &NoteUpdate) {
+    if update.action == NoteAction::Unchanged {
+        println!("{branch}: up to date");
+        return;
+    }
+    println!("{branch}: would be updated");
+    let color = output::enabled();
+    println!(
+        "{}",
+        stackbuddy::diff::unified(&update.old_body, &update.body, 3, color)
+    );
+}
+
+/// The branch GitHub Actions is running for: the head of the PR for `pull_request` events, or the
+/// pushed branch for `push` events
+fn github_actions_branch() -> Result<String, Error> {
+    if let Ok(head) = std::env::var("GITHUB_HEAD_REF") {
+        if !head.is_empty() {
+            return Ok(head);
+        }
+    }
+    let git_ref = std::env::var("GITHUB_REF")
+        .map_err(|_| eyre!("GITHUB_REF is not set, pass the branch explicitly"))?;
+    git_ref
+        .strip_prefix("refs/heads/")
+        .map(str::to_string)
+        .ok_or_else(|| eyre!("GITHUB_REF ({git_ref}) is not a branch"))
+}
+
+/// Returns the given branch, or the head branch of the PR if it's a reference like `#12` or the URL
+/// of a PR, or the current branch if none was given
+fn branch_or_current(forge: &dyn Forge, branch: Option<String>) -> Result<String, Error> {
+    match branch {
+        Some(branch) => stackbuddy::resolve_branch(forge, branch),
+        None => stackbuddy::current_branch(),
+    }
+}
+
+/// Logs to stderr, so the output of commands like `stack --json` can still be piped
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => return,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .without_time()
+        .with_target(false)
+        .init();
+}
+
+fn doctor(kind: Option<ForgeKind>, backend: Option<Backend>, json: bool) -> Result<(), Error> {
+    let checks = stackbuddy::doctor::diagnose(kind, backend);
+    if json {
+        print_json(&checks)?;
+    } else {
+        for check in &checks {
+            let mark = if check.ok { "ok" } else { "!!" };
+            println!("[{mark}] {}: {}", check.name, check.detail);
+            if let Some(fix) = &check.fix {
+                println!("     fix: {fix}");
+            }
+        }
+    }
+
+    let failed = checks.iter().filter(|check| !check.ok).count();
+    if failed > 0 {
+        return Err(eyre!("{failed} of {} checks failed", checks.len()));
+    }
+    Ok(())
+}
+
+fn undo(forge: &dyn Forge, force: bool) -> Result<(), Error> {
+    let entry = stackbuddy::journal::undo(forge, force)?;
+    println!(
+        "Undid `stackbuddy {}`: restored {} branches and {} PR descriptions",
+        entry.command,
+        entry.tips.len(),
+        entry.bodies.len()
+    );
+    Ok(())
+}
+
+/// Shows a picker with every stacked branch and the PR it has, if any, and returns the picked
+/// one, or None if the picker was closed
+fn pick_branch(forge: &dyn Forge) -> Result<Option<String>, Error> {
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre!(
+            "stdin isn't a terminal, pass the branch to check out"
+        ));
+    }
+    let main = stackbuddy::main_branch()?;
+    let branches = stackbuddy::descendants(&main)?;
+    if branches.is_empty() {
+        return Err(eyre!("there are no stacked branches on top of '{main}'"));
+    }
+    let prs = forge.open_prs()?;
+    let items: Vec<String> = branches
+        .iter()
+        .map(|branch| match prs.get(branch) {
+            Some(pr) => format!("{branch}  {} {}", forge.pr_reference(&pr.number), pr.title),
+            None => branch.clone(),
+        })
+        .collect();
+    let picked = stackbuddy::ui::pick("Checkout", &items)?;
+    Ok(picked.map(|i| branches[i].clone()))
+}
+
+/// Shows which of the PRs of `targets` would get their notes changed, lets the user pick some of
+/// them with `pick` and asks for confirmation unless `yes`. Returns the branches to update, or
+/// None if nothing should be
+fn plan_note_updates(
+    forge: &dyn Forge,
+    stack: &[String],
+    targets: &[String],
+    options: &NoteOptions,
+    jobs: usize,
+    pick: bool,
+    yes: bool,
+) -> Result<Option<Vec<String>>, Error> {
+    let prs = forge.open_prs()?;
+    let plan = stackbuddy::update_some_notes(forge, stack, targets, options, true, jobs, false);
+    let mut changes = Vec::new();
+    let mut lines = Vec::new();
+    for (branch, result) in targets.iter().zip(plan) {
+        let pr = match prs.get(branch) {
+            Some(pr) => forge.pr_reference(&pr.number),
+            None => "no open PR".to_string(),
+        };
+        let outcome = match result {
+            Ok(update) if update.action == NoteAction::Unchanged => "up to date".to_string(),
+            Ok(_) => {
+                changes.push((branch.clone(), pr.clone()));
+                "will be updated".to_string()
+            }
+            Err(e) => format!("can't be updated: {e}"),
+        };
+        lines.push((branch.clone(), format!("{pr}, {outcome}")));
+    }
+
+    if changes.is_empty() {
+        info!("Every note is already up to date");
+        for (branch, line) in &lines {
+            info!("  {branch} ({line})");
+        }
+        return Ok(None);
+    }
+    let targets = if pick {
+        let choices: Vec<_> = changes
+            .iter()
+            .map(|(branch, pr)| (branch.clone(), format!("{pr}, will be updated")))
+            .collect();
+        let picked = stackbuddy::pick_branches(&choices, "The PRs whose notes will be updated.")?;
+        if picked.is_empty() {
+            info!("Nothing was picked, so nothing was updated");
+            return Ok(None);
+        }
+        picked
+    } else {
+        changes.into_iter().map(|(branch, _)| branch).collect()
+    };
+    if yes {
+        return Ok(Some(targets));
+    }
+
+    eprintln!("The notes of these PRs will be updated:");
+    for (branch, line) in &lines {
+        if targets.contains(branch) {
+            eprintln!("  {branch} ({line})");
+        }
+    }
+    let question = match targets.len() {
+        1 => "Update 1 PR description?".to_string(),
+        n => format!("Update {n} PR descriptions?"),
+    };
+    if !confirm(&question)? {
+        info!("Nothing was updated, pass --yes to update them without asking");
+        return Ok(None);
+    }
+    Ok(Some(targets))
+}
+
+/// Asks a yes or no question. Fails if stdin isn't a terminal, since nobody could answer it, and
+/// quietly doing nothing would look like a success to scripts. Callers skip it with `--yes`
+fn confirm(question: &str) -> Result<bool, Error> {
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre!(
+            "can't ask \"{question}\" since stdin isn't a terminal. Pass --yes to go ahead \
+             without asking"
+        ));
+    }
+    eprint!("{question} [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), Error> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
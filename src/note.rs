@@ -0,0 +1,1232 @@
+use crate::{
+    config::{self, Config},
+    forge::{self, Forge, ForgeKind, PrState, PrStatus},
+    main_branch,
+    progress::Progress,
+    template,
+};
+use clap::ValueEnum;
+use eyre::{eyre, Context, Error, OptionExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+#[derive(ValueEnum, Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NoteFormat {
+    /// Displays the previous and next PRs, like a doubly linked list
+    #[default]
+    Double,
+
+    /// Displays the entire stack of PRs in a list
+    List,
+
+    /// Displays the previous and next PRs, formatted in two columns of a table
+    Table,
+
+    /// Displays the entire stack as an indented tree, from the top of the stack down to its base,
+    /// marking merged PRs
+    Tree,
+
+    /// Displays the entire stack in a list, collapsed in a `<details>` block that only shows how
+    /// many PRs there are until it's opened
+    Details,
+
+    /// Renders the template given with --template or `note-template` in the config
+    Custom,
+}
+
+/// How notes refer to PRs
+#[derive(ValueEnum, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NoteReferences {
+    /// Just the number, like `#12`, which the forge links to the PR in the same repository
+    #[default]
+    Short,
+
+    /// The number prefixed by the repository, like `owner/repo#12`, which is linked from anywhere
+    /// on the forge
+    Repo,
+
+    /// The full URL of the PR, which still works outside of the forge, e.g. in release notes
+    Url,
+}
+
+/// The flavor of markdown notes are written in, so they render correctly where they're read
+#[derive(ValueEnum, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NoteDialect {
+    /// GitHub Flavored Markdown, with `> [!Note]` callouts
+    #[default]
+    Github,
+
+    /// GitLab Flavored Markdown, which has no callouts, so a bold title is used instead
+    Gitlab,
+
+    /// CommonMark, without callouts, tables or strikethrough. PR references are written as links,
+    /// since they aren't linked outside of the forge
+    Plain,
+}
+
+impl NoteDialect {
+    /// The dialect of the forge of the repository
+    pub fn detect() -> Self {
+        let forge = config::load().ok().and_then(|config| config.forge);
+        match forge.map_or_else(ForgeKind::detect, Ok) {
+            Ok(ForgeKind::Gitlab) => NoteDialect::Gitlab,
+            _ => NoteDialect::Github,
+        }
+    }
+
+    /// The first line of a callout, which the rest of its lines follow with `> `
+    fn callout(self) -> &'static str {
+        match self {
+            NoteDialect::Github => "> [!Note]",
+            NoteDialect::Gitlab | NoteDialect::Plain => "> **Note**",
+        }
+    }
+}
+
+/// How a note is rendered. [`NoteOptions::new`] starts from the settings in the config, and each
+/// option can be changed on top of them, e.g. `NoteOptions::new(NoteFormat::List)?.titles(true)`.
+/// New options are added as more of these methods, so existing callers keep working.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct NoteOptions {
+    pub format: NoteFormat,
+    /// Whether PRs are shown with their titles next to their references
+    pub titles: bool,
+    /// Whether merged and closed PRs are marked as such, in the formats that list them
+    pub states: bool,
+    /// How PRs are referred to
+    pub references: NoteReferences,
+    /// A line above the note, like a Markdown heading
+    pub heading: Option<String>,
+    /// Whether the note says where the PR is in the stack, like "PR 2 of 5", counting from the
+    /// bottom
+    pub position: bool,
+    /// Whether each PR is followed by a link to the diff between its branch and its parent
+    pub compare: bool,
+    /// Whether open PRs are followed by the status of their checks and their review decision
+    pub badges: bool,
+    pub dialect: NoteDialect,
+}
+
+impl NoteOptions {
+    /// The options in the config, with `format` as the format
+    pub fn new(format: NoteFormat) -> Result<Self, Error> {
+        let config = config::load()?;
+        Ok(Self {
+            format,
+            titles: config.note_titles,
+            states: true,
+            references: config.note_references.unwrap_or_default(),
+            heading: None,
+            position: config.note_position,
+            compare: config.note_compare,
+            badges: config.note_badges,
+            dialect: config.note_dialect.unwrap_or_else(NoteDialect::detect),
+        })
+    }
+
+    pub fn format(mut self, format: NoteFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn titles(mut self, titles: bool) -> Self {
+        self.titles = titles;
+        self
+    }
+
+    pub fn states(mut self, states: bool) -> Self {
+        self.states = states;
+        self
+    }
+
+    pub fn references(mut self, references: NoteReferences) -> Self {
+        self.references = references;
+        self
+    }
+
+    pub fn heading(mut self, heading: impl Into<String>) -> Self {
+        self.heading = Some(heading.into());
+        self
+    }
+
+    pub fn position(mut self, position: bool) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn compare(mut self, compare: bool) -> Self {
+        self.compare = compare;
+        self
+    }
+
+    pub fn badges(mut self, badges: bool) -> Self {
+        self.badges = badges;
+        self
+    }
+
+    pub fn dialect(mut self, dialect: NoteDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+}
+
+/// What is known about the PRs of a stack before rendering its note, so it's only fetched once
+#[derive(Default)]
+pub struct Labels {
+    /// The titles of the open PRs, keyed by their head branch. Only known when titles are shown
+    pub titles: HashMap<String, String>,
+    /// How PRs are referred to
+    pub references: NoteReferences,
+    pub dialect: NoteDialect,
+    /// The repository references are prefixed with, for [`NoteReferences::Repo`]
+    pub repo: Option<String>,
+    /// Whether PRs link to the diff between their branch and its parent
+    pub compare: bool,
+    /// The status of the checks and the review decision of the open PRs, keyed by their head
+    /// branch. Only known when badges are shown
+    pub badges: HashMap<String, String>,
+}
+
+impl Labels {
+    /// Fetches what the note needs for `options`. The titles of the PRs are only fetched with
+    /// `titles`. Titles and badges come from the same request for every open PR, which the forge
+    /// caches.
+    pub fn load(forge: &dyn Forge, titles: bool, options: &NoteOptions) -> Result<Self, Error> {
+        let config = config::load()?;
+        let open_prs = if titles || options.badges {
+            forge.open_prs()?
+        } else {
+            HashMap::new()
+        };
+        let badges = if options.badges {
+            open_prs
+                .iter()
+                .filter_map(|(branch, pr)| Some((branch.clone(), badges(pr)?)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let titles = if titles {
+            open_prs
+                .into_iter()
+                .map(|(branch, pr)| (branch, pr.title))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let references = options.references;
+        let repo = match references {
+            NoteReferences::Repo => Some(match &config.note_repo {
+                Some(repo) => repo.clone(),
+                None => forge::repo_name()?,
+            }),
+            _ => None,
+        };
+        Ok(Self {
+            titles,
+            references,
+            dialect: options.dialect,
+            repo,
+            compare: options.compare,
+            badges,
+        })
+    }
+}
+
+/// A PR of the stack, as shown in notes
+#[derive(Debug, Clone)]
+pub struct StackPr {
+    pub branch: String,
+    pub number: String,
+    pub state: PrState,
+    pub url: String,
+    /// How the PR is referenced in markdown, e.g. `#12`, `owner/repo#12` or its URL
+    pub reference: String,
+    /// Only known when titles are shown in notes
+    pub title: Option<String>,
+    /// The page with the diff between the branch and its parent, when notes link to it
+    pub compare: Option<String>,
+    /// The status of the checks and the review decision, when notes show them and the PR is open
+    pub badges: Option<String>,
+}
+
+impl StackPr {
+    /// Returns the PR of `branch`, if there is one. `base` is the branch below it in the stack,
+    /// which its diff is compared to
+    pub fn for_branch(
+        forge: &dyn Forge,
+        branch: &str,
+        base: &str,
+        labels: &Labels,
+    ) -> Result<Option<Self>, Error> {
+        let Some(pr) = forge.pr_for_branch(branch)? else {
+            return Ok(None);
+        };
+        let reference = match (&labels.repo, labels.references) {
+            (Some(repo), _) => format!("{repo}{}", forge.pr_reference(&pr.number)),
+            (None, NoteReferences::Url) if !pr.url.is_empty() => pr.url.clone(),
+            _ => forge.pr_reference(&pr.number),
+        };
+        let reference = match labels.dialect {
+            NoteDialect::Plain if !pr.url.is_empty() && reference != pr.url => {
+                format!("[{reference}]({})", pr.url)
+            }
+            _ => reference,
+        };
+        let compare = if labels.compare {
+            Some(forge::compare_url(base, branch)?)
+        } else {
+            None
+        };
+        Ok(Some(StackPr {
+            branch: branch.to_string(),
+            reference,
+            number: pr.number,
+            state: pr.state,
+            url: pr.url,
+            title: labels.titles.get(branch).cloned(),
+            compare,
+            badges: labels.badges.get(branch).cloned(),
+        }))
+    }
+
+    /// The reference to the PR, followed by its title if it's known, its badges if they're shown
+    /// and a link to its diff if notes link to it
+    pub fn label(&self) -> String {
+        let label = match &self.title {
+            Some(title) => format!("{} — {title}", self.reference),
+            None => self.reference.clone(),
+        };
+        let label = match &self.badges {
+            Some(badges) => format!("{label} {badges}"),
+            None => label,
+        };
+        match &self.compare {
+            Some(url) => format!("{label} ([diff]({url}))"),
+            None => label,
+        }
+    }
+
+    /// The label, marked with the state of the PR if the options say so
+    fn label_with_state(&self, options: &NoteOptions) -> String {
+        match (options.states, options.dialect, self.state) {
+            (false, _, _) => self.label(),
+            // There's no strikethrough
+            (true, NoteDialect::Plain, PrState::Closed) => format!("{} (closed)", self.label()),
+            (true, _, _) => self.marked_label(),
+        }
+    }
+
+    /// The label, checked off if the PR was merged or struck through if it was closed without
+    /// being merged
+    pub fn marked_label(&self) -> String {
+        match self.state {
+            PrState::Open => self.label(),
+            PrState::Merged => format!("✅ {}", self.label()),
+            PrState::Closed => format!("~~{}~~", self.label()),
+        }
+    }
+}
+
+/// The icon of the checks of `pr` and its review decision, like `✅ approved`, or None if neither
+/// is known
+fn badges(pr: &PrStatus) -> Option<String> {
+    let review = match pr.review.as_str() {
+        "" => None,
+        "APPROVED" => Some("approved".to_string()),
+        "CHANGES_REQUESTED" => Some("changes requested".to_string()),
+        "REVIEW_REQUIRED" => Some("review required".to_string()),
+        other => Some(other.to_lowercase().replace('_', " ")),
+    };
+    match (pr.checks.icon(), review) {
+        (Some(icon), Some(review)) => Some(format!("{icon} {review}")),
+        (Some(icon), None) => Some(icon.to_string()),
+        (None, Some(review)) => Some(review),
+        (None, None) => None,
+    }
+}
+
+/// Renders the note of `branch`, which must be part of `stack` (ordered from top to bottom)
+pub fn note_block(
+    forge: &dyn Forge,
+    stack: &[String],
+    branch: String,
+    options: &NoteOptions,
+) -> Result<String, Error> {
+    let note = note_body(forge, stack, branch, options)?;
+    match &options.heading {
+        Some(heading) => Ok(format!("{heading}\n\n{note}")),
+        None => Ok(note),
+    }
+}
+
+fn note_body(
+    forge: &dyn Forge,
+    stack: &[String],
+    branch: String,
+    options: &NoteOptions,
+) -> Result<String, Error> {
+    let format = options.format;
+    let branch_index = stack
+        .iter()
+        .position(|b| b == &branch)
+        .ok_or(eyre!("branch '{}' is not in the stack", branch))?;
+
+    let config = config::load()?;
+    // The bottom PR gets an overview of the whole stack instead, if enabled
+    let overview = config.note_overview && stack.len() > 1 && branch_index + 1 == stack.len();
+    let titles = options.titles || format == NoteFormat::Custom || overview;
+    let labels = Labels::load(forge, titles, options)?;
+    // The stack says what each branch is based on, even when it was found from the PRs and the
+    // branches aren't here
+    let main = main_branch()?;
+    let base_at = |i: usize| stack.get(i + 1).unwrap_or(&main);
+    let pr_at = |i: Option<usize>| -> Result<Option<StackPr>, Error> {
+        match i.and_then(|i| Some((stack.get(i)?, base_at(i)))) {
+            Some((b, base)) => StackPr::for_branch(forge, b, base, &labels),
+            None => Ok(None),
+        }
+    };
+
+    if overview {
+        let rows = stack
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, b)| Ok((b.as_str(), pr_at(Some(i))?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        return note_overview(&branch, &rows, options.dialect);
+    }
+
+    // Other open PRs based on the same branch, when the stack forks. They're found in the open PRs,
+    // which are fetched once for the whole stack, instead of in the local branches
+    let mut siblings = Vec::new();
+    let base = base_at(branch_index);
+    if base != &main {
+        let mut heads: Vec<String> = forge
+            .open_prs()?
+            .into_iter()
+            .filter(|(head, pr)| &pr.base == base && head != &branch)
+            .map(|(head, _)| head)
+            .collect();
+        heads.sort();
+        for head in heads {
+            siblings.extend(StackPr::for_branch(forge, &head, base, &labels)?);
+        }
+    }
+
+    let position = options
+        .position
+        .then(|| format!("PR {} of {}", stack.len() - branch_index, stack.len()));
+    let note = match format {
+        NoteFormat::Double => note_double(
+            pr_at(Some(branch_index + 1))?,
+            pr_at(branch_index.checked_sub(1))?,
+            options.dialect,
+        ),
+        NoteFormat::Table => note_table(
+            pr_at(Some(branch_index + 1))?,
+            pr_at(branch_index.checked_sub(1))?,
+            position.as_deref(),
+            options.dialect,
+        ),
+        NoteFormat::Tree => note_tree(&branch, stack, &main, &pr_at, options),
+        NoteFormat::List | NoteFormat::Details | NoteFormat::Custom => {
+            // Bottom to top
+            let mut prs = Vec::new();
+            for i in (0..stack.len()).rev() {
+                prs.extend(pr_at(Some(i))?);
+            }
+            match format {
+                NoteFormat::List => note_list(&branch, &prs, options),
+                NoteFormat::Details => note_details(&branch, &prs, options),
+                // Templates decide where the siblings go
+                _ => return note_custom(&branch, stack, &prs, &siblings),
+            }
+        }
+    }?;
+    let note = match (position, format, note.split_once('\n')) {
+        (None, _, _) => note,
+        // Tables have it in their header
+        (Some(_), NoteFormat::Table, _) if options.dialect != NoteDialect::Plain => note,
+        // Inside the callout, right below its title
+        (Some(position), NoteFormat::Double, Some((title, rest))) => {
+            format!("{title}\n> {position} in the stack\n{rest}")
+        }
+        (Some(position), _, _) => format!("**{position}** in the stack\n\n{note}"),
+    };
+
+    if siblings.is_empty() {
+        return Ok(note);
+    }
+    let siblings: Vec<String> = siblings
+        .iter()
+        .map(|pr| pr.label_with_state(options))
+        .collect();
+    let siblings = siblings.join(", ");
+    match format {
+        NoteFormat::Double => Ok(format!("{note}\n> - Sibling PRs: {siblings}")),
+        _ => Ok(format!(
+            "{note}\n\nSibling PRs, on the same base: {siblings}"
+        )),
+    }
+}
+
+fn note_double(
+    prev_pr: Option<StackPr>,
+    next_pr: Option<StackPr>,
+    dialect: NoteDialect,
+) -> Result<String, Error> {
+    let mut note = dialect.callout().to_string();
+    if let Some(prev_pr) = prev_pr {
+        note.push_str(&format!("\n> - Previous PR: {}", prev_pr.label()));
+    }
+    if let Some(next_pr) = next_pr {
+        note.push_str(&format!("\n> - Next PR: {}", next_pr.label()));
+    }
+    if note == dialect.callout() {
+        note.push_str("\n> This is currently the only PR in the stack");
+    }
+    Ok(note)
+}
+
+fn note_list(branch: &str, prs: &[StackPr], options: &NoteOptions) -> Result<String, Error> {
+    let mut items = Vec::new();
+    for pr in prs {
+        items.push(format!("- {}", pr.label_with_state(options)));
+        if pr.branch == branch {
+            items.last_mut().unwrap().push_str(" (this)");
+        }
+    }
+    Ok(items.join("\n"))
+}
+
+/// The list of [`note_list`], collapsed
+fn note_details(branch: &str, prs: &[StackPr], options: &NoteOptions) -> Result<String, Error> {
+    let list = note_list(branch, prs, options)?;
+    let size = prs.len();
+    let plural = if size == 1 { "" } else { "s" };
+    // The blank lines let the list inside be rendered as markdown
+    Ok(format!(
+        "<details>\n<summary>Stack ({size} PR{plural})</summary>\n\n{list}\n\n</details>"
+    ))
+}
+
+/// Lists every PR of the stack with its title and status. `rows` go from the bottom of the stack to
+/// the top.
+fn note_overview(
+    branch: &str,
+    rows: &[(&str, Option<StackPr>)],
+    dialect: NoteDialect,
+) -> Result<String, Error> {
+    let mut note = String::new();
+    note.push_str("**Stack overview**, from the bottom to the top\n");
+    // Without tables, it's a list with the same columns
+    if dialect != NoteDialect::Plain {
+        note.push_str("\n| PR | Title | Status |\n");
+        note.push_str("|----|-------|--------|");
+    }
+    for (b, pr) in rows {
+        let (reference, title, status) = match pr {
+            Some(pr) => (
+                match &pr.compare {
+                    Some(url) => format!("{} ([diff]({url}))", pr.reference),
+                    None => pr.reference.clone(),
+                },
+                // A pipe would end the cell early
+                pr.title.clone().unwrap_or_default().replace('|', "\\|"),
+                match (pr.state, &pr.badges) {
+                    (PrState::Open, Some(badges)) => format!("open {badges}"),
+                    (PrState::Open, None) => "open".to_string(),
+                    (PrState::Merged, _) => "✅ merged".to_string(),
+                    (PrState::Closed, _) => "closed".to_string(),
+                },
+            ),
+            None => (format!("`{b}`"), String::new(), "no PR".to_string()),
+        };
+        let this = if *b == branch { " 👈" } else { "" };
+        if dialect == NoteDialect::Plain {
+            let title = if title.is_empty() {
+                title
+            } else {
+                format!(" — {title}")
+            };
+            note.push_str(&format!("\n- {reference}{this}{title}: {status}"));
+        } else {
+            note.push_str(&format!("\n| {reference}{this} | {title} | {status} |"));
+        }
+    }
+    Ok(note)
+}
+
+/// The previous and next PRs side by side, with the `position` of this one between them if given,
+/// like `PR 2 of 5`
+fn note_table(
+    prev_pr: Option<StackPr>,
+    next_pr: Option<StackPr>,
+    position: Option<&str>,
+    dialect: NoteDialect,
+) -> Result<String, Error> {
+    let prev_pr = prev_pr.map_or_else(|| "None".to_string(), |pr| pr.label());
+    let next_pr = next_pr.map_or_else(|| "None".to_string(), |pr| pr.label());
+
+    // Without tables, the columns become the items of a list
+    if dialect == NoteDialect::Plain {
+        return Ok(format!("- Previous PR: {prev_pr}\n- Next PR: {next_pr}"));
+    }
+    let mut note = String::new();
+    match position {
+        Some(position) => {
+            note.push_str(&format!("| Previous PR | {position} | Next PR |\n"));
+            note.push_str("|-------------|---|---------|\n");
+            note.push_str(&format!("| {prev_pr} | 👈 this PR | {next_pr} |"));
+        }
+        None => {
+            note.push_str("| Previous PR | Next PR |\n");
+            note.push_str("|-------------|---------|\n");
+            note.push_str(&format!("| {prev_pr} | {next_pr} |"));
+        }
+    }
+    Ok(note)
+}
+
+/// `stack` goes from the top of the stack to the bottom, which is based on `main`. `pr_at` returns
+/// the PR of the branch at an index of the stack
+fn note_tree(
+    branch: &str,
+    stack: &[String],
+    main: &str,
+    pr_at: &dyn Fn(Option<usize>) -> Result<Option<StackPr>, Error>,
+    options: &NoteOptions,
+) -> Result<String, Error> {
+    let mut lines = Vec::new();
+    for (i, b) in stack.iter().enumerate() {
+        let label = match pr_at(Some(i))? {
+            Some(pr) => pr.label_with_state(options),
+            None => format!("`{b}`"),
+        };
+        if b == branch {
+            lines.push(format!("**{label}** 👈 this PR"));
+        } else {
+            lines.push(label);
+        }
+    }
+    lines.push(format!("`{main}`"));
+
+    let items: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(depth, line)| format!("{}- {line}", "  ".repeat(depth)))
+        .collect();
+    Ok(items.join("\n"))
+}
+
+/// Renders the note template in the config. See the [`template`] module for its syntax.
+/// `stack` goes from the top of the stack to the bottom, and `prs` the other way around.
+fn note_custom(
+    branch: &str,
+    stack: &[String],
+    prs: &[StackPr],
+    siblings: &[StackPr],
+) -> Result<String, Error> {
+    let path = config::load()?
+        .note_template
+        .as_ref()
+        .ok_or_eyre("the custom note format needs a template, pass one with --template")?;
+    let template = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read template {}", path.display()))?;
+
+    let mut items = Vec::new();
+    for b in stack.iter().rev() {
+        let pr = prs.iter().find(|pr| &pr.branch == b);
+        items.push(template::Vars::from([
+            ("branch", b.as_str().into()),
+            ("pr", pr.map(|pr| pr.reference.clone()).into()),
+            ("title", pr.and_then(|pr| pr.title.clone()).into()),
+            ("compare", pr.and_then(|pr| pr.compare.clone()).into()),
+            ("badges", pr.and_then(|pr| pr.badges.clone()).into()),
+            ("this", (b == branch).into()),
+        ]));
+    }
+
+    let index = items
+        .iter()
+        .position(|item| matches!(&item["this"], template::Value::Text(t) if !t.is_empty()))
+        .ok_or_else(|| eyre!("branch '{branch}' is not in the stack"))?;
+    let neighbor = |i: Option<usize>, var: &str| {
+        i.and_then(|i| items.get(i))
+            .map(|item| item[var].clone())
+            .unwrap_or_else(|| "".into())
+    };
+
+    let vars = template::Vars::from([
+        ("branch", branch.into()),
+        ("prev_branch", neighbor(index.checked_sub(1), "branch")),
+        ("prev_pr", neighbor(index.checked_sub(1), "pr")),
+        ("prev_title", neighbor(index.checked_sub(1), "title")),
+        ("next_branch", neighbor(Some(index + 1), "branch")),
+        ("next_pr", neighbor(Some(index + 1), "pr")),
+        ("next_title", neighbor(Some(index + 1), "title")),
+        ("position", (index + 1).to_string().into()),
+        ("size", items.len().to_string().into()),
+        ("stack", items.clone().into()),
+        (
+            "siblings",
+            siblings
+                .iter()
+                .map(|pr| {
+                    template::Vars::from([
+                        ("branch", pr.branch.as_str().into()),
+                        ("pr", pr.reference.as_str().into()),
+                        ("title", pr.title.clone().into()),
+                    ])
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        ),
+    ]);
+    template::render(&template, &vars)
+}
+
+/// What `update_note` did to a PR body or comment
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NoteAction {
+    Updated,
+    /// The body already had the up to date note
+    Unchanged,
+    /// The body would be updated, if this wasn't a dry run
+    WouldUpdate,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NoteUpdate {
+    pub action: NoteAction,
+    /// The PR body before the update, or the note comment in comment mode (empty if there was none)
+    pub old_body: String,
+    /// The PR body or comment with the new note
+    pub body: String,
+}
+
+/// Where the note of a PR is kept
+#[derive(ValueEnum, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NoteMode {
+    /// In the PR body, between the note markers
+    #[default]
+    Body,
+
+    /// In a comment on the PR, which is found again by the note markers and edited in place
+    Comment,
+}
+
+/// Where a note is inserted in a PR body that doesn't have one yet. Notes that are already in a body
+/// are replaced where they are.
+#[derive(ValueEnum, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotePlacement {
+    /// Before the rest of the body
+    #[default]
+    Top,
+
+    /// After the rest of the body
+    Bottom,
+
+    /// Right after the Markdown heading in `note-heading`, or at the bottom if the body doesn't
+    /// have it
+    AfterHeading,
+}
+
+/// Inserts or replaces the note of the given branch, which must be part of `stack`, in its PR body
+/// or comment depending on `note-mode` in the config. With `dry_run`, the PR is left as is.
+pub fn update_note(
+    forge: &dyn Forge,
+    stack: &[String],
+    branch: String,
+    options: &NoteOptions,
+    dry_run: bool,
+) -> Result<NoteUpdate, Error> {
+    let config = config::load()?;
+    let note = note_block(forge, stack, branch.clone(), options)?;
+
+    if config.note_mode.unwrap_or_default() == NoteMode::Comment {
+        return update_note_comment(forge, &branch, &note, dry_run);
+    }
+
+    let body = forge
+        .pr_body(&branch)
+        .with_context(|| format!("failed to get PR body for branch '{branch}'"))?;
+    let new_body = replace_note(&body, &note, config);
+
+    let action = if new_body == body {
+        NoteAction::Unchanged
+    } else if dry_run {
+        NoteAction::WouldUpdate
+    } else {
+        forge.set_pr_body(&branch, &new_body)?;
+        NoteAction::Updated
+    };
+    Ok(NoteUpdate {
+        action,
+        old_body: body,
+        body: new_body,
+    })
+}
+
+/// Posts `note` as a comment on the PR of `branch`, or edits the comment that has the note already
+fn update_note_comment(
+    forge: &dyn Forge,
+    branch: &str,
+    note: &str,
+    dry_run: bool,
+) -> Result<NoteUpdate, Error> {
+    let config = config::load()?;
+    let comments = forge
+        .pr_comments(branch)
+        .with_context(|| format!("failed to get PR comments for branch '{branch}'"))?;
+    let comment = comments
+        .into_iter()
+        .find(|comment| comment.body.contains(config.note_open()));
+    let old_body = comment
+        .as_ref()
+        .map(|comment| comment.body.clone())
+        .unwrap_or_default();
+    let body = format!("{}\n{note}\n{}", config.note_open(), config.note_close());
+
+    // Comments edited in the web UI come back with \r\n line breaks
+    let action = if body == normalize_line_breaks(&old_body) {
+        NoteAction::Unchanged
+    } else if dry_run {
+        NoteAction::WouldUpdate
+    } else {
+        match &comment {
+            Some(comment) => forge.edit_pr_comment(branch, &comment.id, &body)?,
+            None => forge.create_pr_comment(branch, &body)?,
+        }
+        NoteAction::Updated
+    };
+    Ok(NoteUpdate {
+        action,
+        old_body,
+        body,
+    })
+}
+
+/// Removes the note from the PR body of `branch`. Returns whether the body had a note.
+pub fn remove_note(forge: &dyn Forge, branch: &str) -> Result<bool, Error> {
+    let body = forge
+        .pr_body(branch)
+        .with_context(|| format!("failed to get PR body for branch '{branch}'"))?;
+    let Some(new_body) = strip_note(&body, config::load()?) else {
+        return Ok(false);
+    };
+    forge.set_pr_body(branch, &new_body)?;
+    Ok(true)
+}
+
+/// Runs [`update_note`] for every branch of `stack`, with up to `jobs` branches being updated at
+/// the same time. The results are in the same order as `stack`. With `fail_fast`, no more branches
+/// are started after one fails, and the ones that weren't get an error saying so.
+pub fn update_notes(
+    forge: &dyn Forge,
+    stack: &[String],
+    options: &NoteOptions,
+    dry_run: bool,
+    jobs: usize,
+    fail_fast: bool,
+) -> Vec<Result<NoteUpdate, Error>> {
+    update_some_notes(forge, stack, stack, options, dry_run, jobs, fail_fast)
+}
+
+/// Like [`update_notes`], but only updates the PRs of `branches`, which should be part of
+/// `stack`. The notes still list the whole stack. The results are in the same order as
+/// `branches`.
+pub fn update_some_notes(
+    forge: &dyn Forge,
+    stack: &[String],
+    branches: &[String],
+    options: &NoteOptions,
+    dry_run: bool,
+    jobs: usize,
+    fail_fast: bool,
+) -> Vec<Result<NoteUpdate, Error>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results: Mutex<Vec<_>> = Mutex::new(branches.iter().map(|_| None).collect());
+    let title = if dry_run {
+        "Checking notes"
+    } else {
+        "Updating notes"
+    };
+    let progress = Progress::new(title, branches.len());
+
+    // Forges that can read all the PR bodies at once can edit them at once too, so the notes are
+    // only checked in parallel, and the edits are sent together at the end
+    let body_mode = config::load()
+        .map(|config| config.note_mode.unwrap_or_default() == NoteMode::Body)
+        .unwrap_or(false);
+    let bulk = !dry_run
+        && body_mode
+        && match forge.pr_bodies(branches) {
+            Ok(bodies) => bodies.is_some(),
+            Err(e) => {
+                tracing::debug!("failed to get the PR bodies at once: {e}");
+                false
+            }
+        };
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, branches.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(branch) = branches.get(i) else {
+                    break;
+                };
+                let result = if fail_fast && failed.load(Ordering::Relaxed) {
+                    progress.skip();
+                    Err(eyre!(
+                        "skipped, since the note of another branch failed to update"
+                    ))
+                } else {
+                    if dry_run {
+                        progress.start(format!("Checking the note of {branch}"));
+                    } else {
+                        progress.start(format!("Updating notes for {branch}"));
+                    }
+                    let result =
+                        update_note(forge, stack, branch.clone(), options, dry_run || bulk);
+                    progress.finish_step();
+                    result
+                };
+                if result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    let results: Vec<_> = results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every branch is updated by some thread"))
+        .collect();
+    if bulk {
+        set_bodies(forge, branches, results, fail_fast && failed.into_inner())
+    } else {
+        results
+    }
+}
+
+/// Edits the PR bodies that `results`, the dry run of the updates of `branches`, would update,
+/// all at once. If `skip` is true, they're reported as skipped instead
+fn set_bodies(
+    forge: &dyn Forge,
+    branches: &[String],
+    results: Vec<Result<NoteUpdate, Error>>,
+    skip: bool,
+) -> Vec<Result<NoteUpdate, Error>> {
+    let pending = |result: &Result<NoteUpdate, Error>| matches!(result, Ok(update) if update.action == NoteAction::WouldUpdate);
+    if skip {
+        return results
+            .into_iter()
+            .map(|result| {
+                if pending(&result) {
+                    Err(eyre!(
+                        "skipped, since the note of another branch failed to update"
+                    ))
+                } else {
+                    result
+                }
+            })
+            .collect();
+    }
+
+    let bodies: Vec<(String, String)> = branches
+        .iter()
+        .zip(&results)
+        .filter(|(_, result)| pending(result))
+        .map(|(branch, result)| {
+            let body = result.as_ref().map(|update| update.body.clone());
+            (branch.clone(), body.unwrap_or_default())
+        })
+        .collect();
+    let mut edited = forge.set_pr_bodies(&bodies).into_iter();
+    results
+        .into_iter()
+        .map(|result| {
+            if !pending(&result) {
+                return result;
+            }
+            let update = result?;
+            edited
+                .next()
+                .unwrap_or_else(|| Err(eyre!("the PR body wasn't edited")))?;
+            Ok(NoteUpdate {
+                action: NoteAction::Updated,
+                ..update
+            })
+        })
+        .collect()
+}
+
+/// The outcome of updating the notes of every PR in a stack
+#[derive(Serialize, Debug, Default)]
+pub struct UpdateReport {
+    pub branches: Vec<BranchUpdate>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BranchUpdate {
+    pub branch: String,
+    /// None if the update failed
+    pub action: Option<NoteAction>,
+    pub error: Option<String>,
+}
+
+impl UpdateReport {
+    pub fn push(&mut self, branch: String, result: &Result<NoteUpdate, Error>) {
+        let (action, error) = match result {
+            Ok(update) => (Some(update.action), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        self.branches.push(BranchUpdate {
+            branch,
+            action,
+            error,
+        });
+    }
+
+    /// The branches whose notes couldn't be updated or checked
+    pub fn failed(&self) -> Vec<&str> {
+        self.branches
+            .iter()
+            .filter(|update| update.error.is_some())
+            .map(|update| update.branch.as_str())
+            .collect()
+    }
+
+    /// What happened to every branch, one per line, like `feature  updated`
+    pub fn summary(&self) -> String {
+        let width = self
+            .branches
+            .iter()
+            .map(|update| update.branch.chars().count())
+            .max()
+            .unwrap_or(0);
+        let lines: Vec<String> = self
+            .branches
+            .iter()
+            .map(|update| {
+                let outcome = match (update.action, &update.error) {
+                    (_, Some(error)) => format!("failed: {error}"),
+                    (Some(NoteAction::Updated), _) => "updated".to_string(),
+                    (Some(NoteAction::Unchanged), _) => "up to date".to_string(),
+                    (Some(NoteAction::WouldUpdate), _) => "would be updated".to_string(),
+                    (None, None) => "failed".to_string(),
+                };
+                format!("{:width$}  {outcome}", update.branch)
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    /// The branches whose notes are not up to date, including the ones that couldn't be checked
+    pub fn stale(&self) -> Vec<&str> {
+        self.branches
+            .iter()
+            .filter(|update| update.action != Some(NoteAction::Unchanged))
+            .map(|update| update.branch.as_str())
+            .collect()
+    }
+}
+
+fn replace_note(pr_body: &str, note: &str, config: &Config) -> String {
+    let (open_marker, close_marker) = (config.note_open(), config.note_close());
+    // Only the note is rewritten, so the rest of the body keeps its line breaks byte for byte, even
+    // when they're mixed, and the note uses the ones of the body
+    let newline = line_break(pr_body);
+    let note = normalize_line_breaks(note);
+    let block = format!("{open_marker}\n{note}\n{close_marker}\n").replace('\n', newline);
+
+    let open = pr_body.find(open_marker);
+    let close = pr_body.find(close_marker);
+    match (open, close) {
+        (Some(open), Some(close)) if open < close => {
+            let before = &pr_body[..open];
+            let after = after_note(&pr_body[close + close_marker.len()..]);
+            format!("{before}{block}{after}")
+        }
+        _ => insert_note(pr_body, &block, newline, config),
+    }
+}
+
+/// Inserts `block`, a note between its markers, where `note-placement` in the config says, with
+/// `newline` as the line break between them
+fn insert_note(pr_body: &str, block: &str, newline: &str, config: &Config) -> String {
+    let heading_end = match config.note_placement.unwrap_or_default() {
+        NotePlacement::Top => return format!("{block}{pr_body}"),
+        NotePlacement::Bottom => None,
+        NotePlacement::AfterHeading => config
+            .note_heading
+            .as_deref()
+            .and_then(|heading| find_heading(pr_body, heading)),
+    };
+    match heading_end {
+        Some(end) => {
+            let (before, after) = pr_body.split_at(end);
+            let newline = if before.ends_with('\n') { "" } else { newline };
+            format!("{before}{newline}{block}{after}")
+        }
+        None if pr_body.trim().is_empty() => block.to_string(),
+        None => format!("{}{newline}{newline}{block}", pr_body.trim_end()),
+    }
+}
+
+/// Returns where the line of the Markdown heading with the text `heading` ends, including its line
+/// break. Any level of heading matches, and case is ignored.
+fn find_heading(pr_body: &str, heading: &str) -> Option<usize> {
+    let mut start = 0;
+    for line in pr_body.split_inclusive('\n') {
+        let end = start + line.len();
+        let text = line.trim();
+        if text.starts_with('#')
+            && text
+                .trim_start_matches('#')
+                .trim()
+                .eq_ignore_ascii_case(heading.trim_start_matches('#').trim())
+        {
+            return Some(end);
+        }
+        start = end;
+    }
+    None
+}
+
+/// Returns the PR body without its note, or None if it has no note
+fn strip_note(pr_body: &str, config: &Config) -> Option<String> {
+    let (open_marker, close_marker) = (config.note_open(), config.note_close());
+    let open = pr_body.find(open_marker)?;
+    let close = pr_body.find(close_marker).filter(|&close| open < close)?;
+    let before = &pr_body[..open];
+    let after = after_note(&pr_body[close + close_marker.len()..]);
+    Some(format!("{before}{after}"))
+}
+
+/// Skips the line break that follows the note when stackbuddy inserted it, so that replacing the
+/// note again doesn't add one more every time
+fn after_note(after: &str) -> &str {
+    after
+        .strip_prefix("\r\n")
+        .or_else(|| after.strip_prefix('\n'))
+        .unwrap_or(after)
+}
+
+/// Converts `\r\n` line breaks to `\n`. Bodies written on Windows or in the web UI of forges often
+/// have them.
+fn normalize_line_breaks(body: &str) -> String {
+    body.replace("\r\n", "\n")
+}
+
+/// The line break that notes in `body` use: `\r\n` if the body has any, so the note matches the
+/// lines around it
+fn line_break(body: &str) -> &'static str {
+    if body.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = "- #1\n- #2 👈";
+
+    fn config(placement: NotePlacement) -> Config {
+        Config {
+            note_placement: Some(placement),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn crlf_body_round_trips() {
+        let config = config(NotePlacement::Top);
+        let body = "Fixes the parser.\r\n\r\nSee the issue.\r\n";
+        let with_note = replace_note(body, NOTE, &config);
+
+        assert!(
+            !with_note.replace("\r\n", "").contains('\n'),
+            "{with_note:?}"
+        );
+        assert_eq!(strip_note(&with_note, &config).unwrap(), body);
+    }
+
+    #[test]
+    fn mixed_line_breaks_are_kept() {
+        let config = config(NotePlacement::Bottom);
+        let body = "Line one\r\nLine two\nLine three\r\n";
+        let once = replace_note(body, NOTE, &config);
+
+        assert_eq!(replace_note(&once, NOTE, &config), once);
+        // The lines of the body are left as they were, and the note uses \r\n
+        assert!(once.starts_with("Line one\r\nLine two\nLine three\r\n"));
+        assert!(once.ends_with(&format!("{}\r\n", Config::default().note_close())));
+    }
+
+    #[test]
+    fn replacing_twice_changes_nothing() {
+        for body in [
+            "",
+            "Description\n",
+            "Description",
+            "Windows\r\ndescription\r\n",
+        ] {
+            let config = config(NotePlacement::Bottom);
+            let once = replace_note(body, NOTE, &config);
+            let twice = replace_note(&once, NOTE, &config);
+
+            assert_eq!(once, twice);
+            let normalized = normalize_line_breaks(&twice);
+            assert!(!normalized.contains("\n\n\n"), "{twice:?}");
+            assert!(!normalized.ends_with("\n\n"), "{twice:?}");
+        }
+    }
+
+    #[test]
+    fn placement_survives_a_second_run() {
+        let body = "Intro\n\n## Stack\n\n## Testing\nRan it.\n";
+        for placement in [
+            NotePlacement::Top,
+            NotePlacement::Bottom,
+            NotePlacement::AfterHeading,
+        ] {
+            let config = Config {
+                note_heading: Some("Stack".to_string()),
+                ..config(placement)
+            };
+            let once = replace_note(body, NOTE, &config);
+            assert_eq!(replace_note(&once, NOTE, &config), once, "{placement:?}");
+
+            // A new note goes where the old one was
+            let updated = replace_note(&once, "- #1 👈", &config);
+            let position = |body: &str| body.find(config.note_open()).unwrap();
+            assert_eq!(position(&updated), position(&once), "{placement:?}");
+            assert_eq!(replace_note(&updated, "- #1 👈", &config), updated);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Commands that rebase several branches, like `restack` and `reorder`, save what's left to do in
+//! `.git/stackbuddy/`, so that when a rebase stops because of conflicts, `stackbuddy continue`
+//! can pick up where it stopped once they're resolved, and `stackbuddy abort` can put every
+//! branch back where it was.
+
+use crate::{
+    checkout, git, git_dir,
+    progress::Progress,
+    rebase::{rebase_onto, RebaseStep},
+    rev_parse, NoteFormat,
+};
+use eyre::{eyre, Context, Error};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// What's left to do after the rebases of an operation are done
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum Then {
+    Restack,
+    /// Push the branches of `stack`
+    Sync {
+        stack: Vec<String>,
+    },
+    /// Record `onto` as the parent of `branch`, and fix the PRs of the branches that moved
+    Move {
+        branch: String,
+        onto: String,
+        old_parent: String,
+        moved: Vec<String>,
+        format: NoteFormat,
+    },
+    /// Record the new parents of the branches of `order`, bottom to top, and fix their PRs
+    Reorder {
+        order: Vec<String>,
+        format: NoteFormat,
+    },
+}
+
+/// A multi-branch rebase in progress
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Operation {
+    /// The branch that was checked out before the operation started
+    pub original: String,
+    /// Every branch that is rebased, with the commit it pointed to before
+    pub tips: Vec<(String, String)>,
+    /// The rebases that haven't finished yet, including the one that stopped
+    pub steps: Vec<RebaseStep>,
+    pub then: Then,
+}
+
+impl Operation {
+    /// Starts an operation that runs `steps` and then does `then`. Fails if another one is in
+    /// progress.
+    pub fn start(original: String, steps: Vec<RebaseStep>, then: Then) -> Result<Self, Error> {
+        if path()?.exists() {
+            return Err(eyre!(
+                "another operation is in progress, run `stackbuddy continue` or `stackbuddy abort` first"
+            ));
+        }
+        let tips = steps
+            .iter()
+            .map(|step| Ok((step.branch.clone(), rev_parse(&step.branch)?)))
+            .collect::<Result<_, Error>>()?;
+        let op = Self {
+            original,
+            tips,
+            steps,
+            then,
+        };
+        op.save()?;
+        Ok(op)
+    }
+
+    /// Runs the remaining rebases, saving the progress after every one of them
+    pub fn run(&mut self) -> Result<(), Error> {
+        let progress = Progress::new("Rebasing", self.steps.len());
+        while let Some(step) = self.steps.first() {
+            progress.start(format!("Rebasing {} onto {}", step.branch, step.onto));
+            rebase_onto(&step.branch, &step.onto, &step.upstream).map_err(|e| {
+                eyre!(
+                    "{e}. Resolve the conflicts, stage them with `git add` and run `stackbuddy continue`, or run `stackbuddy abort` to put every branch back where it was"
+                )
+            })?;
+            self.steps.remove(0);
+            self.save()?;
+            progress.finish_step();
+        }
+        Ok(())
+    }
+
+    /// Loads the operation in progress and finishes the rebase that stopped, so the remaining
+    /// ones can be run
+    pub fn resume() -> Result<Self, Error> {
+        let mut op = Self::load()?;
+        let Some(step) = op.steps.first() else {
+            return Ok(op);
+        };
+
+        let done = if rebase_in_progress()? {
+            let success = git::status(&["-c", "core.editor=true", "rebase", "--continue"])
+                .context("git rebase --continue failed")?;
+            if !success {
+                return Err(eyre!(
+                    "the rebase can't continue yet. Resolve the conflicts, stage them with `git add` and run `stackbuddy continue` again"
+                ));
+            }
+            true
+        } else {
+            // The rebase was either continued by hand, which moved the branch, or aborted, in
+            // which case it has to run again
+            let tip = op.tips.iter().find(|(branch, _)| branch == &step.branch);
+            tip.is_some_and(|(_, tip)| rev_parse(&step.branch).is_ok_and(|now| &now != tip))
+        };
+        if done {
+            op.steps.remove(0);
+            op.save()?;
+        }
+        Ok(op)
+    }
+
+    /// Stops the operation in progress and moves every branch back to where it was before
+    pub fn abort() -> Result<(), Error> {
+        let op = Self::load()?;
+        if rebase_in_progress()? {
+            let success =
+                git::status(&["rebase", "--abort"]).context("git rebase --abort failed")?;
+            if !success {
+                return Err(eyre!("failed to abort the rebase"));
+            }
+        }
+
+        // Branches can't be moved while they're checked out
+        let success =
+            git::status(&["checkout", "--quiet", "--detach"]).context("git checkout failed")?;
+        if !success {
+            return Err(eyre!("failed to detach HEAD"));
+        }
+        for (branch, tip) in &op.tips {
+            let success = git::status(&["update-ref", &format!("refs/heads/{branch}"), tip])
+                .context("git update-ref failed")?;
+            if !success {
+                return Err(eyre!("failed to move '{branch}' back to {tip}"));
+            }
+        }
+        checkout(&op.original)?;
+        op.clear()
+    }
+
+    fn load() -> Result<Self, Error> {
+        let path = path()?;
+        if !path.exists() {
+            return Err(eyre!("there's no operation in progress"));
+        }
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let path = path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Whether an operation was started and hasn't finished or been aborted yet
+    pub fn in_progress() -> Result<bool, Error> {
+        Ok(path()?.exists())
+    }
+
+    /// Forgets the operation, once it's done
+    pub fn clear(&self) -> Result<(), Error> {
+        let path = path()?;
+        std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))
+    }
+}
+
+fn path() -> Result<PathBuf, Error> {
+    Ok(git_dir()?.join("stackbuddy").join("operation.json"))
+}
+
+fn rebase_in_progress() -> Result<bool, Error> {
+    let dir = git_dir()?;
+    Ok(dir.join("rebase-merge").exists() || dir.join("rebase-apply").exists())
+}
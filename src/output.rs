@@ -0,0 +1,161 @@
+//! Colors for what commands print. By default they're used when stdout is a terminal and the
+//! `NO_COLOR` environment variable isn't set, and `--color` picks otherwise.
+
+use crate::forge::{Checks, PrState};
+use clap::ValueEnum;
+use crossterm::style::{Color, Stylize};
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Colors when stdout is a terminal and NO_COLOR isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Decides whether the output is colored, once, before anything is printed
+pub fn init(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Auto => {
+            // As https://no-color.org says, an empty NO_COLOR doesn't count
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            !no_color && std::io::stdout().is_terminal()
+        }
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Stops printing what's going on, with [`info!`](crate::info) and progress bars
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+fn paint(text: &str, color: Color) -> String {
+    if !enabled() {
+        return text.to_string();
+    }
+    text.with(color).to_string()
+}
+
+/// The current branch, which stands out from the rest
+pub fn current(text: &str) -> String {
+    if !enabled() {
+        return text.to_string();
+    }
+    text.with(Color::Green).bold().to_string()
+}
+
+/// `branch`, highlighted if it's the `current` one
+pub fn branch(branch: &str, current: Option<&str>) -> String {
+    if current == Some(branch) {
+        self::current(branch)
+    } else {
+        branch.to_string()
+    }
+}
+
+/// Something that's less important than what's around it, like a missing value
+pub fn dim(text: &str) -> String {
+    paint(text, Color::DarkGrey)
+}
+
+pub fn error(text: &str) -> String {
+    if !enabled() {
+        return text.to_string();
+    }
+    text.with(Color::Red).bold().to_string()
+}
+
+pub fn warning(text: &str) -> String {
+    paint(text, Color::Yellow)
+}
+
+/// Lines that were added, in a diff stat
+pub fn added(text: &str) -> String {
+    paint(text, Color::Green)
+}
+
+/// Lines that were deleted, in a diff stat
+pub fn deleted(text: &str) -> String {
+    paint(text, Color::Red)
+}
+
+/// `text` colored as a PR in `state` is
+pub fn pr_state(text: &str, state: PrState) -> String {
+    match state {
+        PrState::Open => paint(text, Color::Green),
+        PrState::Merged => paint(text, Color::Magenta),
+        PrState::Closed => paint(text, Color::Red),
+    }
+}
+
+/// The summary of the checks of a PR, red if any failed and yellow if any are pending
+pub fn checks(checks: &Checks) -> String {
+    let text = checks.to_string();
+    if checks.failed > 0 {
+        paint(&text, Color::Red)
+    } else if checks.pending > 0 {
+        paint(&text, Color::Yellow)
+    } else if checks.passed > 0 {
+        paint(&text, Color::Green)
+    } else {
+        dim(&text)
+    }
+}
+
+/// A review decision, e.g. `APPROVED`, shown in lowercase
+pub fn review(review: &str) -> String {
+    let text = review.to_lowercase().replace('_', " ");
+    match review {
+        "APPROVED" => paint(&text, Color::Green),
+        "CHANGES_REQUESTED" => paint(&text, Color::Red),
+        _ => text,
+    }
+}
+
+/// How long ago something happened, e.g. `5 minutes ago`
+pub fn ago(elapsed: Duration) -> String {
+    let minutes = elapsed.as_secs() / 60;
+    let (n, unit) = match minutes {
+        0 => return "just now".to_string(),
+        1..=59 => (minutes, "minute"),
+        60..=1439 => (minutes / 60, "hour"),
+        _ => (minutes / 1440, "day"),
+    };
+    let plural = if n == 1 { "" } else { "s" };
+    format!("{n} {unit}{plural} ago")
+}
+
+/// How many characters of `text` show up on the screen, without the escape sequences of colors
+pub fn width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Colors are `ESC [ ... m`
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
@@ -0,0 +1,73 @@
+//! Like git and cargo, commands that stackbuddy doesn't know are run as `stackbuddy-<name>`
+//! programs from the PATH, so teams can build their own stack tooling on top of it. Plugins are
+//! told about the repository and the current stack in environment variables only, since their
+//! stdin is left to whoever runs them, e.g. for prompts or piped input.
+
+use crate::{config, config::Config, current_branch, repo, top_of, Stack};
+use eyre::{eyre, Context, Error};
+use serde::Serialize;
+use std::{io::ErrorKind, process::ExitStatus};
+
+/// What a plugin is told about the repository, as JSON in `STACKBUDDY_CONTEXT`
+#[derive(Serialize, Debug, Clone)]
+pub struct PluginContext {
+    /// The version of stackbuddy that ran the plugin
+    pub version: &'static str,
+    /// The branch that is checked out, or None if HEAD is detached
+    pub branch: Option<String>,
+    /// The whole stack that contains the current branch, or None if HEAD is detached or the stack
+    /// can't be found
+    pub stack: Option<Stack>,
+    pub remote: String,
+}
+
+impl PluginContext {
+    /// The context of the repository in the current directory. It's only a convenience for plugins,
+    /// so what can't be found, like the stack when a parent is wrong, is left out instead of
+    /// keeping the plugin from running.
+    pub fn current() -> Self {
+        let branch = current_branch()
+            .inspect_err(|e| tracing::debug!("no current branch for the plugin context: {e}"))
+            .ok();
+        let stack = branch.as_ref().and_then(|branch| {
+            Stack::from_branch(top_of(branch).unwrap_or(branch.clone()))
+                .inspect_err(|e| tracing::debug!("no stack for the plugin context: {e}"))
+                .ok()
+        });
+        let remote = match config::load() {
+            Ok(config) => config.remote().to_string(),
+            Err(e) => {
+                tracing::debug!("failed to load the config for the plugin context: {e}");
+                Config::default().remote().to_string()
+            }
+        };
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            branch,
+            stack,
+            remote,
+        }
+    }
+}
+
+/// Runs the plugin `stackbuddy-<name>` with `args` in the repository, and returns how it exited.
+/// Besides `STACKBUDDY_CONTEXT`, it gets the path of the stackbuddy executable in `STACKBUDDY`,
+/// so it can call back into it.
+pub fn run(name: &str, args: &[String]) -> Result<ExitStatus, Error> {
+    let program = format!("stackbuddy-{name}");
+    let context = serde_json::to_string(&PluginContext::current())?;
+    let exe = std::env::current_exe().context("failed to find the stackbuddy executable")?;
+    let status = repo::current()
+        .command(&program)
+        .args(args)
+        .env("STACKBUDDY_CONTEXT", context)
+        .env("STACKBUDDY", exe)
+        .status();
+    match status {
+        Ok(status) => Ok(status),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(eyre!(
+            "unrecognized subcommand '{name}', and there's no {program} plugin in the PATH"
+        )),
+        Err(e) => Err(e).with_context(|| format!("failed to run {program}")),
+    }
+}
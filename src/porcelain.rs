@@ -0,0 +1,138 @@
+//! The `--porcelain` output of `parent`, `stack`, `status` and `update-notes`, for scripts. Unlike
+//! the human output, which can change between releases, a version of this format never changes:
+//! new versions are added instead, and picked with `--porcelain=<version>`.
+//!
+//! # Version 1
+//!
+//! Every line is a record, with fields separated by tabs. The first field names the record, and
+//! the first line is always `version 1`. Values that are unknown or missing are empty fields, and
+//! tabs and line breaks inside values are replaced with spaces. The records are:
+//!
+//! - `parent <branch> <parent> <confidence>`: the parent of a branch, where the confidence is
+//!   `low`, `medium` or `high` for guesses with the `best` strategy, and empty otherwise
+//! - `base <branch>`: the branch a stack is based on, before its `branch` records
+//! - `branch <branch>`: a branch of the stack, from the top to the bottom
+//! - `status <branch> <pr> <draft> <mergeable> <passed> <failed> <pending> <review>`: the PR of a
+//!   branch of the stack, from the top to the bottom. `pr` is its number, `draft` is `true` or
+//!   `false`, `mergeable` and `review` are what the forge says, like `MERGEABLE` and `APPROVED`,
+//!   and the others count its checks. Every field but the branch is empty if it has no open PR
+//! - `stat <branch> <parent> <ahead> <behind-remote> <files> <additions> <deletions>`: with
+//!   `--stat`, after the `status` record of the branch. `behind-remote` is empty if the branch
+//!   wasn't pushed
+//! - `note <branch> <action> <error>`: what update-notes did to the note of a branch, where the
+//!   action is `updated`, `unchanged` or `would-update`, or empty if it failed with `error`
+
+use crate::{
+    forge::Forge,
+    note::{NoteAction, UpdateReport},
+    BranchStats, Stack,
+};
+use eyre::{eyre, Error};
+
+/// The versions of the format that can be asked for
+pub const VERSIONS: &[u32] = &[1];
+
+/// Fails if `version` isn't one of [`VERSIONS`]
+pub fn check_version(version: u32) -> Result<(), Error> {
+    if !VERSIONS.contains(&version) {
+        return Err(eyre!(
+            "unknown porcelain version {version}, the supported ones are {VERSIONS:?}"
+        ));
+    }
+    Ok(())
+}
+
+/// Joins `records` into lines after the version line
+fn render(records: Vec<Vec<String>>) -> String {
+    let mut lines = vec!["version\t1".to_string()];
+    for record in records {
+        let fields: Vec<String> = record
+            .iter()
+            .map(|field| field.replace(['\t', '\n', '\r'], " "))
+            .collect();
+        lines.push(fields.join("\t"));
+    }
+    lines.join("\n")
+}
+
+fn record<const N: usize>(fields: [&str; N]) -> Vec<String> {
+    fields.map(str::to_string).to_vec()
+}
+
+pub fn parent(branch: &str, parent: Option<&str>, confidence: Option<&str>) -> String {
+    render(vec![record([
+        "parent",
+        branch,
+        parent.unwrap_or_default(),
+        confidence.unwrap_or_default(),
+    ])])
+}
+
+pub fn stack(stack: &Stack) -> String {
+    let mut records = vec![record(["base", &stack.base])];
+    records.extend(stack.branches.iter().map(|b| record(["branch", b])));
+    render(records)
+}
+
+/// `stack` goes from the top to the bottom, and `stats` are in the same order
+pub fn status(
+    forge: &dyn Forge,
+    stack: &[String],
+    stats: Option<&[BranchStats]>,
+) -> Result<String, Error> {
+    let prs = forge.open_prs()?;
+    let mut records = Vec::new();
+    for (i, branch) in stack.iter().enumerate() {
+        records.push(match prs.get(branch) {
+            Some(pr) => vec![
+                "status".to_string(),
+                branch.clone(),
+                pr.number.clone(),
+                pr.draft.to_string(),
+                pr.mergeable.clone(),
+                pr.checks.passed.to_string(),
+                pr.checks.failed.to_string(),
+                pr.checks.pending.to_string(),
+                pr.review.clone(),
+            ],
+            None => record(["status", branch, "", "", "", "", "", "", ""]),
+        });
+        if let Some(stat) = stats.and_then(|stats| stats.get(i)) {
+            records.push(vec![
+                "stat".to_string(),
+                stat.branch.clone(),
+                stat.parent.clone(),
+                stat.ahead.to_string(),
+                stat.behind_remote
+                    .map(|n| n.to_string())
+                    .unwrap_or_default(),
+                stat.diff.files.to_string(),
+                stat.diff.additions.to_string(),
+                stat.diff.deletions.to_string(),
+            ]);
+        }
+    }
+    Ok(render(records))
+}
+
+pub fn update_report(report: &UpdateReport) -> String {
+    let records = report
+        .branches
+        .iter()
+        .map(|update| {
+            let action = match update.action {
+                Some(NoteAction::Updated) => "updated",
+                Some(NoteAction::Unchanged) => "unchanged",
+                Some(NoteAction::WouldUpdate) => "would-update",
+                None => "",
+            };
+            record([
+                "note",
+                &update.branch,
+                action,
+                update.error.as_deref().unwrap_or_default(),
+            ])
+        })
+        .collect();
+    render(records)
+}
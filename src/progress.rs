@@ -0,0 +1,82 @@
+//! Progress of operations that go through the branches of a stack, which can take a while with
+//! a forge call or a rebase for each of them. On a terminal it's a progress bar with the elapsed
+//! time, and otherwise a line is printed as each branch starts. Either way it goes to stderr, and
+//! `--quiet` hides it.
+
+use crate::output;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    fmt::Display,
+    io::IsTerminal,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+pub struct Progress {
+    /// None if stderr isn't a terminal or the output is quiet
+    bar: Option<ProgressBar>,
+    len: usize,
+    started: AtomicUsize,
+}
+
+impl Progress {
+    /// Starts reporting the progress of `len` steps, with `title` describing all of them, e.g.
+    /// "Restacking"
+    pub fn new(title: &'static str, len: usize) -> Self {
+        let bar = (!output::quiet() && std::io::stderr().is_terminal()).then(|| {
+            let bar = ProgressBar::with_draw_target(Some(len as u64), ProgressDrawTarget::stderr());
+            let style = ProgressStyle::with_template(
+                "{spinner} {prefix} [{bar:20}] {pos}/{len} {elapsed:>4} {wide_msg}",
+            )
+            .expect("the template is valid")
+            .progress_chars("=> ");
+            bar.set_style(style);
+            bar.set_prefix(title);
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        });
+        Self {
+            bar,
+            len,
+            started: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reports that a step started, e.g. "Rebasing a onto b"
+    pub fn start(&self, step: impl Display) {
+        let position = self.started.fetch_add(1, Ordering::Relaxed) + 1;
+        match &self.bar {
+            Some(bar) => bar.set_message(step.to_string()),
+            None => info!("[{position}/{}] {step}...", self.len),
+        }
+    }
+
+    /// Reports that a step finished
+    pub fn finish_step(&self) {
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        }
+    }
+
+    /// Reports that a step was skipped, since there was nothing to do
+    pub fn skip(&self) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+        self.finish_step();
+    }
+
+    /// Prints a line of output to stdout, hiding the progress bar meanwhile
+    pub fn println(&self, line: impl Display) {
+        match &self.bar {
+            Some(bar) => bar.suspend(|| println!("{line}")),
+            None => println!("{line}"),
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
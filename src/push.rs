@@ -0,0 +1,150 @@
+use crate::{config, git};
+use eyre::{eyre, Context, Error};
+
+/// Fetches from the remote, and from the fork branches are pushed to, if there's one
+pub fn fetch() -> Result<(), Error> {
+    let config = config::load()?;
+    let mut remotes = vec![config.remote()];
+    if config.push_remote() != config.remote() {
+        remotes.push(config.push_remote());
+    }
+    for remote in remotes {
+        let success = git::status(&["fetch", "--quiet", remote]).context("git fetch failed")?;
+
+        if !success {
+            return Err(eyre!("failed to fetch from {remote}"));
+        }
+    }
+    Ok(())
+}
+
+pub fn push_branch(branch: &str) -> Result<(), Error> {
+    let remote = config::load()?.push_remote();
+    let success = git::status(&["push", "--quiet", "--set-upstream", remote, branch])
+        .context("git push failed")?;
+
+    if !success {
+        return Err(eyre!("failed to push '{branch}'"));
+    }
+    Ok(())
+}
+
+pub fn force_push(branches: &[String]) -> Result<(), Error> {
+    let mut args = vec![
+        "push",
+        "--quiet",
+        "--force-with-lease",
+        config::load()?.push_remote(),
+    ];
+    args.extend(branches.iter().map(String::as_str));
+    let success = git::status(&args).context("git push failed")?;
+
+    if !success {
+        return Err(eyre!("failed to push {}", branches.join(", ")));
+    }
+    Ok(())
+}
+
+pub fn delete_remote_branch(branch: &str) -> Result<(), Error> {
+    let remote = config::load()?.push_remote();
+    let success = git::status(&["push", "--quiet", remote, "--delete", branch])
+        .context("git push --delete failed")?;
+
+    if !success {
+        return Err(eyre!("failed to delete '{branch}' from {remote}"));
+    }
+    Ok(())
+}
+
+/// Asks the remote whether it has `branch`, since the remote-tracking branch may be stale, e.g.
+/// when the forge deleted the branch after merging its PR
+pub fn remote_branch_exists(branch: &str) -> Result<bool, Error> {
+    let remote = config::load()?.push_remote();
+    let output = git::output(&["ls-remote", remote, &format!("refs/heads/{branch}")])
+        .context("git ls-remote failed")?;
+
+    if !output.success {
+        return Err(eyre!(
+            "failed to list the branches of {remote}: {}",
+            output.stderr.trim()
+        ));
+    }
+    Ok(!output.stdout.trim().is_empty())
+}
+
+/// How a local branch compares to its copy on the remote, as of the last fetch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteState {
+    /// The branch isn't on the remote yet
+    New,
+    /// The remote has the same commit
+    UpToDate,
+    /// The local branch only adds commits on top of the remote one
+    Ahead,
+    /// The local branch was rewritten, e.g. by a restack, from a commit it had before
+    Rewritten,
+    /// The remote has a commit the local branch never had, like one a teammate pushed.
+    /// Force-pushing would throw it away.
+    Diverged,
+}
+
+impl RemoteState {
+    /// Whether pushing the branch can't lose anything
+    pub fn safe_to_push(self) -> bool {
+        matches!(
+            self,
+            RemoteState::New | RemoteState::Ahead | RemoteState::Rewritten
+        )
+    }
+}
+
+impl std::fmt::Display for RemoteState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            RemoteState::New => "new",
+            RemoteState::UpToDate => "up to date",
+            RemoteState::Ahead => "ahead",
+            RemoteState::Rewritten => "rewritten",
+            RemoteState::Diverged => "diverged",
+        };
+        write!(f, "{state}")
+    }
+}
+
+/// Compares `branch` to its remote-tracking branch. Call [`fetch`] first for an up to date answer.
+pub fn remote_state(branch: &str) -> Result<RemoteState, Error> {
+    let remote_branch = format!("{}/{branch}", config::load()?.push_remote());
+    let local = rev_parse(branch)?.ok_or_else(|| eyre!("branch '{branch}' doesn't exist"))?;
+    let Some(remote) = rev_parse(&remote_branch)? else {
+        return Ok(RemoteState::New);
+    };
+
+    if local == remote {
+        return Ok(RemoteState::UpToDate);
+    }
+    if git::backend().is_ancestor(&remote, &local)? {
+        return Ok(RemoteState::Ahead);
+    }
+
+    // If the branch pointed to the remote commit at some point, whatever is on the remote was
+    // seen here and then rewritten on purpose. Otherwise, it came from somewhere else.
+    let reflog = git::output(&["log", "--walk-reflogs", "--format=%H", branch, "--"])
+        .context("git log failed")?;
+    if reflog.stdout.lines().any(|commit| commit == remote) {
+        Ok(RemoteState::Rewritten)
+    } else {
+        Ok(RemoteState::Diverged)
+    }
+}
+
+/// Returns the commit `rev` points to, or None if it doesn't exist
+fn rev_parse(rev: &str) -> Result<Option<String>, Error> {
+    let output = git::output(&[
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        &format!("{rev}^{{commit}}"),
+    ])
+    .context("git rev-parse failed")?;
+    Ok(Some(output.stdout.trim().to_string()).filter(|_| output.success))
+}
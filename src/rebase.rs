@@ -0,0 +1,75 @@
+use crate::{git, progress::Progress};
+use eyre::{eyre, Context, Error};
+use serde::{Deserialize, Serialize};
+
+/// Moves the commits of `branch` that are not in `upstream` on top of `onto`
+pub fn rebase_onto(branch: &str, onto: &str, upstream: &str) -> Result<(), Error> {
+    let success = git::status(&["rebase", "--quiet", "--onto", onto, upstream, branch])
+        .context("git rebase failed")?;
+
+    if !success {
+        return Err(eyre!(
+            "rebasing '{branch}' onto '{onto}' stopped because of conflicts"
+        ));
+    }
+    Ok(())
+}
+
+/// Rebases the branches of `stack` (ordered from top to bottom) onto their parents. The bottom
+/// branch is moved from `base_upstream` onto `base`.
+pub fn rebase_stack(stack: &[String], base: &str, base_upstream: &str) -> Result<(), Error> {
+    run_steps(&stack_steps(stack, base, base_upstream)?).map_err(|e| {
+        eyre!("{e}. Resolve them, run `git rebase --continue` and then run `stackbuddy restack`")
+    })
+}
+
+/// Returns the steps that rebase the branches of `stack` (ordered from top to bottom) onto their
+/// parents, from the bottom up. The bottom branch is moved from `base_upstream` onto `base`.
+pub fn stack_steps(
+    stack: &[String],
+    base: &str,
+    base_upstream: &str,
+) -> Result<Vec<RebaseStep>, Error> {
+    // The old tips are needed to know which commits belong to each branch once its parent has
+    // been rewritten
+    let old_tips = stack
+        .iter()
+        .map(|b| crate::rev_parse(b))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let steps = (0..stack.len())
+        .rev()
+        .map(|i| {
+            let (onto, upstream) = match stack.get(i + 1) {
+                Some(parent) => (parent.as_str(), old_tips[i + 1].as_str()),
+                None => (base, base_upstream),
+            };
+            RebaseStep {
+                branch: stack[i].clone(),
+                onto: onto.to_string(),
+                upstream: upstream.to_string(),
+            }
+        })
+        .collect();
+    Ok(steps)
+}
+
+/// A branch to rebase: its commits that are not in `upstream` are moved on top of `onto`.
+/// `upstream` is usually the commit the old parent pointed to, taken before anything moved.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RebaseStep {
+    pub branch: String,
+    pub onto: String,
+    pub upstream: String,
+}
+
+/// Runs `steps` in order. A branch that's the `onto` of a later step must come before it.
+pub fn run_steps(steps: &[RebaseStep]) -> Result<(), Error> {
+    let progress = Progress::new("Rebasing", steps.len());
+    for step in steps {
+        progress.start(format!("Rebasing {} onto {}", step.branch, step.onto));
+        rebase_onto(&step.branch, &step.onto, &step.upstream)?;
+        progress.finish_step();
+    }
+    Ok(())
+}
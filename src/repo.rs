@@ -0,0 +1,40 @@
+//! The repository stackbuddy works on. It's the one in the current directory, unless another one
+//! is picked with `-C <path>`, like with git.
+
+use std::{ffi::OsStr, path::PathBuf, process::Command, sync::OnceLock};
+
+/// Where git, the forge CLIs and any other command are run
+#[derive(Debug, Clone, Default)]
+pub struct Repo {
+    /// None for the current directory
+    pub dir: Option<PathBuf>,
+}
+
+impl Repo {
+    pub fn at(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+        }
+    }
+
+    /// Returns a command that runs `program` in the repository
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        let mut command = Command::new(program);
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+}
+
+static REPO: OnceLock<Repo> = OnceLock::new();
+
+/// Sets the repository returned by [`current`]. Does nothing if it was already used.
+pub fn init(repo: Repo) -> &'static Repo {
+    REPO.get_or_init(|| repo)
+}
+
+/// Returns the repository set with [`init`], or the one in the current directory
+pub fn current() -> &'static Repo {
+    REPO.get_or_init(Repo::default)
+}
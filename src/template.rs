@@ -0,0 +1,143 @@
+//! A small, mustache-like template engine for custom notes.
+//!
+//! - `{{name}}` is replaced by the value of the variable `name`
+//! - `{{#name}}...{{/name}}` renders its contents once for every item of the list `name`, with the
+//!   variables of the item in scope. If `name` is text, the contents are rendered once if it's not
+//!   empty
+//! - `{{^name}}...{{/name}}` renders its contents only if `name` is empty
+
+use eyre::{eyre, Error};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Text(String),
+    List(Vec<Vars>),
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Text(text)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<Option<String>> for Value {
+    fn from(text: Option<String>) -> Self {
+        Value::Text(text.unwrap_or_default())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(flag: bool) -> Self {
+        Value::Text(if flag { "true" } else { "" }.to_string())
+    }
+}
+
+impl From<Vec<Vars>> for Value {
+    fn from(items: Vec<Vars>) -> Self {
+        Value::List(items)
+    }
+}
+
+pub type Vars = HashMap<&'static str, Value>;
+
+pub fn render(template: &str, vars: &Vars) -> Result<String, Error> {
+    let mut out = String::new();
+    render_into(template, &[vars], &mut out)?;
+    Ok(out)
+}
+
+fn render_into(template: &str, scopes: &[&Vars], out: &mut String) -> Result<(), Error> {
+    let mut rest = template;
+    while let Some((before, tag, after)) = next_tag(rest)? {
+        out.push_str(before);
+        rest = after;
+
+        if let Some(name) = tag.strip_prefix(['#', '^']) {
+            let inverted = tag.starts_with('^');
+            let (body, after) = split_section(rest, name)?;
+            rest = after;
+
+            match (lookup(scopes, name)?, inverted) {
+                (Value::List(items), false) => {
+                    for item in items {
+                        let mut scopes = scopes.to_vec();
+                        scopes.push(item);
+                        render_into(body, &scopes, out)?;
+                    }
+                }
+                (Value::List(items), true) => {
+                    if items.is_empty() {
+                        render_into(body, scopes, out)?;
+                    }
+                }
+                (Value::Text(text), inverted) => {
+                    if text.is_empty() == inverted {
+                        render_into(body, scopes, out)?;
+                    }
+                }
+            }
+        } else if let Some(name) = tag.strip_prefix('/') {
+            return Err(eyre!("unexpected {{{{/{name}}}}} in template"));
+        } else {
+            match lookup(scopes, tag)? {
+                Value::Text(text) => out.push_str(text),
+                Value::List(_) => {
+                    return Err(eyre!("'{tag}' is a list, use {{{{#{tag}}}}} to render it"))
+                }
+            }
+        }
+    }
+    out.push_str(rest);
+    Ok(())
+}
+
+/// Finds the next `{{tag}}`, returning the text before it, the trimmed tag and the text after it
+fn next_tag(text: &str) -> Result<Option<(&str, &str, &str)>, Error> {
+    let Some(open) = text.find("{{") else {
+        return Ok(None);
+    };
+    let close = text[open..]
+        .find("}}")
+        .map(|close| open + close)
+        .ok_or_else(|| eyre!("unclosed {{{{ in template"))?;
+    Ok(Some((
+        &text[..open],
+        text[open + 2..close].trim(),
+        &text[close + 2..],
+    )))
+}
+
+/// Splits `text` at the `{{/name}}` that closes a section, returning the body of the section and
+/// the text after it
+fn split_section<'t>(text: &'t str, name: &str) -> Result<(&'t str, &'t str), Error> {
+    let mut depth = 0;
+    let mut rest = text;
+    while let Some((before, tag, after)) = next_tag(rest)? {
+        if tag.strip_prefix(['#', '^']) == Some(name) {
+            depth += 1;
+        } else if tag.strip_prefix('/') == Some(name) {
+            if depth == 0 {
+                let body_len = text.len() - rest.len() + before.len();
+                return Ok((&text[..body_len], after));
+            }
+            depth -= 1;
+        }
+        rest = after;
+    }
+    Err(eyre!("{{{{#{name}}}}} is never closed in template"))
+}
+
+fn lookup<'v>(scopes: &[&'v Vars], name: &str) -> Result<&'v Value, Error> {
+    scopes
+        .iter()
+        .rev()
+        .find_map(|vars| vars.get(name))
+        .ok_or_else(|| eyre!("unknown variable '{name}' in template"))
+}
@@ -0,0 +1,381 @@
+//! `stackbuddy ui`, an interactive view of the stack.
+//!
+//! Lists the branches of the stack with the state of their PRs. The cursor moves with the arrow
+//! keys (or `j`/`k`), and every other action is a single key, listed at the bottom of the screen.
+
+use crate::{
+    current_branch, forge::Forge, forge::PrStatus, main_branch, stack_from, update_note,
+    NoteFormat, NoteOptions,
+};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    ExecutableCommand,
+};
+use eyre::{Context, Error};
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+};
+use std::{
+    collections::HashMap,
+    io::{stdout, Stdout},
+};
+
+type Term = Terminal<CrosstermBackend<Stdout>>;
+
+const HELP: &str =
+    "↑/↓ move  enter checkout  n update notes  o open PR  r restack  g refresh  q quit";
+
+/// Runs the interactive view of the stack that ends in `branch` until the user quits
+pub fn run(forge: &dyn Forge, branch: String, note_format: NoteFormat) -> Result<(), Error> {
+    let mut app = App {
+        forge,
+        note_format,
+        top: branch,
+        main: main_branch()?,
+        current: String::new(),
+        stack: Vec::new(),
+        prs: HashMap::new(),
+        list: ListState::default(),
+        message: String::new(),
+    };
+    app.refresh();
+
+    let mut terminal = enter()?;
+    let result = app.event_loop(&mut terminal);
+    leave()?;
+    result
+}
+
+fn enter() -> Result<Term, Error> {
+    enable_raw_mode().context("failed to enable raw mode")?;
+    stdout()
+        .execute(EnterAlternateScreen)
+        .context("failed to enter the alternate screen")?;
+    Terminal::new(CrosstermBackend::new(stdout())).context("failed to set up the terminal")
+}
+
+fn leave() -> Result<(), Error> {
+    disable_raw_mode().context("failed to disable raw mode")?;
+    stdout()
+        .execute(LeaveAlternateScreen)
+        .context("failed to leave the alternate screen")?;
+    Ok(())
+}
+
+struct App<'f> {
+    forge: &'f dyn Forge,
+    note_format: NoteFormat,
+    /// The branch at the top of the stack
+    top: String,
+    main: String,
+    /// The branch that is checked out
+    current: String,
+    /// From the top of the stack to the bottom
+    stack: Vec<String>,
+    prs: HashMap<String, PrStatus>,
+    list: ListState,
+    /// Shown above the help line, e.g. the outcome of the last action
+    message: String,
+}
+
+impl App<'_> {
+    fn event_loop(&mut self, terminal: &mut Term) -> Result<(), Error> {
+        loop {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .context("failed to draw")?;
+
+            let Event::Key(key) = event::read().context("failed to read input")? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => self.move_cursor(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_cursor(1),
+                KeyCode::Enter => self.checkout(),
+                KeyCode::Char('n') => self.update_notes(),
+                KeyCode::Char('o') => self.open_pr(),
+                KeyCode::Char('r') => {
+                    // Rebasing prints its progress and may stop on conflicts, so it runs with the
+                    // terminal restored
+                    leave()?;
+                    let result = crate::restack(self.top.clone());
+                    *terminal = enter()?;
+                    self.message = match result {
+                        Ok(()) => "Restacked".to_string(),
+                        Err(e) => format!("Restack failed: {e}"),
+                    };
+                    self.refresh();
+                }
+                KeyCode::Char('g') => {
+                    self.refresh();
+                    self.message = "Refreshed".to_string();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads the stack and the PRs again, keeping the cursor on the same branch if possible
+    fn refresh(&mut self) {
+        let selected = self.selected().map(str::to_string);
+        self.current = current_branch().unwrap_or_default();
+        match stack_from(self.top.clone()) {
+            Ok(stack) => self.stack = stack,
+            Err(e) => self.message = format!("Failed to get the stack: {e}"),
+        }
+        self.forge.refresh();
+        match self.forge.open_prs() {
+            Ok(prs) => self.prs = prs,
+            Err(e) => self.message = format!("Failed to get PRs: {e}"),
+        }
+
+        let target = selected.as_ref().unwrap_or(&self.current);
+        let index = self.stack.iter().position(|branch| branch == target);
+        self.list
+            .select(index.or(Some(0)).filter(|_| !self.stack.is_empty()));
+    }
+
+    fn selected(&self) -> Option<&str> {
+        self.list
+            .selected()
+            .and_then(|i| self.stack.get(i))
+            .map(String::as_str)
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        if self.stack.is_empty() {
+            return;
+        }
+        let last = self.stack.len() - 1;
+        let index = self.list.selected().unwrap_or(0);
+        let index = index.saturating_add_signed(delta).min(last);
+        self.list.select(Some(index));
+    }
+
+    fn checkout(&mut self) {
+        let Some(branch) = self.selected().map(str::to_string) else {
+            return;
+        };
+        self.message = match crate::checkout(&branch) {
+            Ok(()) => {
+                self.current = branch.clone();
+                format!("Checked out {branch}")
+            }
+            Err(e) => format!("Checkout failed: {e}"),
+        };
+    }
+
+    fn update_notes(&mut self) {
+        let options = match NoteOptions::new(self.note_format) {
+            Ok(options) => options,
+            Err(e) => {
+                self.message = format!("Failed to update notes: {e}");
+                return;
+            }
+        };
+        let mut failed = Vec::new();
+        for branch in &self.stack {
+            if !self.prs.contains_key(branch) {
+                continue;
+            }
+            if let Err(e) = update_note(self.forge, &self.stack, branch.clone(), &options, false) {
+                failed.push(format!("{branch}: {e}"));
+            }
+        }
+        self.message = if failed.is_empty() {
+            "Updated notes".to_string()
+        } else {
+            format!("Failed to update notes of {}", failed.join(", "))
+        };
+    }
+
+    fn open_pr(&mut self) {
+        let Some(branch) = self.selected().map(str::to_string) else {
+            return;
+        };
+        if let Err(e) = self.forge.open_pr(&branch) {
+            self.message = format!("Failed to open PR: {e}");
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, message_area, help_area] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.size());
+
+        let items: Vec<ListItem> = self.stack.iter().map(|branch| self.item(branch)).collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" stack on {} ", self.main)),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, list_area, &mut self.list);
+
+        frame.render_widget(Paragraph::new(self.message.as_str()), message_area);
+        frame.render_widget(
+            Paragraph::new(HELP).style(Style::default().fg(Color::DarkGray)),
+            help_area,
+        );
+    }
+
+    fn item(&self, branch: &str) -> ListItem<'static> {
+        let marker = if branch == self.current { "* " } else { "  " };
+        let mut spans = vec![
+            Span::raw(marker),
+            Span::styled(
+                branch.to_string(),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+        ];
+        match self.prs.get(branch) {
+            Some(pr) => {
+                spans.push(Span::raw(format!(
+                    "  {} {}",
+                    self.forge.pr_reference(&pr.number),
+                    pr.title
+                )));
+                if pr.draft {
+                    spans.push(Span::styled(
+                        "  draft",
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                }
+                let checks_color = if pr.checks.failed > 0 {
+                    Color::Red
+                } else if pr.checks.pending > 0 {
+                    Color::Yellow
+                } else {
+                    Color::Green
+                };
+                spans.push(Span::styled(
+                    format!("  {}", pr.checks),
+                    Style::default().fg(checks_color),
+                ));
+                if !pr.review.is_empty() {
+                    spans.push(Span::raw(format!(
+                        "  {}",
+                        pr.review.to_lowercase().replace('_', " ")
+                    )));
+                }
+            }
+            None => spans.push(Span::styled(
+                "  no PR",
+                Style::default().fg(Color::DarkGray),
+            )),
+        }
+        ListItem::new(Line::from(spans))
+    }
+}
+
+/// Lets the user pick one of `items` by typing part of it, like fzf: the items that have the
+/// typed characters in order are kept, best matches first. Returns the index of the picked item,
+/// or None if the picker was closed with Esc.
+pub fn pick(title: &str, items: &[String]) -> Result<Option<usize>, Error> {
+    let mut terminal = enter()?;
+    let result = pick_loop(&mut terminal, title, items);
+    leave()?;
+    result
+}
+
+fn pick_loop(terminal: &mut Term, title: &str, items: &[String]) -> Result<Option<usize>, Error> {
+    let mut query = String::new();
+    let mut list = ListState::default();
+    loop {
+        let mut matches: Vec<(i64, usize)> = items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| fuzzy_score(&query, item).map(|score| (score, i)))
+            .collect();
+        // Best score first, and in the original order among equals
+        matches.sort_by_key(|&(score, i)| (std::cmp::Reverse(score), i));
+        let selected = list
+            .selected()
+            .unwrap_or(0)
+            .min(matches.len().saturating_sub(1));
+        list.select(Some(selected).filter(|_| !matches.is_empty()));
+
+        terminal
+            .draw(|frame| {
+                let [query_area, list_area] =
+                    Layout::vertical([Constraint::Length(1), Constraint::Min(1)])
+                        .areas(frame.size());
+                frame.render_widget(Paragraph::new(format!("> {query}")), query_area);
+                let rows: Vec<ListItem> = matches
+                    .iter()
+                    .map(|&(_, i)| ListItem::new(items[i].as_str()))
+                    .collect();
+                let rows = List::new(rows)
+                    .block(Block::default().borders(Borders::ALL).title(format!(
+                        " {title} ({}/{}) ",
+                        matches.len(),
+                        items.len()
+                    )))
+                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+                    .highlight_symbol("> ");
+                frame.render_stateful_widget(rows, list_area, &mut list);
+            })
+            .context("failed to draw")?;
+
+        let Event::Key(key) = event::read().context("failed to read input")? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        // Letters are part of the query, so only the arrows move the cursor
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Enter => return Ok(list.selected().map(|i| matches[i].1)),
+            KeyCode::Up => list.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down => list.select(Some(selected + 1)),
+            KeyCode::Backspace => {
+                query.pop();
+                list.select(Some(0));
+            }
+            KeyCode::Char(c) => {
+                query.push(c);
+                list.select(Some(0));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Scores how well `query` matches `text`, ignoring case, or returns None if the characters of
+/// `query` don't all appear in `text` in order. Consecutive characters and ones at the start of a
+/// word score higher, and gaps lower.
+fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for c in query.chars().flat_map(char::to_lowercase) {
+        let found = next + text[next..].iter().position(|&t| t == c)?;
+        score += 1;
+        match previous {
+            Some(p) if p + 1 == found => score += 5,
+            Some(p) => score -= (found - p - 1).min(5) as i64,
+            None => score -= found.min(5) as i64,
+        }
+        let word_start = found == 0 || matches!(text[found - 1], '/' | '-' | '_' | ' ');
+        if word_start {
+            score += 3;
+        }
+        previous = Some(found);
+        next = found + 1;
+    }
+    Some(score)
+}